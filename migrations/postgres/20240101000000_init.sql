@@ -0,0 +1,64 @@
+-- id 沿用 MongoDB ObjectId 的 24 位十六进制字符串, 便于两种存储之间迁移数据
+
+CREATE TABLE IF NOT EXISTS organizations (
+    id                  CHAR(24)    PRIMARY KEY,
+    name                TEXT        NOT NULL UNIQUE,
+    daily_message_limit BIGINT,
+    burst_message_limit BIGINT,
+    created_at          TIMESTAMP   NOT NULL,
+    updated_at          TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS users (
+    id          CHAR(24)    PRIMARY KEY,
+    org_id      CHAR(24)    NOT NULL,
+    name        TEXT        NOT NULL,
+    role        TEXT        NOT NULL DEFAULT 'user',
+    disabled    BOOLEAN     NOT NULL DEFAULT FALSE,
+    preferences JSONB       NOT NULL DEFAULT '{}',
+    created_at  TIMESTAMP   NOT NULL,
+    updated_at  TIMESTAMP,
+    -- 用户名在组织内唯一, 不同组织可以有同名用户
+    UNIQUE (org_id, name)
+);
+
+CREATE INDEX IF NOT EXISTS users_org_id_created_at ON users (org_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS messages (
+    id          CHAR(24)    PRIMARY KEY,
+    org_id      CHAR(24)    NOT NULL,
+    user_id     CHAR(24)    NOT NULL,
+    type        TEXT        NOT NULL,
+    text        TEXT        NOT NULL,
+    created_at  TIMESTAMP   NOT NULL,
+    created_by  CHAR(24)    NOT NULL,
+    updated_at  TIMESTAMP,
+    updated_by  CHAR(24)
+);
+
+CREATE INDEX IF NOT EXISTS messages_user_id_created_at ON messages (org_id, user_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS messages_user_id_type_created_at ON messages (org_id, user_id, type, created_at DESC);
+CREATE INDEX IF NOT EXISTS messages_org_id_created_at ON messages (org_id, created_at);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id           CHAR(24)   PRIMARY KEY,
+    user_id      CHAR(24)   NOT NULL,
+    name         TEXT       NOT NULL,
+    prefix       TEXT       NOT NULL,
+    key_hash     TEXT       NOT NULL UNIQUE,
+    created_at   TIMESTAMP  NOT NULL,
+    last_used_at TIMESTAMP,
+    revoked_at   TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_user_id_created_at ON api_keys (user_id, created_at DESC);
+
+-- recent 为最近一个窗口内预留的时间点(毫秒时间戳)
+CREATE TABLE IF NOT EXISTS quotas (
+    user_id   CHAR(24)  PRIMARY KEY,
+    day       TEXT      NOT NULL,
+    day_count BIGINT    NOT NULL,
+    recent    JSONB     NOT NULL DEFAULT '[]',
+    pending   BIGINT    NOT NULL,
+    version   BIGINT    NOT NULL
+);
@@ -0,0 +1,3 @@
+-- AI 消息的模型和 token 用量
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS model TEXT;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS usage JSONB;
@@ -0,0 +1,3 @@
+-- 消息保留天数, 0 表示永久保留, 为空时使用上一级的设置
+ALTER TABLE organizations ADD COLUMN IF NOT EXISTS message_retention_days BIGINT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS message_retention_days BIGINT;
@@ -0,0 +1,2 @@
+-- AI 消息的生成耗时(毫秒)
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS latency_ms BIGINT;
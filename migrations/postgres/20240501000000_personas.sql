@@ -0,0 +1,10 @@
+-- 组织内的角色设定, 角色名在组织内唯一
+CREATE TABLE IF NOT EXISTS personas (
+    id          CHAR(24)    PRIMARY KEY,
+    org_id      CHAR(24)    NOT NULL,
+    name        TEXT        NOT NULL,
+    prompt      TEXT        NOT NULL,
+    created_at  TIMESTAMP   NOT NULL,
+    updated_at  TIMESTAMP,
+    UNIQUE (org_id, name)
+);
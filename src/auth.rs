@@ -0,0 +1,180 @@
+use okapi::openapi3::{Object, SecurityRequirement, SecurityScheme, SecuritySchemeData};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use crate::error::Error;
+use crate::model::Context;
+use crate::providers::Providers;
+use crate::store::Store;
+use crate::telemetry;
+
+const SECURITY_SCHEME_NAME: &str = "BearerAuth";
+
+/// 从 Authorization: Bearer <API Key 或 JWT> 认证用户, 得到请求的 Context
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Context {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Error> {
+        let Some(store) = request.rocket().state::<Store>() else {
+            return Outcome::Error((
+                Status::ServiceUnavailable,
+                Error::ServerError("从 State 获取 Store 失败".to_string()),
+            ));
+        };
+        let Some(token) = bearer_token(request) else {
+            return Outcome::Error((Status::Unauthorized, Error::Unauthorized));
+        };
+        match Providers::new(store).auth().authenticate(token).await {
+            Ok(Some(user)) if user.disabled => Outcome::Error((Status::Forbidden, Error::Forbidden)),
+            Ok(Some(user)) => {
+                telemetry::record("enduser.id", user.id.clone());
+                Outcome::Success(Context::new(user))
+            }
+            Ok(None) => Outcome::Error((Status::Unauthorized, Error::Unauthorized)),
+            Err(err) => Outcome::Error((err.get_http_status(), err)),
+        }
+    }
+}
+
+fn bearer_token(request: &Request<'_>) -> Option<String> {
+    request.headers().get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// 抓取 /metrics 的请求: 未开启 METRICS_ENABLED 时返回 404, 设置了 METRICS_TOKEN 时需带上 Authorization: Bearer <METRICS_TOKEN>
+pub struct MetricsScraper;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MetricsScraper {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Error> {
+        let Some(store) = request.rocket().state::<Store>() else {
+            return Outcome::Error((
+                Status::ServiceUnavailable,
+                Error::ServerError("从 State 获取 Store 失败".to_string()),
+            ));
+        };
+        let config = &store.config;
+        if !config.metrics_enabled {
+            return Outcome::Forward(Status::NotFound);
+        }
+        if !config.metrics_token.is_empty() && bearer_token(request).as_deref() != Some(config.metrics_token.as_str()) {
+            return Outcome::Error((Status::Unauthorized, Error::Unauthorized));
+        }
+        Outcome::Success(MetricsScraper)
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for MetricsScraper {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for Context {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        let security_scheme = SecurityScheme {
+            description: Some("Authorization: Bearer <token>, token 为 API Key (sk-sl-...) 或 JWT (sub 为用户名, org 为组织 id, 未指定时为默认组织)".to_string()),
+            data: SecuritySchemeData::Http {
+                scheme: "bearer".to_string(),
+                bearer_format: Some("API Key / JWT".to_string()),
+            },
+            extensions: Object::default(),
+        };
+        let mut security_requirement = SecurityRequirement::new();
+        security_requirement.insert(SECURITY_SCHEME_NAME.to_string(), Vec::new());
+        Ok(RequestHeaderInput::Security(
+            SECURITY_SCHEME_NAME.to_string(),
+            security_scheme,
+            security_requirement,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+    use crate::conf::Config;
+    use crate::model::{Role, DEFAULT_ORGANIZATION_ID};
+    use super::*;
+
+    #[rocket::get("/whoami")]
+    fn whoami(ctx: Context) -> String {
+        ctx.user.name
+    }
+
+    #[rocket::get("/scrape")]
+    fn scrape(_scraper: MetricsScraper) -> &'static str {
+        "ok"
+    }
+
+    async fn client(store: Store) -> Client {
+        let rocket = rocket::build().manage(store).mount("/", rocket::routes![whoami, scrape]);
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn guard_maps_credentials_to_status() {
+        let store = Store::memory_for_test(Config::default()).await;
+        let pvd = Providers::new(&store);
+        let alice = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "alice".to_string(), Role::User).await.unwrap();
+        let (_, key) = pvd.auth().create_api_key(alice.clone(), "default".to_string()).await.unwrap();
+        let client = client(store).await;
+        let get = |authorization: Option<String>| {
+            let mut req = client.get("/whoami");
+            if let Some(authorization) = authorization {
+                req.add_header(Header::new("Authorization", authorization));
+            }
+            req.dispatch()
+        };
+
+        assert_eq!(get(None).await.status(), Status::Unauthorized);
+        assert_eq!(get(Some(key.clone())).await.status(), Status::Unauthorized);
+        assert_eq!(get(Some("Bearer ".to_string())).await.status(), Status::Unauthorized);
+        assert_eq!(get(Some("Bearer sk-sl-unknown".to_string())).await.status(), Status::Unauthorized);
+        let res = get(Some(format!("Bearer {}", key))).await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().await.unwrap(), "alice");
+
+        pvd.user().set_user_disabled(alice, true).await.unwrap();
+        assert_eq!(get(Some(format!("Bearer {}", key))).await.status(), Status::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn metrics_need_to_be_enabled_and_token_matched() {
+        let scraper = client(Store::memory_for_test(Config::default()).await).await;
+        assert_eq!(scraper.get("/scrape").dispatch().await.status(), Status::NotFound);
+
+        let scraper = client(Store::memory_for_test(Config {
+            metrics_enabled: true,
+            metrics_token: "scrape-token".to_string(),
+            ..Default::default()
+        }).await).await;
+        assert_eq!(scraper.get("/scrape").dispatch().await.status(), Status::Unauthorized);
+        let res = scraper.get("/scrape").header(Header::new("Authorization", "Bearer wrong")).dispatch().await;
+        assert_eq!(res.status(), Status::Unauthorized);
+        let res = scraper.get("/scrape").header(Header::new("Authorization", "Bearer scrape-token")).dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let scraper = client(Store::memory_for_test(Config {
+            metrics_enabled: true,
+            ..Default::default()
+        }).await).await;
+        assert_eq!(scraper.get("/scrape").dispatch().await.status(), Status::Ok);
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use crate::conf::Config;
+use crate::error::{Code, Error};
+use crate::model::{Context, CreateApiKeyInput, DEFAULT_ORGANIZATION_ID, RegisterUserInput, TranscriptFormat};
+use crate::providers::Providers;
+use crate::services::{Services, UserService};
+use crate::store::database::Databases;
+use crate::store::postgres::PgDatabases;
+use crate::store::Store;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// 不指定子命令时启动 HTTP 服务
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 注册用户并创建首个 API Key
+    RegisterUser {
+        #[arg(long)]
+        name: String,
+    },
+    /// 为已有用户创建 API Key
+    CreateApiKey {
+        #[arg(long)]
+        user_name: String,
+        /// 用户所在的组织, 默认为默认组织
+        #[arg(long)]
+        org_id: Option<String>,
+        #[arg(long, default_value = "default")]
+        name: String,
+    },
+    /// 导出聊天记录, 未指定用户时导出组织内所有用户
+    ExportMessages {
+        #[arg(long)]
+        user_name: Option<String>,
+        /// 默认为默认组织, 指定 user_name 时为该用户所在的组织
+        #[arg(long)]
+        org_id: Option<String>,
+        /// jsonl/csv/markdown
+        #[arg(long, default_value = "jsonl")]
+        format: String,
+        /// 默认输出到标准输出
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// 从 jsonl/csv/xlsx 文件导入聊天记录, 不存在的用户创建在指定组织
+    ImportMessages {
+        #[arg(long)]
+        file: PathBuf,
+        /// 默认按文件扩展名判断
+        #[arg(long)]
+        format: Option<String>,
+        /// 默认为默认组织
+        #[arg(long)]
+        org_id: Option<String>,
+        /// 只校验并输出报告, 不写入
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// 执行 DATABASE_BACKEND 尚未执行的迁移, 用于关闭 MIGRATE_ON_STARTUP 的部署
+    Migrate,
+}
+
+pub async fn run(command: Command) -> Result<(), Error> {
+    if let Command::Migrate = command {
+        return migrate().await;
+    }
+    let store = Store::new().await?;
+    let pvd = Providers::new(&store);
+    pvd.organization().ensure_default_organization().await?;
+    match command {
+        Command::RegisterUser { name } => {
+            let res = UserService::register_user(pvd, RegisterUserInput { name }).await?;
+            println!("{}", serde_json::to_string_pretty(&res)?);
+        }
+        Command::CreateApiKey { user_name, org_id, name } => {
+            let org_id = org_id.unwrap_or(DEFAULT_ORGANIZATION_ID.to_string());
+            let user = pvd.user().get_org_user_by_name(org_id, user_name).await?
+                .ok_or(Error::Feedback(Code::UserNotFound))?;
+            let svc = Services::new(Context::new(user), pvd);
+            let res = svc.auth().create_api_key(CreateApiKeyInput { name }).await?;
+            println!("{}", serde_json::to_string_pretty(&res)?);
+        }
+        Command::ExportMessages { user_name, org_id, format, output } => {
+            let format = format.parse::<TranscriptFormat>()?;
+            let org_id = org_id.unwrap_or(DEFAULT_ORGANIZATION_ID.to_string());
+            let users = match user_name {
+                Some(user_name) => vec![pvd.user().get_org_user_by_name(org_id, user_name).await?
+                    .ok_or(Error::Feedback(Code::UserNotFound))?],
+                None => pvd.user().list_all_org_users(org_id).await?,
+            };
+            let content = pvd.transcript().export_messages(users, format).await?;
+            match output {
+                Some(output) => std::fs::write(output, content)?,
+                None => print!("{}", content),
+            }
+        }
+        Command::ImportMessages { file, format, org_id, dry_run } => {
+            let format = match format {
+                Some(format) => format,
+                None => file.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase(),
+            };
+            let format = format.parse::<TranscriptFormat>()?;
+            let org_id = org_id.unwrap_or(DEFAULT_ORGANIZATION_ID.to_string());
+            pvd.organization().get_organization(org_id.clone()).await?
+                .ok_or(Error::Feedback(Code::OrganizationNotFound))?;
+            let data = std::fs::read(file)?;
+            let transcript = pvd.transcript();
+            let rows = transcript.parse_transcript(format, data.as_slice())?;
+            let res = transcript.import_messages(org_id, rows, dry_run).await?;
+            println!("{}", serde_json::to_string_pretty(&res)?);
+        }
+        Command::Migrate => unreachable!(),
+    }
+    Ok(())
+}
+
+/// 只连接数据库, 不经过 Store, 避免 MIGRATE_ON_STARTUP 在这里先执行一遍
+async fn migrate() -> Result<(), Error> {
+    let config = Config::new().await?;
+    let applied = match config.database_backend.as_str() {
+        "mongo" => Databases::new(config.clone()).await?.migrate().await?,
+        "postgres" => PgDatabases::new(config.clone()).await?.migrate().await?,
+        backend => return Err(Error::ParamsError(format!("{} 不需要迁移", backend))),
+    };
+    if applied.is_empty() {
+        println!("没有需要执行的迁移");
+    }
+    for name in applied {
+        println!("applied: {}", name);
+    }
+    Ok(())
+}
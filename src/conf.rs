@@ -1,16 +1,53 @@
 use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
 
+use chrono_tz::Tz;
+use log::{debug, info, warn};
 use dotenvy::dotenv;
+use crate::error::Error;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub app_env: String,
     pub debug: bool,
     pub sentry_dsn: String,
+    pub database_backend: String,
     pub database_url: String,
     pub max_size: u32,
+    pub migrate_on_startup: bool,
+    pub database_connect_timeout_secs: u64,
+    pub database_server_selection_timeout_secs: u64,
+    pub mongo_uri: String,
+    pub mongo_db_name: String,
+    pub mongo_app_name: String,
     pub redis_url: String,
+    pub cache_key_prefix: String,
+    pub cache_memory_capacity: usize,
+    pub user_cache_ttl_secs: u64,
     pub openrouter_api_key: String,
+    pub default_timezone: Tz,
+    pub daily_message_limit: i64,
+    pub burst_message_limit: usize,
+    pub burst_window_secs: u64,
+    pub message_retention_days: i64,
+    pub retention_purge_interval_secs: u64,
+    pub import_max_bytes: u64,
+    pub message_poll_interval_secs: u64,
+    pub jwt_secret: String,
+    pub jwt_public_key: String,
+    pub auto_provision_users: bool,
+    pub admin_users: Vec<String>,
+    pub chat_model: String,
+    pub allowed_models: Vec<String>,
+    pub response_cache_ttl_secs: u64,
+    pub health_check_timeout_secs: u64,
+    pub readiness_check_upstream: bool,
+    pub metrics_enabled: bool,
+    pub metrics_token: String,
+    pub otel_traces_exporter: String,
+    pub otel_exporter_otlp_endpoint: String,
+    pub otel_service_name: String,
 }
 
 impl Default for Config {
@@ -19,49 +56,278 @@ impl Default for Config {
             app_env: "dev".to_string(),
             debug: true,
             sentry_dsn: "".to_string(),
+            database_backend: "mongo".to_string(),
             database_url: "".to_string(),
             max_size: 10,
+            migrate_on_startup: true,
+            database_connect_timeout_secs: 10,
+            database_server_selection_timeout_secs: 30,
+            mongo_uri: "mongodb://localhost:27017/".to_string(),
+            mongo_db_name: "".to_string(),
+            mongo_app_name: "simplylab".to_string(),
             redis_url: "".to_string(),
+            cache_key_prefix: "simplylab".to_string(),
+            cache_memory_capacity: 10000,
+            user_cache_ttl_secs: 60,
             openrouter_api_key: "".to_string(),
+            default_timezone: Tz::UTC,
+            daily_message_limit: 20,
+            burst_message_limit: 3,
+            burst_window_secs: 30,
+            message_retention_days: 0,
+            retention_purge_interval_secs: 3600,
+            import_max_bytes: 10 * 1024 * 1024,
+            message_poll_interval_secs: 2,
+            jwt_secret: "".to_string(),
+            jwt_public_key: "".to_string(),
+            auto_provision_users: false,
+            admin_users: vec![],
+            chat_model: "mistralai/mistral-7b-instruct:free".to_string(),
+            allowed_models: vec![],
+            response_cache_ttl_secs: 0,
+            health_check_timeout_secs: 3,
+            readiness_check_upstream: false,
+            metrics_enabled: false,
+            metrics_token: "".to_string(),
+            otel_traces_exporter: "none".to_string(),
+            otel_exporter_otlp_endpoint: "http://localhost:4318".to_string(),
+            otel_service_name: "simplylab".to_string(),
         }
     }
 }
 
 impl Config {
-    pub async fn new() -> Self {
-        println!("Config init");
-        let config = connect().await;
-        println!("{:?}", config);
-        config
+    pub async fn new() -> Result<Self, Error> {
+        info!("Config init");
+        let config = connect().await?;
+        debug!("{:?}", config);
+        Ok(config)
     }
 }
 
-async fn connect() -> Config {
+/// 环境变量的值, 未设置或为空时为 None, 使 `.env.example` 中留空的项等同于未配置
+fn var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.trim().is_empty())
+}
+
+/// 未设置或为空时使用 default, 无法解析时返回 Error::Misconfigured
+fn parse_var<T: FromStr>(key: &str, default: T) -> Result<T, Error> where T::Err: Display {
+    match var(key) {
+        Some(value) => value.trim().parse::<T>()
+            .map_err(|err| Error::Misconfigured(format!("{} 无效: {:?}: {}", key, value, err))),
+        None => Ok(default),
+    }
+}
+
+async fn connect() -> Result<Config, Error> {
     dotenv().ok();
-    let app_env = env::var("APP_ENV").unwrap_or("dev".to_string());
+    let app_env = var("APP_ENV").unwrap_or("dev".to_string());
+
+    let debug = parse_var::<bool>("DEBUG", true)?;
+
+    let sentry_dsn = var("SENTRY_DSN").unwrap_or("".to_string());
+
+    // 数据存储: mongo, postgres (连接 DATABASE_URL, 启动时执行迁移), 或 memory (只保存在进程内, 用于测试和本地调试)
+    let database_backend = var("DATABASE_BACKEND").unwrap_or("mongo".to_string());
+    let database_url = var("DATABASE_URL").unwrap_or("".to_string());
+    let max_size = parse_var::<u32>("DATABASE_POOL_MAX_SIZE", 10)?;
+    // 启动时执行尚未执行的迁移(索引, 表结构), 关闭后需通过 `simplylab migrate` 手动执行
+    let migrate_on_startup = parse_var::<bool>("MIGRATE_ON_STARTUP", true)?;
+    // 建立连接和选择可用节点的超时, 连接串中指定了 connectTimeoutMS 和 serverSelectionTimeoutMS 时以连接串为准
+    let database_connect_timeout_secs = parse_var::<u64>("DATABASE_CONNECT_TIMEOUT_SECS", 10)?;
+    let database_server_selection_timeout_secs = parse_var::<u64>("DATABASE_SERVER_SELECTION_TIMEOUT_SECS", 30)?;
+
+    // MongoDB 连接串, 支持副本集, mongodb+srv, TLS 和 authSource 等参数
+    // 未配置时依次使用 mongodb 开头的 DATABASE_URL, 或由 MONGO_HOST 等拼接, 未配置 MONGO_USERNAME 时不认证
+    let mongo_uri = var("MONGO_URI")
+        .or_else(|| Some(database_url.clone()).filter(|url| url.starts_with("mongodb")))
+        .unwrap_or_else(mongo_uri_from_parts);
+    // 数据库名, 未配置时使用连接串中的数据库, 都没有时为 simplylab
+    let mongo_db_name = var("MONGO_DB_NAME").unwrap_or("".to_string());
+    // 连接串中没有 appName 时使用, 便于在 MongoDB 日志中区分客户端
+    let mongo_app_name = var("MONGO_APP_NAME").unwrap_or("simplylab".to_string());
 
-    let debug = env::var("DEBUG").unwrap_or("true".to_string())
-        .parse::<bool>()
-        .unwrap();
+    // 缓存: 配置了 REDIS_URL 时使用 Redis, 否则使用进程内最多保存 CACHE_MEMORY_CAPACITY 个键的 LRU
+    let redis_url = var("REDIS_URL").unwrap_or("".to_string());
+    // 所有缓存键的前缀, 多个服务共用一个 Redis 时用于区分
+    let cache_key_prefix = var("CACHE_KEY_PREFIX").unwrap_or("simplylab".to_string());
+    let cache_memory_capacity = parse_var::<usize>("CACHE_MEMORY_CAPACITY", 10000)?;
+    // 缓存用户名到用户 id 的秒数, 0 表示不缓存; 用户本身每次从存储读取
+    let user_cache_ttl_secs = parse_var::<u64>("USER_CACHE_TTL_SECS", 60)?;
+    let openrouter_api_key = var("OPENROUTER_API_KEY").unwrap_or("".to_string());
 
-    let sentry_dsn = env::var("SENTRY_DSN").unwrap_or("".to_string());
+    // 用户未设置时区时使用的默认时区, 未配置时沿用容器的 TZ, TZ 不是 IANA 时区名(如 CST-8)时使用 UTC
+    let default_timezone = match var("DEFAULT_TIMEZONE") {
+        Some(_) => parse_var::<Tz>("DEFAULT_TIMEZONE", Tz::UTC)?,
+        None => var("TZ").map(|tz| tz_from_env(tz.as_str())).unwrap_or(Tz::UTC),
+    };
 
-    let database_url = env::var("DATABASE_URL").unwrap_or("".to_string());
-    let max_size = env::var("DATABASE_POOL_MAX_SIZE").unwrap_or("10".to_string())
-        .parse::<u32>()
-        .unwrap();
+    // 每个用户每天最多发送的消息数, 以及 burst_window_secs 秒内最多发送的消息数
+    let daily_message_limit = parse_var::<i64>("DAILY_MESSAGE_LIMIT", 20)?;
+    let burst_message_limit = parse_var::<usize>("BURST_MESSAGE_LIMIT", 3)?;
+    let burst_window_secs = parse_var::<u64>("BURST_WINDOW_SECS", 30)?;
+
+    // 消息默认保留的天数, 0 表示永久保留, 组织和用户可以单独设置
+    let message_retention_days = parse_var::<i64>("MESSAGE_RETENTION_DAYS", 0)?;
+    // 每隔多少秒清理一次过期消息, 0 表示不自动清理
+    let retention_purge_interval_secs = parse_var::<u64>("RETENTION_PURGE_INTERVAL_SECS", 3600)?;
+
+    // 导入聊天记录时上传文件的最大字节数, 默认 10 MiB
+    let import_max_bytes = parse_var::<u64>("IMPORT_MAX_BYTES", 10485760)?;
+
+    // 订阅新消息时, 存储不支持推送 (MongoDB 单机, postgres, memory) 则每隔多少秒轮询一次
+    let message_poll_interval_secs = parse_var::<u64>("MESSAGE_POLL_INTERVAL_SECS", 2)?;
+
+    // JWT 校验: 配置了公钥(PEM)时使用 RS256, 否则配置了密钥时使用 HS256, 都未配置则只接受 API Key
+    let jwt_secret = var("JWT_SECRET").unwrap_or("".to_string());
+    let jwt_public_key = var("JWT_PUBLIC_KEY").unwrap_or("".to_string());
+
+    // 开启后, JWT 中的用户不存在时自动创建, 否则只能通过注册创建用户
+    let auto_provision_users = parse_var::<bool>("AUTO_PROVISION_USERS", false)?;
+
+    // 初始管理员的用户名, 逗号分隔: 启动时不存在的在默认组织创建为 admin (已存在的不修改角色), 且不能通过注册接口注册
+    let admin_users = var("ADMIN_USERS").unwrap_or("".to_string())
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<String>>();
+
+    // 对话默认使用的模型, 以及用户可以在偏好中选择的模型(逗号分隔, 默认只有 CHAT_MODEL)
+    let chat_model = var("CHAT_MODEL").unwrap_or("mistralai/mistral-7b-instruct:free".to_string());
+    let mut allowed_models = var("ALLOWED_MODELS").unwrap_or("".to_string())
+        .split(',')
+        .map(|model| model.trim().to_string())
+        .filter(|model| !model.is_empty())
+        .collect::<Vec<String>>();
+    if !allowed_models.contains(&chat_model) {
+        allowed_models.push(chat_model.clone());
+    }
 
-    let redis_url = env::var("REDIS_URL").unwrap_or("".to_string());
-    let openrouter_api_key = env::var("OPENROUTER_API_KEY").unwrap_or("".to_string());
+    // 相同模型和提示词的回复缓存多少秒, 命中时不再请求上游; 0 表示不缓存
+    let response_cache_ttl_secs = parse_var::<u64>("RESPONSE_CACHE_TTL_SECS", 0)?;
 
-    Config {
+    // 就绪探针中每项依赖检查的超时, 超时视为不可用
+    let health_check_timeout_secs = parse_var::<u64>("HEALTH_CHECK_TIMEOUT_SECS", 3)?;
+    // 就绪探针是否检查 OpenRouter, 上游故障时所有实例都会变为未就绪, 默认关闭
+    let readiness_check_upstream = parse_var::<bool>("READINESS_CHECK_UPSTREAM", false)?;
+    // 是否提供 /metrics, 默认关闭; 开启时建议同时设置 METRICS_TOKEN, 抓取时带上 Authorization: Bearer <METRICS_TOKEN>
+    let metrics_enabled = parse_var::<bool>("METRICS_ENABLED", false)?;
+    let metrics_token = var("METRICS_TOKEN").unwrap_or("".to_string());
+
+    // 链路追踪的导出方式: none, stdout (每行一个 span, 用于本地调试) 或 otlp (OTLP/HTTP JSON 发往 OTEL_EXPORTER_OTLP_ENDPOINT)
+    let otel_traces_exporter = var("OTEL_TRACES_EXPORTER").unwrap_or("none".to_string());
+    // Collector 的地址, 未以 /v1/traces 结尾时自动追加
+    let otel_exporter_otlp_endpoint = var("OTEL_EXPORTER_OTLP_ENDPOINT").unwrap_or("http://localhost:4318".to_string());
+    let otel_service_name = var("OTEL_SERVICE_NAME").unwrap_or("simplylab".to_string());
+
+    Ok(Config {
         app_env,
         debug,
         sentry_dsn,
+        database_backend,
         database_url,
         max_size,
+        migrate_on_startup,
+        database_connect_timeout_secs,
+        database_server_selection_timeout_secs,
+        mongo_uri,
+        mongo_db_name,
+        mongo_app_name,
         redis_url,
+        cache_key_prefix,
+        cache_memory_capacity,
+        user_cache_ttl_secs,
         openrouter_api_key,
+        default_timezone,
+        daily_message_limit,
+        burst_message_limit,
+        burst_window_secs,
+        message_retention_days,
+        retention_purge_interval_secs,
+        import_max_bytes,
+        message_poll_interval_secs,
+        jwt_secret,
+        jwt_public_key,
+        auto_provision_users,
+        admin_users,
+        chat_model,
+        allowed_models,
+        response_cache_ttl_secs,
+        health_check_timeout_secs,
+        readiness_check_upstream,
+        metrics_enabled,
+        metrics_token,
+        otel_traces_exporter,
+        otel_exporter_otlp_endpoint,
+        otel_service_name,
         ..Default::default()
+    })
+}
+
+/// 容器的 TZ 可能是 `:Asia/Shanghai`, `:/usr/share/zoneinfo/Asia/Shanghai` 或 POSIX 格式, 无法识别时使用 UTC
+fn tz_from_env(tz: &str) -> Tz {
+    let name = tz.trim().trim_start_matches(':');
+    let name = name.rsplit_once("zoneinfo/").map(|(_, name)| name).unwrap_or(name);
+    name.parse::<Tz>().unwrap_or_else(|_| {
+        warn!("无法识别 TZ={:?}, 默认时区使用 UTC, 可通过 DEFAULT_TIMEZONE 指定", tz);
+        Tz::UTC
+    })
+}
+
+/// mongodb://{MONGO_USERNAME}:{MONGO_PASSWORD}@{MONGO_HOST}:{MONGO_PORT}/
+fn mongo_uri_from_parts() -> String {
+    let mongo_host = var("MONGO_HOST").unwrap_or("localhost".to_string());
+    let mongo_port = var("MONGO_PORT").unwrap_or("27017".to_string());
+    let mongo_username = var("MONGO_USERNAME").unwrap_or("".to_string());
+    let mongo_password = var("MONGO_PASSWORD").unwrap_or("".to_string());
+    if mongo_username.is_empty() {
+        return format!("mongodb://{mongo_host}:{mongo_port}/");
+    }
+    let mongo_username = percent_encode(mongo_username.as_str());
+    let mongo_password = percent_encode(mongo_password.as_str());
+    format!("mongodb://{mongo_username}:{mongo_password}@{mongo_host}:{mongo_port}/")
+}
+
+/// 用户名和密码中的 @ : / 等字符需要转义
+fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encode_escapes_uri_delimiters() {
+        assert_eq!(percent_encode("admin"), "admin");
+        assert_eq!(percent_encode("p@ss:w/rd%"), "p%40ss%3Aw%2Frd%25");
+    }
+
+    #[test]
+    fn empty_values_use_defaults_and_bad_values_are_errors() {
+        env::set_var("SIMPLYLAB_TEST_EMPTY_LIMIT", " ");
+        env::set_var("SIMPLYLAB_TEST_BAD_LIMIT", "ten");
+        env::set_var("SIMPLYLAB_TEST_GOOD_LIMIT", " 7 ");
+        assert_eq!(parse_var::<u64>("SIMPLYLAB_TEST_EMPTY_LIMIT", 3).unwrap(), 3);
+        assert_eq!(parse_var::<u64>("SIMPLYLAB_TEST_UNSET_LIMIT", 3).unwrap(), 3);
+        assert_eq!(parse_var::<u64>("SIMPLYLAB_TEST_GOOD_LIMIT", 3).unwrap(), 7);
+        assert!(matches!(parse_var::<u64>("SIMPLYLAB_TEST_BAD_LIMIT", 3), Err(Error::Misconfigured(_))));
+        assert_eq!(var("SIMPLYLAB_TEST_EMPTY_LIMIT"), None);
+    }
+
+    #[test]
+    fn container_tz_falls_back_to_utc() {
+        assert_eq!(tz_from_env("Asia/Shanghai"), Tz::Asia__Shanghai);
+        assert_eq!(tz_from_env(":Asia/Shanghai"), Tz::Asia__Shanghai);
+        assert_eq!(tz_from_env(":/usr/share/zoneinfo/Europe/Berlin"), Tz::Europe__Berlin);
+        assert_eq!(tz_from_env(":/etc/localtime"), Tz::UTC);
+        assert_eq!(tz_from_env("CST-8"), Tz::UTC);
+        env::set_var("SIMPLYLAB_TEST_TIMEZONE", "Mars/Olympus");
+        assert!(matches!(parse_var::<Tz>("SIMPLYLAB_TEST_TIMEZONE", Tz::UTC), Err(Error::Misconfigured(_))));
     }
 }
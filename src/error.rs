@@ -12,6 +12,8 @@ use schemars::JsonSchema;
 use schemars::schema::SchemaObject;
 use serde::{Deserialize, Serialize};
 use tokio::task;
+use log::{error, warn};
+use crate::logger;
 
 #[derive(Error, Debug, serde::Serialize, schemars::JsonSchema, Copy, Clone)]
 pub enum Code {
@@ -21,6 +23,18 @@ pub enum Code {
     RecordNotFound,
     #[error("未找到用户")]
     UserNotFound,
+    #[error("用户名已存在")]
+    UserAlreadyExists,
+    #[error("未找到组织")]
+    OrganizationNotFound,
+    #[error("组织名已存在")]
+    OrganizationAlreadyExists,
+    #[error("未找到消息")]
+    MessageNotFound,
+    #[error("未找到角色")]
+    PersonaNotFound,
+    #[error("角色名已存在")]
+    PersonaAlreadyExists,
 }
 
 #[derive(Error, Debug)]
@@ -35,6 +49,8 @@ pub enum Error {
     ParamsError(String),
     #[error("服务报错: {0}")]
     ServerError(String),
+    #[error("配置错误: {0}")]
+    Misconfigured(String),
     #[error("数据库连接报错: 无法获取数据库连接: {0}")]
     DatabaseConnectionError(String),
     #[error("数据库Ping报错: {0}")]
@@ -80,7 +96,7 @@ pub enum Error {
 }
 
 impl Error {
-    fn get_http_status(&self) -> Status {
+    pub fn get_http_status(&self) -> Status {
         match self {
             Error::Unauthorized => Status::Unauthorized,
             Error::Forbidden => Status::Forbidden,
@@ -99,28 +115,38 @@ impl Error {
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 struct ErrorMessage {
     message: String,
+    /// 与响应头 X-Request-Id 相同, 便于反馈问题时查找日志和 Sentry 事件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 impl<'r> Responder<'r, 'static> for Error {
     fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
         let errormsg = self.to_string();
-        println!(
-            "Error: Request: {} Response: {:#}",
-            req, errormsg
-        );
+        let status = self.get_http_status();
+        let request_id = logger::request_id(req);
+        if status.class().is_server_error() {
+            error!("Request: {} Response: {} {:#}", req, status.code, errormsg);
+        } else {
+            warn!("Request: {} Response: {} {:#}", req, status.code, errormsg);
+        }
         // 发往sentry的如果有Backtrace则会包含Backtrace，返回给用户的则一定不会有Backtrace
-        sentry::capture_error(&self);
+        sentry::with_scope(
+            |scope| scope.set_tag("request_id", request_id.as_str()),
+            || sentry::capture_error(&self),
+        );
         let resp = ErrorMessage {
             message: errormsg
                 .split(", Backtrace")
                 .next()
                 .unwrap_or_default()
                 .parse()
-                .unwrap()
+                .unwrap(),
+            request_id: Some(request_id),
         };
         let err_response = serde_json::to_string(&resp).unwrap();
         Response::build()
-            .status(self.get_http_status())
+            .status(status)
             .header(ContentType::JSON)
             .sized_body(err_response.len(), std::io::Cursor::new(err_response))
             .ok()
@@ -0,0 +1,201 @@
+use std::env;
+use std::future::Future;
+use std::io::Write;
+use std::time::Instant;
+use chrono::{SecondsFormat, Utc};
+use log::{info, LevelFilter, Log, Metadata, Record};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Response, Route};
+use serde_json::{json, Map, Value};
+use crate::telemetry;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// 客户端传入的请求 ID 最长的长度, 超出或包含不可见字符时重新生成
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// 当前请求的 ID, 在路由处理期间可用, 日志, Context 和上游请求从这里获取
+    static REQUEST_ID: String;
+}
+
+/// 当前任务所处理请求的 ID, 不在请求中(如后台任务, 命令行)时为 None
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// 在 request_id 的范围内执行 future, 其中的日志都会带上 request_id
+pub async fn scope<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// 每行输出一个 JSON 对象到 stdout: ts, level, target, message, 请求中时还有 request_id
+pub struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_record(record, current_request_id().as_deref());
+        let _ = writeln!(std::io::stdout().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+fn format_record(record: &Record, request_id: Option<&str>) -> String {
+    let mut line = Map::new();
+    line.insert("ts".to_string(), json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+    line.insert("level".to_string(), json!(record.level().as_str()));
+    line.insert("target".to_string(), json!(record.target()));
+    line.insert("message".to_string(), json!(record.args().to_string()));
+    if let Some(request_id) = request_id {
+        line.insert("request_id".to_string(), json!(request_id));
+    }
+    Value::Object(line).to_string()
+}
+
+/// 安装 JSON 日志, 级别由 LOG_LEVEL 指定(默认 info)
+/// 需在 Rocket 启动前调用, Rocket 发现已有日志实现时不再安装自己的, 其日志也会输出为 JSON
+pub fn init() {
+    // 日志级别: off, error, warn, info, debug, trace
+    let level = env::var("LOG_LEVEL").unwrap_or("info".to_string()).parse::<LevelFilter>().unwrap_or(LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(JsonLogger { level })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// 客户端传入的 X-Request-Id 合法时沿用, 否则生成新的
+fn parse_request_id(value: Option<&str>) -> Option<String> {
+    value.map(|value| value.trim())
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LEN)
+        .filter(|value| value.chars().all(|c| c.is_ascii_graphic()))
+        .map(|value| value.to_string())
+}
+
+/// 请求的 ID 和开始时间, 保存在 request local cache 中
+struct RequestInfo {
+    id: String,
+    started_at: Instant,
+}
+
+fn request_info<'r>(req: &'r Request<'_>) -> &'r RequestInfo {
+    req.local_cache(|| RequestInfo {
+        id: parse_request_id(req.headers().get_one(REQUEST_ID_HEADER))
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        started_at: Instant::now(),
+    })
+}
+
+/// 请求的 ID, 由 RequestId fairing 在请求开始时确定
+pub fn request_id(req: &Request<'_>) -> String {
+    request_info(req).id.clone()
+}
+
+/// 生成或沿用 X-Request-Id, 写入响应头, 并记录访问日志
+pub struct RequestId;
+
+#[rocket::async_trait]
+impl Fairing for RequestId {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Id",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        request_info(req);
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let info = request_info(req);
+        res.set_header(Header::new(REQUEST_ID_HEADER, info.id.clone()));
+        let elapsed_ms = info.started_at.elapsed().as_millis();
+        REQUEST_ID.sync_scope(info.id.clone(), || {
+            info!("{} {} {} {}ms", req.method(), req.uri().path(), res.status().code, elapsed_ms);
+        });
+    }
+}
+
+/// 在请求 ID 和请求的根 span 的范围内执行路由处理,
+/// 使请求守卫, 服务, Provider 和错误响应中的日志都带上 request_id, 其中开始的 span 都属于同一个 trace
+#[derive(Clone)]
+struct RequestScoped(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for RequestScoped {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let request_id = request_id(req);
+        let span = telemetry::server_span(req);
+        span.set_attribute("http.request_id", request_id.clone());
+        let outcome = scope(request_id, span.scope(self.0.handle(req, data))).await;
+        let status = match &outcome {
+            Outcome::Success(response) => response.status(),
+            Outcome::Error(status) => *status,
+            Outcome::Forward((_, status)) => *status,
+        };
+        span.set_attribute("http.response.status_code", status.code);
+        if status.class().is_server_error() {
+            span.set_error(status);
+        }
+        span.end();
+        outcome
+    }
+}
+
+/// 给所有路由的处理包上请求 ID 和 span 的范围
+pub fn request_scoped(routes: Vec<Route>) -> Vec<Route> {
+    routes.into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(RequestScoped(route.handler));
+            route
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use super::*;
+
+    #[test]
+    fn formats_record_as_json_line() {
+        let args = format_args!("hello \"world\"");
+        let record = Record::builder().level(Level::Warn).target("simplylab::test").args(args).build();
+        let line: Value = serde_json::from_str(format_record(&record, Some("abc")).as_str()).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["target"], "simplylab::test");
+        assert_eq!(line["message"], "hello \"world\"");
+        assert_eq!(line["request_id"], "abc");
+        let line: Value = serde_json::from_str(format_record(&record, None).as_str()).unwrap();
+        assert!(line.get("request_id").is_none());
+    }
+
+    #[test]
+    fn accepts_only_printable_request_ids() {
+        assert_eq!(parse_request_id(Some(" req-1 ")), Some("req-1".to_string()));
+        assert_eq!(parse_request_id(Some("")), None);
+        assert_eq!(parse_request_id(Some("a b")), None);
+        assert_eq!(parse_request_id(Some("a\nb")), None);
+        assert_eq!(parse_request_id(Some("x".repeat(129).as_str())), None);
+        assert_eq!(parse_request_id(None), None);
+    }
+
+    #[tokio::test]
+    async fn request_id_is_visible_within_scope() {
+        assert_eq!(current_request_id(), None);
+        let id = scope("req-1".to_string(), async { current_request_id() }).await;
+        assert_eq!(id, Some("req-1".to_string()));
+    }
+}
@@ -8,6 +8,7 @@ extern crate dotenv;
 extern crate rocket;
 
 use std::env;
+use clap::Parser;
 use dotenv::dotenv;
 use rocket_okapi::openapi_get_routes;
 use rocket_okapi::rapidoc::GeneralConfig;
@@ -17,6 +18,8 @@ use rocket_okapi::settings::UrlObject;
 use rocket_okapi::swagger_ui::make_swagger_ui;
 use rocket_okapi::swagger_ui::SwaggerUIConfig;
 
+use crate::cli::Cli;
+use crate::providers::Providers;
 use crate::services::Services;
 use crate::store::Store;
 
@@ -27,6 +30,11 @@ mod route;
 mod providers;
 mod store;
 mod error;
+mod auth;
+mod cli;
+mod metrics;
+mod logger;
+mod telemetry;
 
 
 pub fn get_docs() -> SwaggerUIConfig {
@@ -51,14 +59,88 @@ pub fn get_rapidoc() -> RapiDocConfig {
 async fn main() -> Result<(), rocket::Error> {
     env::set_var("RUST_BACKTRACE", "1");
     dotenv().ok();
+    logger::init();
+    let cli = Cli::parse();
+    if let Some(command) = cli.command {
+        if let Err(err) = cli::run(command).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     let routes = openapi_get_routes![
         route::index,
         route::favicon,
+        route::healthz,
+        route::readyz,
+        route::get_metrics,
         route::get_ai_chat_response,
         route::get_user_chat_history,
+        route::get_user_chat_history_v2,
+        route::subscribe_user_chat_messages,
         route::get_chat_status_today,
+        route::register_user,
+        route::set_user_timezone,
+        route::get_user_preferences,
+        route::update_user_preferences,
+        route::list_personas,
+        route::create_api_key,
+        route::list_api_keys,
+        route::revoke_api_key,
+        route::admin_list_users,
+        route::admin_get_user_stats,
+        route::admin_rename_user,
+        route::admin_set_user_disabled,
+        route::admin_set_user_role,
+        route::admin_delete_user_chat_history,
+        route::admin_delete_user,
+        route::admin_create_user,
+        route::admin_create_persona,
+        route::admin_update_persona,
+        route::admin_delete_persona,
+        route::admin_create_organization,
+        route::admin_list_organizations,
+        route::admin_update_organization_quota,
+        route::admin_get_organization_usage,
+        route::admin_get_model_usage,
+        route::admin_get_top_users,
+        route::admin_download_analytics,
+        route::admin_set_organization_retention,
+        route::admin_set_user_retention,
+        route::admin_get_retention_report,
+        route::admin_export_messages,
+        route::admin_import_messages,
     ];
-    let store = Store::new().await;
+    let store = match Store::new().await {
+        Ok(store) => store,
+        Err(err) => {
+            error!("init store: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = telemetry::init(&store.config) {
+        error!("init telemetry: {}", err);
+        std::process::exit(1);
+    }
+    Providers::new(&store).organization().ensure_default_organization().await
+        .expect("ensure default organization");
+    for user in Providers::new(&store).user().seed_admin_users().await.expect("seed admin users") {
+        info!("created admin user {}", user.name);
+    }
+    let purge_interval = store.config.retention_purge_interval_secs;
+    if purge_interval > 0 {
+        let pvd = Providers::new(&store);
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(std::time::Duration::from_secs(purge_interval));
+            loop {
+                interval.tick().await;
+                match pvd.retention().purge_expired_messages().await {
+                    Ok(deleted) => info!("purge expired messages: {}", deleted),
+                    Err(err) => error!("purge expired messages: {:?}", err),
+                }
+            }
+        });
+    }
     let sentry_dsn = store.config.sentry_dsn.clone();
     let app_env = store.config.app_env.clone();
     let _guard = sentry::init((
@@ -72,7 +154,10 @@ async fn main() -> Result<(), rocket::Error> {
     ));
     let _rocket = rocket::build()
         .manage(store)
-        .mount("/", routes)
+        .attach(logger::RequestId)
+        .attach(metrics::RequestMetrics)
+        .mount("/", logger::request_scoped(routes))
+        .register("/", catchers![route::unauthorized, route::forbidden])
         .mount("/docs", make_swagger_ui(&get_docs()))
         .mount("/rapidoc", make_rapidoc(&get_rapidoc()))
         .launch()
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandSucceededEvent};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use crate::telemetry::{self, AttributeValue, SpanKind};
+
+/// 指标的名称, 说明和类型, 同名指标的不同标签组合各自计数
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    kind: MetricKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MetricKind {
+    Counter,
+    Histogram,
+}
+
+pub static HTTP_REQUESTS_TOTAL: Metric = Metric {
+    name: "simplylab_http_requests_total",
+    help: "HTTP requests by method, route and status",
+    kind: MetricKind::Counter,
+};
+pub static HTTP_REQUEST_DURATION: Metric = Metric {
+    name: "simplylab_http_request_duration_seconds",
+    help: "HTTP request latency by method, route and status",
+    kind: MetricKind::Histogram,
+};
+pub static UPSTREAM_REQUEST_DURATION: Metric = Metric {
+    name: "simplylab_upstream_request_duration_seconds",
+    help: "LLM upstream call latency by model",
+    kind: MetricKind::Histogram,
+};
+pub static UPSTREAM_ERRORS_TOTAL: Metric = Metric {
+    name: "simplylab_upstream_errors_total",
+    help: "Failed LLM upstream calls by model",
+    kind: MetricKind::Counter,
+};
+pub static TOKENS_TOTAL: Metric = Metric {
+    name: "simplylab_tokens_total",
+    help: "Tokens reported by the LLM upstream by model and type (prompt/completion)",
+    kind: MetricKind::Counter,
+};
+pub static RATE_LIMIT_REJECTIONS_TOTAL: Metric = Metric {
+    name: "simplylab_rate_limit_rejections_total",
+    help: "Chat messages rejected by the daily or burst limit",
+    kind: MetricKind::Counter,
+};
+pub static MONGO_COMMAND_DURATION: Metric = Metric {
+    name: "simplylab_mongo_command_duration_seconds",
+    help: "MongoDB command latency by command and outcome",
+    kind: MetricKind::Histogram,
+};
+
+/// 直方图的桶(秒), 覆盖从数据库查询到模型生成的耗时
+const DURATION_BUCKETS: [f64; 14] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+type Labels = Vec<(&'static str, String)>;
+
+enum Series {
+    Counter(f64),
+    /// 各桶为累计计数
+    Histogram { buckets: [u64; DURATION_BUCKETS.len()], sum: f64, count: u64 },
+}
+
+#[derive(Default)]
+struct Registry {
+    families: BTreeMap<&'static str, (&'static Metric, BTreeMap<Labels, Series>)>,
+}
+
+impl Registry {
+    fn series(&mut self, metric: &'static Metric, labels: &[(&'static str, &str)]) -> &mut Series {
+        let labels = labels.iter().map(|(name, value)| (*name, value.to_string())).collect::<Labels>();
+        let (_, series) = self.families.entry(metric.name).or_insert_with(|| (metric, BTreeMap::new()));
+        series.entry(labels).or_insert_with(|| match metric.kind {
+            MetricKind::Counter => Series::Counter(0.0),
+            MetricKind::Histogram => Series::Histogram { buckets: [0; DURATION_BUCKETS.len()], sum: 0.0, count: 0 },
+        })
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+/// 计数器加 value
+pub fn inc(metric: &'static Metric, labels: &[(&'static str, &str)], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    if let Series::Counter(total) = registry.series(metric, labels) {
+        *total += value;
+    }
+}
+
+/// 直方图记录一次耗时
+pub fn observe(metric: &'static Metric, labels: &[(&'static str, &str)], duration: Duration) {
+    let seconds = duration.as_secs_f64();
+    let mut registry = REGISTRY.lock().unwrap();
+    if let Series::Histogram { buckets, sum, count } = registry.series(metric, labels) {
+        for (bucket, le) in buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        *sum += seconds;
+        *count += 1;
+    }
+}
+
+/// Prometheus 文本格式 (0.0.4)
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+    for (metric, series) in registry.families.values() {
+        let kind = match metric.kind {
+            MetricKind::Counter => "counter",
+            MetricKind::Histogram => "histogram",
+        };
+        let _ = writeln!(out, "# HELP {} {}", metric.name, metric.help);
+        let _ = writeln!(out, "# TYPE {} {}", metric.name, kind);
+        for (labels, series) in series {
+            match series {
+                Series::Counter(total) => {
+                    let _ = writeln!(out, "{}{} {}", metric.name, format_labels(labels, None), total);
+                }
+                Series::Histogram { buckets, sum, count } => {
+                    for (bucket, le) in buckets.iter().zip(DURATION_BUCKETS) {
+                        let _ = writeln!(out, "{}_bucket{} {}", metric.name, format_labels(labels, Some(le.to_string())), bucket);
+                    }
+                    let _ = writeln!(out, "{}_bucket{} {}", metric.name, format_labels(labels, Some("+Inf".to_string())), count);
+                    let _ = writeln!(out, "{}_sum{} {}", metric.name, format_labels(labels, None), sum);
+                    let _ = writeln!(out, "{}_count{} {}", metric.name, format_labels(labels, None), count);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn format_labels(labels: &Labels, le: Option<String>) -> String {
+    let mut pairs = labels.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect::<Vec<String>>();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// 按路由和状态码记录请求数和耗时, 路由取匹配到的路由模板, 避免路径参数造成过多的标签组合
+pub struct RequestMetrics;
+
+/// 请求开始的时间, 保存在 request local cache 中
+struct RequestStart(Instant);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        req.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let started_at = req.local_cache(|| RequestStart(Instant::now())).0;
+        let route = req.route().map(|route| route.uri.path()).unwrap_or("unmatched");
+        let method = req.method().as_str();
+        let status = res.status().code.to_string();
+        let labels = [("method", method), ("route", route), ("status", status.as_str())];
+        inc(&HTTP_REQUESTS_TOTAL, &labels, 1.0);
+        observe(&HTTP_REQUEST_DURATION, &labels, started_at.elapsed());
+    }
+}
+
+/// 通过驱动的命令事件记录 MongoDB 各命令的耗时, 在请求中执行的命令同时记录为当前 span 的子 span
+pub struct MongoCommandMetrics;
+
+impl CommandEventHandler for MongoCommandMetrics {
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        observe(&MONGO_COMMAND_DURATION, &[("command", event.command_name.as_str()), ("outcome", "ok")], event.duration);
+        telemetry::record_finished(format!("mongodb {}", event.command_name), SpanKind::Client, event.duration,
+                                   mongo_span_attributes(event.command_name), None);
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        observe(&MONGO_COMMAND_DURATION, &[("command", event.command_name.as_str()), ("outcome", "error")], event.duration);
+        telemetry::record_finished(format!("mongodb {}", event.command_name), SpanKind::Client, event.duration,
+                                   mongo_span_attributes(event.command_name), Some(event.failure.to_string()));
+    }
+}
+
+fn mongo_span_attributes(command_name: String) -> Vec<(&'static str, AttributeValue)> {
+    vec![("db.system", "mongodb".into()), ("db.operation", command_name.into())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TEST_COUNTER: Metric = Metric {
+        name: "test_counter_total",
+        help: "test counter",
+        kind: MetricKind::Counter,
+    };
+    static TEST_HISTOGRAM: Metric = Metric {
+        name: "test_duration_seconds",
+        help: "test histogram",
+        kind: MetricKind::Histogram,
+    };
+
+    #[test]
+    fn renders_prometheus_text_format() {
+        inc(&TEST_COUNTER, &[("model", "a\"b")], 2.0);
+        inc(&TEST_COUNTER, &[("model", "a\"b")], 1.0);
+        observe(&TEST_HISTOGRAM, &[], Duration::from_millis(30));
+        observe(&TEST_HISTOGRAM, &[], Duration::from_secs(200));
+        let out = render();
+        assert!(out.contains("# TYPE test_counter_total counter\ntest_counter_total{model=\"a\\\"b\"} 3\n"));
+        assert!(out.contains("test_duration_seconds_bucket{le=\"0.025\"} 0\n"));
+        assert!(out.contains("test_duration_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(out.contains("test_duration_seconds_bucket{le=\"120\"} 1\n"));
+        assert!(out.contains("test_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("test_duration_seconds_count 2\n"));
+    }
+}
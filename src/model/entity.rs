@@ -3,6 +3,7 @@ use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
+use chrono_tz::Tz;
 use mongodb::bson;
 use mongodb::bson::oid::ObjectId;
 use schemars::JsonSchema;
@@ -10,7 +11,10 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use uuid::Uuid;
 use crate::error::Error;
+use crate::logger::current_request_id;
 use bson::serde_helpers::hex_string_as_object_id;
+use lazy_static::lazy_static;
+use regex::Regex;
 
 
 pub type UserId = String;
@@ -19,17 +23,261 @@ pub type CreatedAt = NaiveDateTime;
 pub type CreatedBy = UserId;
 pub type UpdatedAt = Option<NaiveDateTime>;
 pub type UpdatedBy = Option<UserId>;
+pub type OrganizationId = String;
 
+/// 默认组织, 未指定组织的用户以及多租户之前的数据都属于默认组织
+pub const DEFAULT_ORGANIZATION_ID: &str = "000000000000000000000000";
+/// 消息保留天数的上限(约 100 年)
+pub const MAX_RETENTION_DAYS: i64 = 36500;
+
+/// 组织(租户), 拥有用户及其消息, 角色和额度, 组织之间的数据互相隔离
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Organization {
+    pub id: OrganizationId,
+    pub name: String,
+    /// 每个用户每天最多发送的消息数, 未设置时使用服务端默认值
+    pub daily_message_limit: Option<i64>,
+    /// 窗口内最多发送的消息数, 未设置时使用服务端默认值
+    pub burst_message_limit: Option<i64>,
+    /// 消息保留的天数, 0 表示永久保留, 未设置时使用服务端默认值
+    pub message_retention_days: Option<i64>,
+    pub created_at: CreatedAt,
+    pub updated_at: UpdatedAt,
+}
+
+/// 组织内的 AI 角色设定, 由管理员维护, 用户在偏好中按名字选择
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Persona {
+    pub id: String,
+    pub org_id: OrganizationId,
+    /// 组织内唯一
+    pub name: String,
+    /// 对话时作为 system prompt 的角色设定
+    pub prompt: String,
+    pub created_at: CreatedAt,
+    pub updated_at: UpdatedAt,
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct User {
     pub id: UserId,
+    pub org_id: OrganizationId,
     pub name: UserName,
+    pub preferences: UserPreferences,
+    pub role: Role,
+    pub disabled: bool,
+    /// 消息保留的天数, 0 表示永久保留, 未设置时使用组织的设置
+    pub message_retention_days: Option<i64>,
     pub created_at: CreatedAt,
     pub updated_at: UpdatedAt,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+/// 用户偏好, 未设置的项使用服务端默认值
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct UserPreferences {
+    /// 对话中对用户的称呼
+    pub display_name: Option<String>,
+    /// BCP 47 语言标签, 例如 zh-CN
+    pub locale: Option<String>,
+    /// IANA 时区名, 例如 Asia/Shanghai, 决定每日额度的重置时间
+    pub timezone: Option<String>,
+    /// 对话使用的模型
+    pub default_model: Option<String>,
+    /// 对话时 AI 扮演的角色, 为组织内的角色名
+    pub default_persona: Option<String>,
+    /// AI 回复使用的语言, 未设置时使用 locale
+    pub response_language: Option<String>,
+    /// 开启后不保存对话记录
+    #[serde(default)]
+    pub disable_history: bool,
+    /// 上次修改时区的时间(UTC), 两次修改之间至少间隔 TIMEZONE_CHANGE_INTERVAL_HOURS 小时
+    #[serde(default)]
+    pub timezone_changed_at: Option<NaiveDateTime>,
+}
+
+/// 两次修改时区的最小间隔, 避免来回切换时区使每日额度按新的日期重新计算
+pub const TIMEZONE_CHANGE_INTERVAL_HOURS: i64 = 24;
+
+impl UserPreferences {
+    /// 修改时区, 与当前时区相同时不算修改; 距上次修改不足 TIMEZONE_CHANGE_INTERVAL_HOURS 小时时返回错误
+    pub fn set_timezone(&mut self, timezone: Option<String>, now: NaiveDateTime) -> Result<(), Error> {
+        if timezone == self.timezone {
+            return Ok(());
+        }
+        if let Some(changed_at) = self.timezone_changed_at {
+            let next_change_at = changed_at + chrono::Duration::hours(TIMEZONE_CHANGE_INTERVAL_HOURS);
+            if now < next_change_at {
+                return Err(Error::ParamsError(format!("时区每 {} 小时只能修改一次, 请在 {} (UTC) 之后再修改", TIMEZONE_CHANGE_INTERVAL_HOURS, next_change_at.format("%Y-%m-%d %H:%M:%S"))));
+            }
+        }
+        self.timezone = timezone;
+        self.timezone_changed_at = Some(now);
+        Ok(())
+    }
+
+    /// 根据偏好和 default_persona 对应角色的设定生成对话的 system prompt, 没有需要告诉模型的内容时返回 None
+    pub fn system_prompt(&self, persona: Option<&Persona>) -> Option<String> {
+        let mut lines = vec![];
+        if let Some(persona) = persona {
+            lines.push(persona.prompt.clone());
+        }
+        if let Some(display_name) = &self.display_name {
+            lines.push(format!("The user's name is {}.", display_name));
+        }
+        if let Some(language) = self.response_language.as_ref().or(self.locale.as_ref()) {
+            lines.push(format!("Always respond in {}.", language));
+        }
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+}
+
+/// 对用户偏好的部分修改, 外层 None 表示不修改, Some(None) 表示清除
+/// 在重新读取的用户上合并, 避免用请求开始时的旧偏好覆盖并发的修改
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserPreferencesPatch {
+    pub display_name: Option<Option<String>>,
+    pub locale: Option<Option<String>>,
+    pub timezone: Option<Option<String>>,
+    pub default_model: Option<Option<String>>,
+    pub default_persona: Option<Option<String>>,
+    pub response_language: Option<Option<String>>,
+    pub disable_history: Option<bool>,
+}
+
+impl UserPreferencesPatch {
+    pub fn apply(self, preferences: &mut UserPreferences, now: NaiveDateTime) -> Result<(), Error> {
+        if let Some(display_name) = self.display_name {
+            preferences.display_name = display_name;
+        }
+        if let Some(locale) = self.locale {
+            preferences.locale = locale;
+        }
+        if let Some(timezone) = self.timezone {
+            preferences.set_timezone(timezone, now)?;
+        }
+        if let Some(default_model) = self.default_model {
+            preferences.default_model = default_model;
+        }
+        if let Some(default_persona) = self.default_persona {
+            preferences.default_persona = default_persona;
+        }
+        if let Some(response_language) = self.response_language {
+            preferences.response_language = response_language;
+        }
+        if let Some(disable_history) = self.disable_history {
+            preferences.disable_history = disable_history;
+        }
+        Ok(())
+    }
+}
+
+/// 用户名的最大字符数
+pub const MAX_USER_NAME_LEN: usize = 32;
+
+lazy_static! {
+    static ref USER_NAME_RE: Regex = Regex::new(&format!(r"^[\p{{L}}\p{{N}}_.-]{{2,{}}}$", MAX_USER_NAME_LEN)).unwrap();
+    static ref USER_NAME_CHAR_RE: Regex = Regex::new(r"^[\p{L}\p{N}_.-]$").unwrap();
+}
+
+impl User {
+    /// 校验用户名: 2 到 32 个字符, 只允许文字, 数字, 下划线, 点和中划线
+    pub fn validate_name(name: &str) -> Result<UserName, Error> {
+        let name = name.trim();
+        if USER_NAME_RE.is_match(name) {
+            Ok(name.to_string())
+        } else {
+            Err(Error::ParamsError("用户名需为 2 到 32 个字符, 只能包含文字, 数字, 下划线, 点和中划线".to_string()))
+        }
+    }
+
+    /// 去掉 name 中用户名不允许的字符, 截断到加上 suffix 后不超过 MAX_USER_NAME_LEN 个字符
+    pub fn name_with_suffix(name: &str, suffix: &str) -> UserName {
+        let mut buf = [0u8; 4];
+        let base = name.chars()
+            .filter(|c| USER_NAME_CHAR_RE.is_match(c.encode_utf8(&mut buf)))
+            .take(MAX_USER_NAME_LEN.saturating_sub(suffix.chars().count()))
+            .collect::<String>();
+        format!("{}{}", base, suffix)
+    }
+
+    /// 用户所在时区, 未设置或无法识别时使用默认时区
+    pub fn tz(&self, default: Tz) -> Tz {
+        self.preferences.timezone
+            .as_deref()
+            .and_then(|tz| tz.parse::<Tz>().ok())
+            .unwrap_or(default)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Role {
+    #[serde(rename="user")]
+    User,
+    #[serde(rename="support")]
+    Support,
+    #[serde(rename="admin")]
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Self::User),
+            "support" => Ok(Self::Support),
+            "admin" => Ok(Self::Admin),
+            _ => Err(Error::ParamsError("user/support/admin pls".to_string()))
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User => f.write_str("user"),
+            Self::Support => f.write_str("support"),
+            Self::Admin => f.write_str("admin"),
+        }
+    }
+}
+
+/// 需要校验的操作, 操作自己的数据不需要额外权限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// 查看其他用户的聊天记录和今日状态
+    ReadAnyUserChat,
+    /// 删除其他用户的聊天记录
+    DeleteAnyUserChat,
+    /// 查看用户列表和用户统计
+    ViewUsers,
+    /// 改名, 禁用和启用用户
+    ManageUsers,
+    /// 删除用户
+    DeleteUsers,
+    /// 修改用户角色
+    ManageRoles,
+    /// 创建, 修改和删除组织内的角色设定
+    ManagePersonas,
+    /// 创建和管理组织, 查看其他组织的数据, 只授予默认组织的 admin
+    ManageOrganizations,
+}
+
+impl Role {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        match self {
+            Self::Admin => true,
+            Self::Support => matches!(permission, Permission::ReadAnyUserChat | Permission::ViewUsers),
+            Self::User => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 pub enum MessageRoleType {
     #[serde(rename="user")]
     User,
@@ -58,12 +306,101 @@ impl Display for MessageRoleType {
     }
 }
 
+/// 列表的排序方向
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum SortOrder {
+    #[serde(rename="asc")]
+    Asc,
+    #[default]
+    #[serde(rename="desc")]
+    Desc,
+}
+
+impl FromStr for SortOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(Self::Asc),
+            "desc" => Ok(Self::Desc),
+            _ => Err(Error::ParamsError("asc/desc pls".to_string()))
+        }
+    }
+}
+
+/// 聊天记录导出和导入的文件格式, markdown 只能导出, xlsx 只能导入
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum TranscriptFormat {
+    #[serde(rename="jsonl")]
+    Jsonl,
+    #[serde(rename="csv")]
+    Csv,
+    #[serde(rename="markdown")]
+    Markdown,
+    #[serde(rename="xlsx")]
+    Xlsx,
+}
+
+impl FromStr for TranscriptFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "xlsx" => Ok(Self::Xlsx),
+            _ => Err(Error::ParamsError("jsonl/csv/markdown/xlsx pls".to_string()))
+        }
+    }
+}
+
+/// 可以下载为 CSV 的统计报表
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum AnalyticsReport {
+    /// 每天的消息数和活跃用户数
+    #[serde(rename="daily")]
+    Daily,
+    /// 各模型的 token 用量和平均耗时
+    #[serde(rename="models")]
+    Models,
+    /// 发送消息最多的用户
+    #[serde(rename="top_users")]
+    TopUsers,
+}
+
+impl FromStr for AnalyticsReport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Self::Daily),
+            "models" => Ok(Self::Models),
+            "top_users" => Ok(Self::TopUsers),
+            _ => Err(Error::ParamsError("daily/models/top_users pls".to_string()))
+        }
+    }
+}
+
+/// 导出和导入的一条消息, 各格式的列相同
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TranscriptRecord {
+    pub user_name: String,
+    #[serde(rename="type")]
+    pub type_: MessageRoleType,
+    pub text: String,
+    /// UTC, 导入时为空则使用导入时间
+    pub created_at: Option<NaiveDateTime>,
+    pub model: Option<String>,
+}
+
 
 
 pub type MessageId = String;
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Message {
     pub id: MessageId,
+    pub org_id: OrganizationId,
     pub user_id: UserId,
     #[serde(rename="type")]
     pub type_: MessageRoleType,
@@ -72,18 +409,211 @@ pub struct Message {
     pub created_by: CreatedBy,
     pub updated_at: UpdatedAt,
     pub updated_by: UpdatedBy,
+    /// 生成 AI 消息的模型
+    pub model: Option<String>,
+    /// AI 消息消耗的 token
+    pub usage: Option<TokenUsage>,
+    /// 生成 AI 消息的耗时(毫秒), 从请求上游到收到完整回复
+    pub latency_ms: Option<u64>,
+}
+
+/// 上游返回的 token 用量
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+pub type ApiKeyId = String;
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    pub user_id: UserId,
+    pub name: String,
+    /// 明文的前几位, 用于辨认是哪个 Key
+    pub prefix: String,
+    pub created_at: CreatedAt,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Context {
     pub user: User,
+    pub org_id: OrganizationId,
+    /// 当前请求的 X-Request-Id, 不在请求中(如命令行)时为空
+    pub request_id: String,
 }
 
 impl Context {
     pub fn new(user: User) -> Self {
         Self {
-            user
+            org_id: user.org_id.clone(),
+            user,
+            request_id: current_request_id().unwrap_or_default(),
+        }
+    }
+
+    /// 请求中指定了用户名时, 必须是当前认证的用户
+    pub fn check_user_name(&self, user_name: Option<&str>) -> Result<(), Error> {
+        match user_name {
+            Some(user_name) if user_name != self.user.name => Err(Error::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_permission(&self, permission: Permission) -> Result<(), Error> {
+        let granted = self.user.role.has_permission(permission)
+            && (permission != Permission::ManageOrganizations || self.org_id == DEFAULT_ORGANIZATION_ID);
+        if granted {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    /// 操作的是其他组织时需要 ManageOrganizations 权限
+    pub fn check_organization(&self, org_id: &str) -> Result<(), Error> {
+        if org_id == self.org_id {
+            Ok(())
+        } else {
+            self.check_permission(Permission::ManageOrganizations)
+        }
+    }
+
+    /// 操作的是其他用户时需要对应权限, 返回 true 表示操作的是其他用户
+    pub fn check_other_user_permission(&self, user_name: Option<&str>, permission: Permission) -> Result<bool, Error> {
+        match user_name {
+            Some(user_name) if user_name != self.user.name => {
+                self.check_permission(permission)?;
+                Ok(true)
+            }
+            _ => Ok(false),
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+
+    const ALL_PERMISSIONS: [Permission; 8] = [
+        Permission::ReadAnyUserChat,
+        Permission::DeleteAnyUserChat,
+        Permission::ViewUsers,
+        Permission::ManageUsers,
+        Permission::DeleteUsers,
+        Permission::ManageRoles,
+        Permission::ManagePersonas,
+        Permission::ManageOrganizations,
+    ];
+
+    fn context(name: &str, role: Role) -> Context {
+        org_context(DEFAULT_ORGANIZATION_ID, name, role)
+    }
+
+    fn org_context(org_id: &str, name: &str, role: Role) -> Context {
+        Context::new(User {
+            id: ObjectId::new().to_hex(),
+            org_id: org_id.to_string(),
+            name: name.to_string(),
+            preferences: Default::default(),
+            role,
+            disabled: false,
+            message_retention_days: None,
+            created_at: Utc::now().naive_utc(),
+            updated_at: None,
+        })
+    }
+
+    #[test]
+    fn role_permission_matrix() {
+        let granted = |role: Role| ALL_PERMISSIONS.iter()
+            .copied()
+            .filter(|permission| role.has_permission(*permission))
+            .collect::<Vec<Permission>>();
+        assert_eq!(granted(Role::User), vec![]);
+        assert_eq!(granted(Role::Support), vec![Permission::ReadAnyUserChat, Permission::ViewUsers]);
+        assert_eq!(granted(Role::Admin), ALL_PERMISSIONS.to_vec());
+    }
+
+    #[test]
+    fn support_can_read_but_not_delete_other_user_chat() {
+        let ctx = context("support", Role::Support);
+        assert!(ctx.check_permission(Permission::ReadAnyUserChat).is_ok());
+        assert!(matches!(ctx.check_permission(Permission::DeleteAnyUserChat), Err(Error::Forbidden)));
+        assert!(matches!(ctx.check_permission(Permission::DeleteUsers), Err(Error::Forbidden)));
+    }
+
+    #[test]
+    fn other_user_access_requires_permission() {
+        let user = context("alice", Role::User);
+        assert!(!user.check_other_user_permission(None, Permission::ReadAnyUserChat).unwrap());
+        assert!(!user.check_other_user_permission(Some("alice"), Permission::ReadAnyUserChat).unwrap());
+        assert!(matches!(user.check_other_user_permission(Some("bob"), Permission::ReadAnyUserChat), Err(Error::Forbidden)));
+
+        let support = context("support", Role::Support);
+        assert!(support.check_other_user_permission(Some("bob"), Permission::ReadAnyUserChat).unwrap());
+        assert!(matches!(support.check_other_user_permission(Some("bob"), Permission::DeleteAnyUserChat), Err(Error::Forbidden)));
+
+        let admin = context("admin", Role::Admin);
+        assert!(admin.check_other_user_permission(Some("bob"), Permission::DeleteAnyUserChat).unwrap());
+    }
+
+    #[test]
+    fn only_default_organization_admin_manages_organizations() {
+        let other_org = ObjectId::new().to_hex();
+        let admin = context("admin", Role::Admin);
+        assert!(admin.check_permission(Permission::ManageOrganizations).is_ok());
+        assert!(admin.check_organization(other_org.as_str()).is_ok());
+
+        let org_admin = org_context(other_org.as_str(), "org-admin", Role::Admin);
+        assert!(org_admin.check_permission(Permission::ManageUsers).is_ok());
+        assert!(matches!(org_admin.check_permission(Permission::ManageOrganizations), Err(Error::Forbidden)));
+        assert!(org_admin.check_organization(other_org.as_str()).is_ok());
+        assert!(matches!(org_admin.check_organization(DEFAULT_ORGANIZATION_ID), Err(Error::Forbidden)));
+
+        let support = context("support", Role::Support);
+        assert!(matches!(support.check_organization(other_org.as_str()), Err(Error::Forbidden)));
+    }
+
+    #[test]
+    fn role_round_trips_through_string() {
+        for role in [Role::User, Role::Support, Role::Admin] {
+            assert_eq!(role.to_string().parse::<Role>().unwrap(), role);
+        }
+        assert!("root".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn system_prompt_follows_preferences() {
+        assert_eq!(UserPreferences::default().system_prompt(None), None);
+
+        let persona = Persona {
+            id: ObjectId::new().to_hex(),
+            org_id: DEFAULT_ORGANIZATION_ID.to_string(),
+            name: "tutor".to_string(),
+            prompt: "You are a patient math tutor.".to_string(),
+            created_at: Utc::now().naive_utc(),
+            updated_at: None,
+        };
+        let preferences = UserPreferences {
+            display_name: Some("小明".to_string()),
+            locale: Some("zh-CN".to_string()),
+            default_persona: Some("tutor".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            preferences.system_prompt(Some(&persona)).unwrap(),
+            "You are a patient math tutor.\nThe user's name is 小明.\nAlways respond in zh-CN.",
+        );
+
+        let preferences = UserPreferences {
+            response_language: Some("English".to_string()),
+            ..preferences
+        };
+        assert!(preferences.system_prompt(None).unwrap().ends_with("Always respond in English."));
+    }
+}
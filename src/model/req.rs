@@ -3,18 +3,39 @@ use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
 use rocket::form::FromForm;
-use crate::model::MessageRoleType;
+use crate::model::{MessageRoleType, SortOrder, TokenUsage};
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
 pub struct GetAiChatResponseInput {
     pub message: String,
-    pub user_name: String,
+    /// 可选, 指定时必须与当前认证的用户一致
+    pub user_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct GetUserChatHistoryQuery {
+    /// 可选, 默认为当前认证的用户
+    pub user_name: Option<String>,
+    /// 消息 id, 返回这条消息之前(更早)的消息
+    pub before: Option<String>,
+    /// 消息 id, 返回这条消息之后(更新)的消息
+    pub after: Option<String>,
+    /// 默认 20, 范围 1 ~ 100
+    pub limit: Option<u64>,
+    /// 已废弃, 等同于 limit
+    pub last_n: Option<u64>,
+    /// desc (默认, 新消息在前) 或 asc
+    pub order: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GetUserChatHistoryInput {
-    pub user_name: String,
-    pub last_n: i8,
+    pub user_name: Option<String>,
+    /// before 和 after 只能指定一个, 都不指定时从最新 (desc) 或最早 (asc) 的消息开始
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub limit: u64,
+    pub order: SortOrder,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -22,11 +43,238 @@ pub struct GetChatStatusTodayInput {
     pub user_name: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SetUserTimezoneInput {
+    /// IANA 时区名, 例如 Asia/Shanghai, 每 24 小时只能修改一次
+    pub timezone: String,
+}
+
+/// 只修改传入的项, 传空字符串表示清除该项
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateUserPreferencesInput {
+    pub display_name: Option<String>,
+    /// BCP 47 语言标签, 例如 zh-CN
+    pub locale: Option<String>,
+    /// IANA 时区名, 例如 Asia/Shanghai, 每 24 小时只能修改一次
+    pub timezone: Option<String>,
+    pub default_model: Option<String>,
+    /// 组织内的角色名, 可选的角色见 list_personas
+    pub default_persona: Option<String>,
+    pub response_language: Option<String>,
+    pub disable_history: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RegisterUserInput {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateApiKeyInput {
+    /// 便于辨认的名称, 例如 laptop
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RevokeApiKeyInput {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct ListUsersQuery {
+    /// 从 1 开始, 默认 1
+    pub page: Option<u64>,
+    /// 默认 20, 最大 100
+    pub page_size: Option<u64>,
+    /// 用户名包含的文字, 不区分大小写
+    pub name: Option<String>,
+    pub disabled: Option<bool>,
+    /// RFC 3339 时间或 YYYY-MM-DD (UTC)
+    pub created_after: Option<String>,
+    /// RFC 3339 时间或 YYYY-MM-DD (UTC)
+    pub created_before: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ListUsersInput {
+    /// 从 1 开始
+    pub page: u64,
+    pub page_size: u64,
+    /// 用户名包含的文字, 不区分大小写
+    pub name: Option<String>,
+    pub disabled: Option<bool>,
+    pub created_after: Option<NaiveDateTime>,
+    pub created_before: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RenameUserInput {
+    pub user_id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SetUserDisabledInput {
+    pub user_id: String,
+    pub disabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SetUserRoleInput {
+    pub user_id: String,
+    /// user/support/admin
+    pub role: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeleteUserChatHistoryInput {
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeleteUserInput {
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateUserInput {
+    pub name: String,
+    /// 未指定时创建在当前组织, 指定其他组织需要 ManageOrganizations 权限
+    pub org_id: Option<String>,
+    /// user/support/admin, 默认 user
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateOrganizationInput {
+    pub name: String,
+    pub daily_message_limit: Option<i64>,
+    pub burst_message_limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreatePersonaInput {
+    /// 组织内唯一
+    pub name: String,
+    /// 对话时作为 system prompt 的角色设定
+    pub prompt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UpdatePersonaInput {
+    pub persona_id: String,
+    pub prompt: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeletePersonaInput {
+    pub persona_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateOrganizationQuotaInput {
+    pub org_id: String,
+    /// 为空时恢复服务端默认值
+    pub daily_message_limit: Option<i64>,
+    /// 为空时恢复服务端默认值
+    pub burst_message_limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct GetOrganizationUsageQuery {
+    /// 未指定时为当前组织
+    pub org_id: Option<String>,
+    /// YYYY-MM-DD, 默认 30 天前
+    pub start: Option<String>,
+    /// YYYY-MM-DD (不含), 默认明天
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct GetModelUsageQuery {
+    /// 未指定时为当前组织
+    pub org_id: Option<String>,
+    /// YYYY-MM-DD, 默认 30 天前
+    pub start: Option<String>,
+    /// YYYY-MM-DD (不含), 默认明天
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct GetTopUsersQuery {
+    /// 未指定时为当前组织
+    pub org_id: Option<String>,
+    /// YYYY-MM-DD, 默认 30 天前
+    pub start: Option<String>,
+    /// YYYY-MM-DD (不含), 默认明天
+    pub end: Option<String>,
+    /// 默认 10, 最多 100
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct DownloadAnalyticsQuery {
+    /// daily/models/top_users
+    pub report: String,
+    /// 未指定时为当前组织
+    pub org_id: Option<String>,
+    /// YYYY-MM-DD, 默认 30 天前
+    pub start: Option<String>,
+    /// YYYY-MM-DD (不含), 默认明天
+    pub end: Option<String>,
+    /// 仅 top_users, 默认 10, 最多 100
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SetOrganizationRetentionInput {
+    pub org_id: String,
+    /// 消息保留天数, 0 表示永久保留, 为空时恢复服务端默认值
+    pub message_retention_days: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SetUserRetentionInput {
+    pub user_id: String,
+    /// 消息保留天数, 0 表示永久保留, 为空时使用组织的设置
+    pub message_retention_days: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct GetRetentionReportQuery {
+    /// 未指定时为当前组织
+    pub org_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct ExportMessagesQuery {
+    /// 未指定时导出组织内所有用户的消息
+    pub user_name: Option<String>,
+    /// 未指定时为当前组织
+    pub org_id: Option<String>,
+    /// jsonl/csv/markdown, 默认 jsonl
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, FromForm)]
+pub struct ImportMessagesQuery {
+    /// jsonl/csv/xlsx
+    pub format: String,
+    /// 未指定时为当前组织, 不存在的用户创建在该组织
+    pub org_id: Option<String>,
+    /// 只校验并返回报告, 不写入
+    pub dry_run: Option<bool>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct NewMessage {
+    pub org_id: String,
     pub user_id: String,
     #[serde(rename="type")]
     pub type_: MessageRoleType,
     pub text: String,
+    pub model: Option<String>,
+    pub usage: Option<TokenUsage>,
+    pub latency_ms: Option<u64>,
 }
 
@@ -1,16 +1,21 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
-use crate::model::MessageRoleType;
+use crate::model::{ApiKey, Message, MessageRoleType, Organization, Persona, TokenUsage, User};
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GetAiChatResponseOutput {
     pub response: String,
+    /// 回复来自缓存, 没有请求模型
+    pub cached: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GetChatStatusTodayOutput {
     pub user_name: String,
     pub chat_cnt: u64,
+    pub timezone: String,
+    pub next_reset_at: DateTime<FixedOffset>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -20,4 +25,226 @@ pub struct UserChatMessage {
     pub text: String,
 }
 
-pub type GetUserChatHistoryOutput = Vec<UserChatMessage>;
\ No newline at end of file
+pub type GetUserChatHistoryOutput = Vec<UserChatMessage>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UserChatMessageV2 {
+    pub id: String,
+    #[serde(rename="type")]
+    pub type_: MessageRoleType,
+    pub text: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>,
+    /// 仅 AI 消息
+    pub model: Option<String>,
+    /// 仅 AI 消息, 上游未返回时为空
+    pub usage: Option<TokenUsage>,
+}
+
+impl UserChatMessageV2 {
+    pub fn from_entity(msg: Message) -> Self {
+        Self {
+            id: msg.id,
+            type_: msg.type_,
+            text: msg.text,
+            created_at: msg.created_at,
+            updated_at: msg.updated_at,
+            model: msg.model,
+            usage: msg.usage,
+        }
+    }
+}
+
+/// 与 v1 的分页参数相同, 消息带上 id, 时间和模型信息
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetUserChatHistoryV2Output {
+    pub messages: Vec<UserChatMessageV2>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateApiKeyOutput {
+    pub api_key: ApiKey,
+    /// API Key 明文, 只返回这一次
+    pub key: String,
+}
+
+pub type ListApiKeysOutput = Vec<ApiKey>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RegisterUserOutput {
+    pub user: User,
+    pub api_key: ApiKey,
+    /// 首个 API Key 的明文, 只返回这一次
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ListUsersOutput {
+    pub users: Vec<User>,
+    pub total: u64,
+    pub page: u64,
+    pub page_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UserChatStats {
+    pub message_count: u64,
+    pub user_message_count: u64,
+    pub ai_message_count: u64,
+    pub today_message_count: u64,
+    pub first_message_at: Option<NaiveDateTime>,
+    pub last_message_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetUserStatsOutput {
+    pub user: User,
+    pub chat: UserChatStats,
+    pub api_key_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeleteUserOutput {
+    pub user_id: String,
+    pub deleted_messages: u64,
+    pub deleted_api_keys: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeleteUserChatHistoryOutput {
+    pub user_id: String,
+    pub deleted_messages: u64,
+}
+
+pub type ListOrganizationsOutput = Vec<Organization>;
+
+pub type ListPersonasOutput = Vec<Persona>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ImportRowError {
+    /// 文件中的行号, 从 1 开始, csv 和 xlsx 包含表头
+    pub row: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ImportUserSummary {
+    pub user_name: String,
+    /// 用户不存在, 导入时会创建
+    pub new_user: bool,
+    pub message_count: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ImportMessagesOutput {
+    pub dry_run: bool,
+    /// 有错误或 dry_run 时不会写入任何数据
+    pub imported: bool,
+    pub row_count: u64,
+    pub message_count: u64,
+    pub users: Vec<ImportUserSummary>,
+    pub error_count: u64,
+    /// 最多列出前 100 个错误
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UserRetentionReport {
+    pub user_id: String,
+    pub user_name: String,
+    /// 生效的保留天数
+    pub message_retention_days: i64,
+    /// 早于该时间 (UTC) 的消息会被清理
+    pub cutoff: String,
+    pub expired_messages: u64,
+}
+
+/// 仅统计, 不会删除消息
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetRetentionReportOutput {
+    pub organization: Organization,
+    /// 组织生效的保留天数, 为空表示永久保留
+    pub message_retention_days: Option<i64>,
+    pub expired_messages: u64,
+    /// 只包含有过期消息的用户
+    pub users: Vec<UserRetentionReport>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DailyUsage {
+    /// YYYY-MM-DD, 服务端默认时区
+    pub date: String,
+    pub user_message_count: u64,
+    pub ai_message_count: u64,
+    pub active_user_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetOrganizationUsageOutput {
+    pub organization: Organization,
+    pub start: String,
+    pub end: String,
+    pub user_count: u64,
+    pub active_user_count: u64,
+    pub user_message_count: u64,
+    pub ai_message_count: u64,
+    pub daily: Vec<DailyUsage>,
+}
+
+/// 一个模型的用量, model 为空表示未记录模型的消息
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ModelUsageStat {
+    pub model: String,
+    pub ai_message_count: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    /// 平均生成耗时(毫秒), 没有记录耗时的消息时为空
+    pub average_latency_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetModelUsageOutput {
+    pub organization: Organization,
+    pub start: String,
+    pub end: String,
+    pub models: Vec<ModelUsageStat>,
+}
+
+/// 用户已删除时 user_name 为空
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TopUserStat {
+    pub user_id: String,
+    pub user_name: String,
+    pub user_message_count: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GetTopUsersOutput {
+    pub organization: Organization,
+    pub start: String,
+    pub end: String,
+    pub users: Vec<TopUserStat>,
+}
+
+/// 一项依赖的检查结果
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DependencyStatus {
+    /// database, cache 或 upstream
+    pub name: String,
+    /// 具体实现, 如 mongo, postgres, redis, memory, openrouter
+    pub backend: String,
+    pub ok: bool,
+    pub latency_ms: u64,
+    /// 失败时为 不可用 或 超时, 具体原因只记录在服务端日志中
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HealthOutput {
+    /// ok 或 unavailable
+    pub status: String,
+    pub checks: Vec<DependencyStatus>,
+}
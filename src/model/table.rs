@@ -1,24 +1,140 @@
+use std::str::FromStr;
+use anyhow::Context;
+use chrono::NaiveDateTime;
 use mongodb::bson::DateTime;
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use crate::error::Error;
 
-use crate::model::{Message, User};
+use crate::model::{ApiKey, DEFAULT_ORGANIZATION_ID, Message, Organization, Persona, Role, TokenUsage, User, UserPreferences};
 
 
+fn org_id_to_hex(org_id: Option<ObjectId>) -> String {
+    org_id.map(|org_id| org_id.to_hex()).unwrap_or(DEFAULT_ORGANIZATION_ID.to_string())
+}
+
+pub fn parse_oid(id: &str) -> Result<ObjectId, Error> {
+    Ok(ObjectId::from_str(id).with_context(|| format!("parse oid error: {}", id))?)
+}
+
+fn to_bson_datetime(dt: NaiveDateTime) -> DateTime {
+    DateTime::from_chrono(dt.and_utc())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationDoc {
+    pub _id: ObjectId,
+    pub name: String,
+    pub daily_message_limit: Option<i64>,
+    pub burst_message_limit: Option<i64>,
+    pub message_retention_days: Option<i64>,
+    pub created_at: DateTime,
+    pub updated_at: Option<DateTime>,
+}
+
+impl OrganizationDoc {
+    pub fn from_entity(org: Organization) -> Result<Self, Error> {
+        Ok(Self {
+            _id: parse_oid(org.id.as_str())?,
+            name: org.name,
+            daily_message_limit: org.daily_message_limit,
+            burst_message_limit: org.burst_message_limit,
+            message_retention_days: org.message_retention_days,
+            created_at: to_bson_datetime(org.created_at),
+            updated_at: org.updated_at.map(to_bson_datetime),
+        })
+    }
+
+    pub fn into_entity(self) -> Result<Organization, Error> {
+        let org = Organization {
+            id: self._id.to_hex(),
+            name: self.name,
+            daily_message_limit: self.daily_message_limit,
+            burst_message_limit: self.burst_message_limit,
+            message_retention_days: self.message_retention_days,
+            created_at: self.created_at.to_chrono().naive_utc(),
+            updated_at: self.updated_at.map(|updated_at| updated_at.to_chrono().naive_utc()),
+        };
+        Ok(org)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonaDoc {
+    pub _id: ObjectId,
+    pub org_id: ObjectId,
+    pub name: String,
+    pub prompt: String,
+    pub created_at: DateTime,
+    pub updated_at: Option<DateTime>,
+}
+
+impl PersonaDoc {
+    pub fn from_entity(persona: Persona) -> Result<Self, Error> {
+        Ok(Self {
+            _id: parse_oid(persona.id.as_str())?,
+            org_id: parse_oid(persona.org_id.as_str())?,
+            name: persona.name,
+            prompt: persona.prompt,
+            created_at: to_bson_datetime(persona.created_at),
+            updated_at: persona.updated_at.map(to_bson_datetime),
+        })
+    }
+
+    pub fn into_entity(self) -> Persona {
+        Persona {
+            id: self._id.to_hex(),
+            org_id: self.org_id.to_hex(),
+            name: self.name,
+            prompt: self.prompt,
+            created_at: self.created_at.to_chrono().naive_utc(),
+            updated_at: self.updated_at.map(|updated_at| updated_at.to_chrono().naive_utc()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserDoc {
     pub _id: ObjectId,
+    /// 未设置时属于默认组织
+    pub org_id: Option<ObjectId>,
     pub name: String,
+    pub preferences: Option<UserPreferences>,
+    /// user/support/admin, 未设置时为 user
+    pub role: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    pub message_retention_days: Option<i64>,
     pub created_at: DateTime,
     pub updated_at: Option<DateTime>,
 }
 
 impl UserDoc {
-    pub fn to_entity(self) -> Result<User, Error> {
+    pub fn from_entity(user: User) -> Result<Self, Error> {
+        Ok(Self {
+            _id: parse_oid(user.id.as_str())?,
+            org_id: Some(parse_oid(user.org_id.as_str())?),
+            name: user.name,
+            preferences: Some(user.preferences),
+            role: Some(user.role.to_string()),
+            disabled: user.disabled,
+            message_retention_days: user.message_retention_days,
+            created_at: to_bson_datetime(user.created_at),
+            updated_at: user.updated_at.map(to_bson_datetime),
+        })
+    }
+
+    pub fn into_entity(self) -> Result<User, Error> {
         let user = User {
             id: self._id.to_hex(),
+            org_id: org_id_to_hex(self.org_id),
             name: self.name,
+            preferences: self.preferences.unwrap_or_default(),
+            role: if let Some(role) = self.role {
+                role.parse()?
+            } else { Role::User },
+            disabled: self.disabled,
+            message_retention_days: self.message_retention_days,
             created_at: self.created_at.to_chrono().naive_utc(),
             updated_at: if let Some(updated_at) = self.updated_at {
                 Some(updated_at.to_chrono().naive_utc())
@@ -31,6 +147,8 @@ impl UserDoc {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageDoc {
     pub _id: ObjectId,
+    /// 未设置时属于默认组织
+    pub org_id: Option<ObjectId>,
     pub user_id: ObjectId,
     #[serde(rename="type")]
     pub type_: String,
@@ -39,12 +157,36 @@ pub struct MessageDoc {
     pub created_by: ObjectId,
     pub updated_at: Option<DateTime>,
     pub updated_by: Option<ObjectId>,
+    pub model: Option<String>,
+    pub usage: Option<TokenUsage>,
+    pub latency_ms: Option<i64>,
 }
 
 impl MessageDoc {
-    pub fn to_entity(self) -> Result<Message, Error> {
+    pub fn from_entity(msg: Message) -> Result<Self, Error> {
+        Ok(Self {
+            _id: parse_oid(msg.id.as_str())?,
+            org_id: Some(parse_oid(msg.org_id.as_str())?),
+            user_id: parse_oid(msg.user_id.as_str())?,
+            type_: msg.type_.to_string(),
+            text: msg.text,
+            created_at: to_bson_datetime(msg.created_at),
+            created_by: parse_oid(msg.created_by.as_str())?,
+            updated_at: msg.updated_at.map(to_bson_datetime),
+            updated_by: match msg.updated_by {
+                Some(updated_by) => Some(parse_oid(updated_by.as_str())?),
+                None => None,
+            },
+            model: msg.model,
+            usage: msg.usage,
+            latency_ms: msg.latency_ms.map(|latency_ms| latency_ms as i64),
+        })
+    }
+
+    pub fn into_entity(self) -> Result<Message, Error> {
         let msg = Message {
             id: self._id.to_hex(),
+            org_id: org_id_to_hex(self.org_id),
             user_id: self.user_id.to_hex(),
             type_: self.type_.parse()?,
             text: self.text,
@@ -56,7 +198,73 @@ impl MessageDoc {
             updated_by: if let Some(updated_by) = self.updated_by {
                 Some(updated_by.to_hex())
             } else { None },
+            model: self.model,
+            usage: self.usage,
+            latency_ms: self.latency_ms.map(|latency_ms| latency_ms.max(0) as u64),
         };
         Ok(msg)
     }
-}
\ No newline at end of file
+}
+
+/// 用户的额度计数, _id 即用户 id, 通过 version 做乐观锁保证预留额度的原子性
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaDoc {
+    pub _id: ObjectId,
+    /// 用户所在时区的日期, 例如 2024-01-01, 只向后滚动, 滚动到更晚的日期时 day_count 和 pending 清零
+    pub day: String,
+    pub day_count: i64,
+    /// 最近一个窗口内预留的时间点
+    pub recent: Vec<DateTime>,
+    /// 已预留但尚未提交或释放的数量
+    pub pending: i64,
+    pub version: i64,
+}
+
+/// API Key 只保存 sha256 摘要, 明文只在创建时返回一次
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyDoc {
+    pub _id: ObjectId,
+    pub user_id: ObjectId,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+    pub revoked_at: Option<DateTime>,
+}
+
+impl ApiKeyDoc {
+    pub fn from_entity(api_key: ApiKey, key_hash: String) -> Result<Self, Error> {
+        Ok(Self {
+            _id: parse_oid(api_key.id.as_str())?,
+            user_id: parse_oid(api_key.user_id.as_str())?,
+            name: api_key.name,
+            prefix: api_key.prefix,
+            key_hash,
+            created_at: to_bson_datetime(api_key.created_at),
+            last_used_at: api_key.last_used_at.map(to_bson_datetime),
+            revoked_at: api_key.revoked_at.map(to_bson_datetime),
+        })
+    }
+
+    pub fn into_entity(self) -> Result<ApiKey, Error> {
+        let api_key = ApiKey {
+            id: self._id.to_hex(),
+            user_id: self.user_id.to_hex(),
+            name: self.name,
+            prefix: self.prefix,
+            created_at: self.created_at.to_chrono().naive_utc(),
+            last_used_at: self.last_used_at.map(|dt| dt.to_chrono().naive_utc()),
+            revoked_at: self.revoked_at.map(|dt| dt.to_chrono().naive_utc()),
+        };
+        Ok(api_key)
+    }
+}
+
+/// 已执行的迁移, _id 即迁移版本号
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationDoc {
+    pub _id: i64,
+    pub name: String,
+    pub applied_at: DateTime,
+}
@@ -0,0 +1,208 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use crate::error::Error;
+use crate::model::{DailyUsage, ModelUsageStat, TopUserStat};
+use crate::store::api_client::ApiClients;
+use crate::store::cache::Caches;
+use crate::store::repository::{MessageUsage, Repositories};
+use crate::store::Store;
+
+pub struct AnalyticsProvider {
+    store: Store,
+    repo: Repositories,
+    cache: Caches,
+    api: ApiClients,
+}
+
+
+impl AnalyticsProvider {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store: store.clone(),
+            repo: store.repositories.clone(),
+            cache: store.caches.clone(),
+            api: store.api_clients.clone(),
+        }
+    }
+}
+
+/// 未指定统计区间时默认统计的天数
+const DEFAULT_DAYS: i64 = 30;
+/// 未指定时返回的用户数, 以及最多返回的用户数
+const DEFAULT_TOP_USERS: u64 = 10;
+const MAX_TOP_USERS: u64 = 100;
+
+pub const DAILY_COLUMNS: [&str; 4] = ["date", "user_message_count", "ai_message_count", "active_user_count"];
+pub const MODEL_COLUMNS: [&str; 6] = ["model", "ai_message_count", "prompt_tokens", "completion_tokens", "total_tokens", "average_latency_ms"];
+pub const TOP_USER_COLUMNS: [&str; 4] = ["user_id", "user_name", "user_message_count", "total_tokens"];
+
+/// 统计区间 [start, end), 日期按服务端默认时区划分
+#[derive(Debug, Clone, Copy)]
+pub struct DateRange {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    tz: Tz,
+}
+
+impl DateRange {
+    pub fn start_utc(&self) -> DateTime<Utc> {
+        self.date_to_utc(self.start)
+    }
+
+    pub fn end_utc(&self) -> DateTime<Utc> {
+        self.date_to_utc(self.end)
+    }
+
+    /// 当地 0 点对应的 UTC 时间, 夏令时导致 0 点不存在时按 UTC 0 点
+    fn date_to_utc(&self, date: NaiveDate) -> DateTime<Utc> {
+        let dt = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+        dt.and_local_timezone(self.tz).earliest()
+            .map(|dt| dt.with_timezone(&Utc))
+            .unwrap_or_else(|| dt.and_utc())
+    }
+}
+
+impl AnalyticsProvider {
+    /// 默认为截至今天的最近 30 天
+    pub fn date_range(&self, start: Option<NaiveDate>, end: Option<NaiveDate>) -> Result<DateRange, Error> {
+        let tz = self.store.config.default_timezone;
+        let today = Utc::now().with_timezone(&tz).date_naive();
+        let end = end.unwrap_or(today + Duration::days(1));
+        let start = start.unwrap_or(end - Duration::days(DEFAULT_DAYS));
+        if start >= end {
+            return Err(Error::ParamsError(format!("start 需早于 end: {} >= {}", start, end)));
+        }
+        Ok(DateRange { start, end, tz })
+    }
+
+    /// 每天的消息数和活跃用户数
+    pub async fn get_daily_usage(&self, org_id: String, range: DateRange) -> Result<MessageUsage, Error> {
+        self.repo.message.org_usage(org_id, range.start_utc(), range.end_utc(), range.tz).await
+    }
+
+    /// 各模型的 token 用量和平均耗时
+    pub async fn get_model_usage(&self, org_id: String, range: DateRange) -> Result<Vec<ModelUsageStat>, Error> {
+        let usage = self.repo.message.model_usage(org_id, range.start_utc(), range.end_utc()).await?;
+        Ok(usage.into_iter()
+            .map(|usage| ModelUsageStat {
+                model: usage.model,
+                ai_message_count: usage.message_count,
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                total_tokens: usage.total_tokens,
+                average_latency_ms: (usage.latency_count > 0).then(|| usage.latency_ms_sum / usage.latency_count),
+            })
+            .collect())
+    }
+
+    /// 发送消息最多的用户, limit 默认 10, 最多 100
+    pub async fn get_top_users(&self, org_id: String, range: DateRange, limit: Option<u64>) -> Result<Vec<TopUserStat>, Error> {
+        let limit = limit.unwrap_or(DEFAULT_TOP_USERS).clamp(1, MAX_TOP_USERS);
+        let activities = self.repo.message.top_users(org_id.clone(), range.start_utc(), range.end_utc(), limit).await?;
+        let mut res = vec![];
+        for activity in activities {
+            let user = self.repo.user.find_in_org_by_id(org_id.clone(), activity.user_id.clone()).await?;
+            res.push(TopUserStat {
+                user_id: activity.user_id,
+                user_name: user.map(|user| user.name).unwrap_or_default(),
+                user_message_count: activity.user_message_count,
+                total_tokens: activity.total_tokens,
+            });
+        }
+        Ok(res)
+    }
+
+    pub fn render_daily_csv(&self, daily: &[DailyUsage]) -> Result<String, Error> {
+        render_csv(&DAILY_COLUMNS, daily)
+    }
+
+    pub fn render_model_csv(&self, models: &[ModelUsageStat]) -> Result<String, Error> {
+        render_csv(&MODEL_COLUMNS, models)
+    }
+
+    pub fn render_top_user_csv(&self, users: &[TopUserStat]) -> Result<String, Error> {
+        render_csv(&TOP_USER_COLUMNS, users)
+    }
+}
+
+/// columns 需与 T 的字段顺序一致, 没有数据时也输出表头
+fn render_csv<T: Serialize>(columns: &[&str], rows: &[T]) -> Result<String, Error> {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+    writer.write_record(columns).with_context(|| "write csv header".to_string())?;
+    for row in rows {
+        writer.serialize(row).with_context(|| "write csv row".to_string())?;
+    }
+    let data = writer.into_inner().map_err(|err| anyhow::anyhow!("flush csv: {}", err))?;
+    Ok(String::from_utf8(data).with_context(|| "csv to utf-8".to_string())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+    use mongodb::bson::oid::ObjectId;
+    use crate::conf::Config;
+    use crate::model::{DEFAULT_ORGANIZATION_ID, Message, MessageRoleType, Role, TokenUsage, User};
+    use crate::providers::Providers;
+    use super::*;
+
+    fn message(user: &User, type_: MessageRoleType, created_at: NaiveDateTime, model: Option<&str>, latency_ms: Option<u64>) -> Message {
+        Message {
+            id: ObjectId::new().to_hex(),
+            org_id: user.org_id.clone(),
+            user_id: user.id.clone(),
+            type_,
+            text: "hi".to_string(),
+            created_at,
+            created_by: user.id.clone(),
+            updated_at: None,
+            updated_by: None,
+            model: model.map(|model| model.to_string()),
+            usage: model.map(|_| TokenUsage { prompt_tokens: 2, completion_tokens: 3, total_tokens: 5 }),
+            latency_ms,
+        }
+    }
+
+    #[tokio::test]
+    async fn model_usage_and_top_users_within_range() {
+        let store = Store::memory_for_test(Config::default()).await;
+        let pvd = Providers::new(&store);
+        let org_id = DEFAULT_ORGANIZATION_ID.to_string();
+        let alice = pvd.user().create_user(org_id.clone(), "alice".to_string(), Role::User).await.unwrap();
+        let bob = pvd.user().create_user(org_id.clone(), "bob".to_string(), Role::User).await.unwrap();
+
+        let day = |d: u32| NaiveDate::from_ymd_opt(2024, 3, d).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let messages = vec![
+            message(&alice, MessageRoleType::User, day(1), None, None),
+            message(&alice, MessageRoleType::AI, day(1), Some("a"), Some(100)),
+            message(&alice, MessageRoleType::User, day(2), None, None),
+            message(&alice, MessageRoleType::AI, day(2), Some("a"), Some(300)),
+            message(&bob, MessageRoleType::User, day(2), None, None),
+            message(&bob, MessageRoleType::AI, day(2), Some("b"), None),
+            // 区间外
+            message(&bob, MessageRoleType::User, day(5), None, None),
+            message(&bob, MessageRoleType::User, day(5), None, None),
+        ];
+        store.repositories.message.insert_many(messages).await.unwrap();
+
+        let analytics = pvd.analytics();
+        let range = analytics.date_range(NaiveDate::from_ymd_opt(2024, 3, 1), NaiveDate::from_ymd_opt(2024, 3, 3)).unwrap();
+        let models = analytics.get_model_usage(org_id.clone(), range).await.unwrap();
+        assert_eq!(models, vec![
+            ModelUsageStat { model: "a".to_string(), ai_message_count: 2, prompt_tokens: 4, completion_tokens: 6, total_tokens: 10, average_latency_ms: Some(200) },
+            ModelUsageStat { model: "b".to_string(), ai_message_count: 1, prompt_tokens: 2, completion_tokens: 3, total_tokens: 5, average_latency_ms: None },
+        ]);
+
+        let users = analytics.get_top_users(org_id.clone(), range, Some(1)).await.unwrap();
+        assert_eq!(users, vec![
+            TopUserStat { user_id: alice.id.clone(), user_name: "alice".to_string(), user_message_count: 2, total_tokens: 10 },
+        ]);
+
+        let daily = analytics.get_daily_usage(org_id, range).await.unwrap();
+        assert_eq!(analytics.render_daily_csv(&daily.daily).unwrap(),
+                   "date,user_message_count,ai_message_count,active_user_count\n2024-03-01,1,1,1\n2024-03-02,2,2,2\n");
+        assert_eq!(analytics.render_model_csv(&models).unwrap().lines().nth(2), Some("b,1,2,3,5,"));
+        assert!(analytics.date_range(NaiveDate::from_ymd_opt(2024, 3, 3), NaiveDate::from_ymd_opt(2024, 3, 3)).is_err());
+    }
+}
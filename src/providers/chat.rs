@@ -1,23 +1,22 @@
-use std::cmp::max;
-use std::str::FromStr;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
 use std::time::Duration;
-use anyhow::Context;
-use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
-use futures::TryStreamExt;
-use mongodb::bson::{DateTime as BsonDateTime, doc};
+use chrono::{DateTime, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use futures::future::ready;
+use futures::{stream, StreamExt};
+use chrono_tz::Tz;
 use mongodb::bson::oid::ObjectId;
-use mongodb::options::{FindOneOptions, FindOptions};
-use crate::error::Error;
+use crate::error::{Code, Error};
 
-use crate::model::{Message, MessageDoc, MessageRoleType, NewMessage, User};
+use crate::model::{Message, MessageRoleType, NewMessage, User, UserChatStats};
 use crate::store::api_client::ApiClients;
 use crate::store::cache::Caches;
-use crate::store::database::Databases;
+use crate::store::repository::{Direction, MessageRepository, MessageStream, Repositories};
 use crate::store::Store;
 
 pub struct ChatProvider {
     store: Store,
-    db: Databases,
+    repo: Repositories,
     cache: Caches,
     api: ApiClients,
 }
@@ -27,7 +26,7 @@ impl ChatProvider {
     pub fn new(store: Store) -> Self {
         Self {
             store: store.clone(),
-            db: store.databases.clone(),
+            repo: store.repositories.clone(),
             cache: store.caches.clone(),
             api: store.api_clients.clone(),
         }
@@ -35,98 +34,167 @@ impl ChatProvider {
 }
 
 impl ChatProvider {
-    pub async fn check_user_message_limited_in_30_seconds(&self, user: User) -> Result<bool, Error> {
-        let now = Utc::now();
-        let dt_start = now - Duration::from_secs(30);
-        let start = BsonDateTime::from_chrono(now);
-        let filter = doc! {
-            "user_id": ObjectId::from_str(user.id.as_str()).with_context(||format!("parse oid error: {}", user.id))?,
-            "type": MessageRoleType::User.to_string(),
-            "created_at": {"$gte": start}
-        };
-        debug!("filter: {}", filter);
-        let count = self.db.message().count_documents(filter, None).await
-            .with_context(|| "count_documents".to_string())?;
-        debug!("count: {}", count);
-        if count > 3 {
-            Ok(true)
-        } else {
-            Ok(false)
-        }
-    }
-
-    pub async fn check_user_message_limited_in_daily(&self, user: User) -> Result<bool, Error> {
-        let now = Utc::now();
-        let dt_start = NaiveDateTime::new(now.date_naive(), NaiveTime::default()).and_utc();
-        let start = BsonDateTime::from_chrono(dt_start);
-        let filter = doc! {
-            "user_id": ObjectId::from_str(user.id.as_str()).with_context(||format!("parse oid error: {}", user.id))?,
-            "type": MessageRoleType::User.to_string(),
-            "created_at": {"$gte": start}
-        };
-        debug!("filter: {}", filter);
-        let count = self.db.message().count_documents(filter, None).await
-            .with_context(|| "count_documents".to_string())?;
-        debug!("count: {}", count);
-        if count > 20 {
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+    /// 用户所在时区的今天: [今天零点, 明天零点), 明天零点即每日额度的重置时间
+    pub fn get_user_today(&self, user: User) -> (DateTime<Tz>, DateTime<Tz>) {
+        let tz = user.tz(self.store.config.default_timezone);
+        let today = Utc::now().with_timezone(&tz).date_naive();
+        let tomorrow = today.succ_opt().unwrap_or(today);
+        (local_midnight(tz, today), local_midnight(tz, tomorrow))
     }
 
     pub async fn add_chat_message(&self, messages: Vec<NewMessage>) -> Result<usize, Error> {
-        let mut docs = vec![];
-        for message in messages.iter() {
-            let doc = MessageDoc {
-                _id: ObjectId::new(),
-                user_id: ObjectId::from_str(message.user_id.as_str()).with_context(||format!("parse oid error: {}", message.user_id))?,
-                type_: message.type_.to_string(),
-                text: message.text.to_owned(),
-                created_at: BsonDateTime::now(),
-                created_by: ObjectId::from_str(message.user_id.as_str()).with_context(||format!("parse oid error: {}", message.user_id))?,
+        let now = Utc::now().naive_utc();
+        let messages = messages.into_iter()
+            .map(|message| Message {
+                id: ObjectId::new().to_hex(),
+                org_id: message.org_id,
+                user_id: message.user_id.clone(),
+                type_: message.type_,
+                text: message.text,
+                created_at: now,
+                created_by: message.user_id,
                 updated_at: None,
                 updated_by: None,
-            };
-            docs.push(doc);
-        }
-        let res = self.db.message().insert_many(docs, None).await
-            .with_context(|| "insert_many".to_string())?;
-        debug!("inserted: {:?}", res);
-        Ok(res.inserted_ids.len())
+                model: message.model,
+                usage: message.usage,
+                latency_ms: message.latency_ms,
+            })
+            .collect();
+        self.repo.message.insert_many(messages).await
     }
 
-    pub async fn get_user_chat_messages(&self, user: User, limit: i64) -> Result<Vec<Message>, Error> {
-        let limit = max(limit, 10);
-        let opts = FindOptions::builder().sort(doc! {"created_at": -1}).limit(limit).build();
-        let filter = doc! {
-            "user_id": ObjectId::from_str(user.id.as_str())?,
+    /// 从 cursor (消息 id, 不含) 开始沿 direction 的一页消息, 按 direction 的顺序返回, 还有更多消息时同时返回下一页的 cursor
+    pub async fn get_user_chat_messages(&self, user: User, cursor: Option<String>, direction: Direction, limit: u64) -> Result<(Vec<Message>, Option<String>), Error> {
+        let cursor = match cursor {
+            Some(message_id) => Some(self.repo.message.find_by_id(user.clone(), message_id).await?
+                .ok_or(Error::Feedback(Code::MessageNotFound))?),
+            None => None,
         };
-        debug!("filter: {}", filter);
-        let mut cursor = self.db.message().find(filter, opts).await
-            .with_context(|| "find".to_string())?;
-        let mut res = vec![];
-        while let Some(doc) = cursor.try_next().await
-            .with_context(|| "try_next".to_string())? {
-            res.push(doc.to_entity()?)
-        }
+        // 多取一条用于判断是否还有下一页
+        let mut res = self.repo.message.find_page(user, cursor, direction, limit + 1).await?;
         debug!("messages: {:?}", res);
-        Ok(res)
+        let next_cursor = if res.len() as u64 > limit {
+            res.truncate(limit as usize);
+            res.last().map(|msg| msg.id.clone())
+        } else {
+            None
+        };
+        Ok((res, next_cursor))
     }
 
-    pub async fn get_user_chat_messages_count_today(&self, user: User) -> Result<u64, Error> {
-        let now = Utc::now();
-        let dt_start = NaiveDateTime::new(now.date_naive(), NaiveTime::default()).and_utc();
-        let start = BsonDateTime::from_chrono(dt_start);
-        let filter = doc! {
-            "user_id": ObjectId::from_str(user.id.as_str()).with_context(||format!("parse oid error: {}", user.id))?,
-            "type": MessageRoleType::User.to_string(),
-            "created_at": {"$gte": start}
+    /// 订阅用户此后的新消息, 指定 after (消息 id, 不含) 时先补发其后的消息
+    pub async fn subscribe_user_chat_messages(&self, user: User, after: Option<String>) -> Result<MessageStream, Error> {
+        let cursor = match after {
+            Some(message_id) => Some(self.repo.message.find_by_id(user.clone(), message_id).await?
+                .ok_or(Error::Feedback(Code::MessageNotFound))?),
+            None => None,
         };
-        debug!("filter: {}", filter);
-        let count = self.db.message().count_documents(filter, None).await
-            .with_context(|| "count_documents".to_string())?;
+        if let Some(changes) = self.repo.message.watch(user.clone()).await? {
+            // 先订阅再补发, 补发期间插入的消息会同时出现在订阅中, 按 id 去重
+            let missed = match cursor {
+                Some(cursor) => self.repo.message.find_page(user, Some(cursor), Direction::Newer, MAX_MISSED_MESSAGES).await?,
+                None => vec![],
+            };
+            let missed_ids = missed.iter().map(|msg| msg.id.clone()).collect::<HashSet<String>>();
+            let changes = changes.filter(move |msg| ready(!matches!(msg, Ok(msg) if missed_ids.contains(&msg.id))));
+            return Ok(stream::iter(missed.into_iter().map(Ok)).chain(changes).boxed());
+        }
+        // 不支持推送时从最新一条消息开始轮询
+        let cursor = match cursor {
+            Some(cursor) => Some(cursor),
+            None => self.repo.message.find_page(user.clone(), None, Direction::Older, 1).await?.pop(),
+        };
+        let interval = Duration::from_secs(self.store.config.message_poll_interval_secs.max(1));
+        Ok(poll_messages(self.repo.message.clone(), user, cursor, interval))
+    }
+
+    pub async fn get_user_chat_messages_count_today(&self, user: User) -> Result<u64, Error> {
+        let (dt_start, _) = self.get_user_today(user.clone());
+        let count = self.repo.message.count(user, Some(MessageRoleType::User), Some(dt_start.with_timezone(&Utc))).await?;
         debug!("count: {}", count);
         Ok(count)
     }
+
+    pub async fn get_user_chat_stats(&self, user: User) -> Result<UserChatStats, Error> {
+        let message_count = self.repo.message.count(user.clone(), None, None).await?;
+        let user_message_count = self.repo.message.count(user.clone(), Some(MessageRoleType::User), None).await?;
+        let ai_message_count = self.repo.message.count(user.clone(), Some(MessageRoleType::AI), None).await?;
+        let today_message_count = self.get_user_chat_messages_count_today(user.clone()).await?;
+        let (first, last) = self.repo.message.find_first_and_last(user).await?;
+        Ok(UserChatStats {
+            message_count,
+            user_message_count,
+            ai_message_count,
+            today_message_count,
+            first_message_at: first.map(|msg| msg.created_at),
+            last_message_at: last.map(|msg| msg.created_at),
+        })
+    }
+
+    pub async fn delete_user_chat_messages(&self, user: User) -> Result<u64, Error> {
+        self.repo.message.delete_by_user(user).await
+    }
+}
+
+/// 订阅时最多补发的消息数, 更早的消息需通过聊天记录接口获取
+const MAX_MISSED_MESSAGES: u64 = 100;
+
+struct PollState {
+    repo: Arc<dyn MessageRepository>,
+    user: User,
+    cursor: Option<Message>,
+    pending: VecDeque<Message>,
+    interval: Duration,
+}
+
+/// 每隔 interval 查询 cursor 之后的消息
+fn poll_messages(repo: Arc<dyn MessageRepository>, user: User, cursor: Option<Message>, interval: Duration) -> MessageStream {
+    let state = PollState { repo, user, cursor, pending: VecDeque::new(), interval };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(msg) = state.pending.pop_front() {
+                return Some((Ok(msg), state));
+            }
+            match state.repo.find_page(state.user.clone(), state.cursor.clone(), Direction::Newer, MAX_MISSED_MESSAGES).await {
+                Ok(page) if page.is_empty() => tokio::time::sleep(state.interval).await,
+                Ok(page) => {
+                    state.cursor = page.last().cloned();
+                    state.pending.extend(page);
+                }
+                Err(err) => return Some((Err(err), state)),
+            }
+        }
+    }).boxed()
+}
+
+/// 指定时区某天的零点, 夏令时跳变导致零点不存在时取当天最早存在的整点
+fn local_midnight(tz: Tz, date: NaiveDate) -> DateTime<Tz> {
+    let midnight = NaiveDateTime::new(date, NaiveTime::default());
+    (0..24)
+        .find_map(|hour| match tz.from_local_datetime(&(midnight + chrono::Duration::hours(hour))) {
+            LocalResult::Single(dt) => Some(dt),
+            LocalResult::Ambiguous(earliest, _) => Some(earliest),
+            LocalResult::None => None,
+        })
+        .unwrap_or_else(|| tz.from_utc_datetime(&midnight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midnight_utc(tz: Tz, y: i32, m: u32, d: u32) -> String {
+        local_midnight(tz, NaiveDate::from_ymd_opt(y, m, d).unwrap()).with_timezone(&Utc).to_rfc3339()
+    }
+
+    #[test]
+    fn local_midnight_handles_dst_gaps_and_folds() {
+        assert_eq!(midnight_utc(Tz::Asia__Shanghai, 2023, 9, 3), "2023-09-02T16:00:00+00:00");
+        // 圣地亚哥夏令时从 0 点开始, 0 点不存在, 取 01:00 -03
+        assert_eq!(midnight_utc(Tz::America__Santiago, 2023, 9, 3), "2023-09-03T04:00:00+00:00");
+        // 哈瓦那夏令时在 1 点结束, 0 点出现两次, 取较早的 00:00 -04
+        assert_eq!(midnight_utc(Tz::America__Havana, 2023, 11, 5), "2023-11-05T04:00:00+00:00");
+        // 萨摩亚跳过了 2011-12-30 整天, 没有存在的整点时按 UTC 0 点
+        assert_eq!(midnight_utc(Tz::Pacific__Apia, 2011, 12, 30), "2011-12-30T00:00:00+00:00");
+    }
 }
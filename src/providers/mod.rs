@@ -1,6 +1,13 @@
+use crate::providers::analytics::AnalyticsProvider;
+use crate::providers::auth::AuthProvider;
+use crate::providers::organization::OrganizationProvider;
+use crate::providers::persona::PersonaProvider;
 use crate::providers::ping::PingProvider;
 use crate::providers::chat::ChatProvider;
 use crate::providers::openrouter::OpenRouterProvider;
+use crate::providers::quota::QuotaProvider;
+use crate::providers::retention::RetentionProvider;
+use crate::providers::transcript::TranscriptProvider;
 use crate::providers::user::UserProvider;
 use crate::store::Store;
 
@@ -8,6 +15,13 @@ mod ping;
 mod chat;
 mod openrouter;
 mod user;
+mod quota;
+mod auth;
+mod organization;
+mod persona;
+mod retention;
+mod transcript;
+mod analytics;
 
 #[derive(Clone)]
 pub struct Providers {
@@ -36,4 +50,32 @@ impl Providers {
     pub fn chat(&self) -> ChatProvider {
         ChatProvider::new(self.store.clone())
     }
+
+    pub fn quota(&self) -> QuotaProvider {
+        QuotaProvider::new(self.store.clone())
+    }
+
+    pub fn auth(&self) -> AuthProvider {
+        AuthProvider::new(self.store.clone())
+    }
+
+    pub fn organization(&self) -> OrganizationProvider {
+        OrganizationProvider::new(self.store.clone())
+    }
+
+    pub fn persona(&self) -> PersonaProvider {
+        PersonaProvider::new(self.store.clone())
+    }
+
+    pub fn retention(&self) -> RetentionProvider {
+        RetentionProvider::new(self.store.clone())
+    }
+
+    pub fn transcript(&self) -> TranscriptProvider {
+        TranscriptProvider::new(self.store.clone())
+    }
+
+    pub fn analytics(&self) -> AnalyticsProvider {
+        AnalyticsProvider::new(self.store.clone())
+    }
 }
@@ -2,19 +2,20 @@ use anyhow::Context;
 use async_openai::Client;
 use async_openai::config::OpenAIConfig;
 use async_openai::types::{ChatCompletionRequestMessage, ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, CompletionUsage, CreateChatCompletionRequestArgs, CreateChatCompletionResponse, CreateCompletionRequestArgs, Role};
-use reqwest::header::HeaderMap;
-use rocket_okapi::hash_map;
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use log::warn;
+use sha2::{Digest, Sha256};
 use crate::error::Error;
-use crate::store::api_client::ApiClients;
+use crate::metrics;
+use crate::store::api_client::{ApiClients, ChatCompletionMessage, ChatCompletionResult, OpenRouterCreateChatCompletionRequestArgs};
 use crate::store::cache::Caches;
-use crate::store::database::Databases;
+use crate::store::repository::Repositories;
 use crate::store::Store;
+use crate::telemetry::{self, Span, SpanKind};
 
 pub struct OpenRouterProvider {
     store: Store,
-    db: Databases,
+    repo: Repositories,
     cache: Caches,
     api: ApiClients,
 }
@@ -24,7 +25,7 @@ impl OpenRouterProvider {
     pub fn new(store: Store) -> Self {
         Self {
             store: store.clone(),
-            db: store.databases.clone(),
+            repo: store.repositories.clone(),
             cache: store.caches.clone(),
             api: store.api_clients.clone(),
         }
@@ -32,7 +33,23 @@ impl OpenRouterProvider {
 }
 
 impl OpenRouterProvider {
-    pub async fn chat(self, content: String) -> Result<String, Error> {
+    /// 用户可以选择的模型
+    pub fn check_model(&self, model: &str) -> Result<(), Error> {
+        if self.store.config.allowed_models.iter().any(|allowed| allowed == model) {
+            Ok(())
+        } else {
+            Err(Error::ParamsError(format!("不支持的模型: {}, 可选: {}", model, self.store.config.allowed_models.join(", "))))
+        }
+    }
+
+    /// model 为空时使用 CHAT_MODEL, system_prompt 不为空时作为第一条 system 消息
+    pub async fn chat(self, model: Option<String>, system_prompt: Option<String>, content: String) -> Result<ChatCompletionResult, Error> {
+        let span = Span::start("OpenRouterProvider.chat", SpanKind::Internal);
+        span.instrument(self.cached_chat(model, system_prompt, content)).await
+    }
+
+    /// 开启了回复缓存时, 相同的请求直接返回缓存的回复
+    async fn cached_chat(self, model: Option<String>, system_prompt: Option<String>, content: String) -> Result<ChatCompletionResult, Error> {
         // let config = OpenAIConfig::default()
         //     .with_api_base("https://openrouter.ai/api/v1")
         //     .with_api_key(self.store.config.openrouter_api_key);
@@ -50,69 +67,106 @@ impl OpenRouterProvider {
         // println!("{}", serde_json::to_string(&request).unwrap());
         // let response = client.chat().create(request).await
         //     .with_context(|| "chat create".to_string())?;
-        let url = "https://openrouter.ai/api/v1/chat/completions";
-        let mut headers = HeaderMap::new();
-        headers.insert("Authorization", format!("Bearer {}", self.store.config.openrouter_api_key).parse()?);
-        headers.insert("Content-Type", "application/json".parse()?);
-        let body = OpenRouterCreateChatCompletionRequestArgs {
-            model: "mistralai/mistral-7b-instruct:free".to_string(),
-            messages: vec![OpenRouterCreateChatCompletionRequestArgsMessage {
-                role: Role::User,
-                content: content,
-            }],
-        };
-        let client = reqwest::Client::new();
-        let response: OpenRouterCreateChatCompletionResponse = client.post(url).headers(headers).json(&body)
-            .send().await.with_context(|| "send request to openrouter".to_string())?
-            .json().await.with_context(|| "deserialize from openrouter".to_string())?;
-        debug!("response: {:?}", response);
-        let choice = response.choices[0].clone();
-        if let Some(response_content) = choice.message.content {
-            Ok(response_content)
-        } else {
-            Ok("todo".to_string())
+        let mut messages = vec![];
+        if let Some(system_prompt) = system_prompt {
+            messages.push(ChatCompletionMessage {
+                role: Role::System,
+                content: system_prompt,
+            });
+        }
+        messages.push(ChatCompletionMessage {
+            role: Role::User,
+            content,
+        });
+        let model = model.unwrap_or(self.store.config.chat_model.clone());
+        telemetry::record("gen_ai.request.model", model.clone());
+        let ttl = Duration::from_secs(self.store.config.response_cache_ttl_secs);
+        if ttl.is_zero() {
+            return self.complete(model, messages).await;
+        }
+        let key = response_cache_key(model.as_str(), &messages)?;
+        let cache = self.cache.chat_response();
+        match cache.get::<ChatCompletionResult>(key.as_str()).await {
+            Ok(Some(response)) => {
+                telemetry::record("gen_ai.response.cached", true);
+                return Ok(ChatCompletionResult { cached: true, ..response });
+            }
+            Ok(None) => {}
+            Err(err) => warn!("get cached response {}: {:?}", key, err),
+        }
+        let response = self.complete(model, messages).await?;
+        if let Err(err) = cache.set(key.as_str(), &response, ttl).await {
+            warn!("cache response {}: {:?}", key, err);
         }
+        Ok(response)
     }
-}
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct OpenRouterCreateChatCompletionRequestArgsMessage {
-    pub role: Role,
-    pub content: String,
+    /// 请求上游, 记录耗时, 错误数和 token 用量, 上游请求带上此 span 的 traceparent.
+    /// 空回复视为上游错误, 不会被缓存或保存
+    async fn complete(&self, model: String, messages: Vec<ChatCompletionMessage>) -> Result<ChatCompletionResult, Error> {
+        let span = Span::start(format!("chat {}", model), SpanKind::Client);
+        span.set_attribute("gen_ai.system", "openrouter");
+        span.set_attribute("gen_ai.request.model", model.clone());
+        let started_at = Instant::now();
+        let res = span.scope(self.api.chat.complete(model.clone(), messages)).await
+            .and_then(|response| if response.content.trim().is_empty() {
+                Err(Error::UpstreamError(format!("{} 返回了空回复", model)))
+            } else {
+                Ok(response)
+            });
+        let labels = [("model", model.as_str())];
+        metrics::observe(&metrics::UPSTREAM_REQUEST_DURATION, &labels, started_at.elapsed());
+        match &res {
+            Ok(response) => {
+                span.set_attribute("gen_ai.response.model", response.model.clone());
+                if let Some(usage) = &response.usage {
+                    span.set_attribute("gen_ai.usage.input_tokens", usage.prompt_tokens);
+                    span.set_attribute("gen_ai.usage.output_tokens", usage.completion_tokens);
+                    metrics::inc(&metrics::TOKENS_TOTAL, &[("model", model.as_str()), ("type", "prompt")], usage.prompt_tokens as f64);
+                    metrics::inc(&metrics::TOKENS_TOTAL, &[("model", model.as_str()), ("type", "completion")], usage.completion_tokens as f64);
+                }
+            }
+            Err(err) => {
+                span.set_error(err);
+                metrics::inc(&metrics::UPSTREAM_ERRORS_TOTAL, &labels, 1.0);
+            }
+        }
+        span.end();
+        res
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct OpenRouterCreateChatCompletionRequestArgs {
-    pub model: String,
-    pub messages: Vec<OpenRouterCreateChatCompletionRequestArgsMessage>,
+/// 模型和各消息的角色, 内容决定回复, 内容去掉首尾空白并把连续空白合并为一个空格
+fn response_cache_key(model: &str, messages: &[ChatCompletionMessage]) -> Result<String, Error> {
+    let messages = messages.iter()
+        .map(|message| ChatCompletionMessage {
+            role: message.role,
+            content: message.content.split_whitespace().collect::<Vec<&str>>().join(" "),
+        })
+        .collect::<Vec<ChatCompletionMessage>>();
+    let request = OpenRouterCreateChatCompletionRequestArgs {
+        model: model.to_string(),
+        messages,
+    };
+    let digest = Sha256::digest(serde_json::to_vec(&request)?);
+    Ok(hex::encode(digest))
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct OpenRouterChatChoiceMessage {
-    pub role: Role,
-    pub content: Option<String>,
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct OpenRouterChatChoice {
-    pub message: OpenRouterChatChoiceMessage,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn message(role: Role, content: &str) -> ChatCompletionMessage {
+        ChatCompletionMessage { role, content: content.to_string() }
+    }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct OpenRouterCompletionUsage {
-    pub prompt_tokens: u32,
-    pub completion_tokens: u32,
-    pub total_tokens: u32,
-    pub total_cost: u32,
+    #[test]
+    fn cache_key_ignores_whitespace_but_not_model_or_role() {
+        let key = |model: &str, messages: &[ChatCompletionMessage]| response_cache_key(model, messages).unwrap();
+        let base = key("m", &[message(Role::User, "How do I  reset my password?")]);
+        assert_eq!(base, key("m", &[message(Role::User, " How do I reset\nmy password? ")]));
+        assert_ne!(base, key("other", &[message(Role::User, "How do I reset my password?")]));
+        assert_ne!(base, key("m", &[message(Role::System, "How do I reset my password?")]));
+        assert_ne!(base, key("m", &[message(Role::System, "Be brief."), message(Role::User, "How do I reset my password?")]));
+    }
 }
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct OpenRouterCreateChatCompletionResponse {
-    pub id: String,
-    pub model: String,
-    pub created: u32,
-    pub object: String,
-    pub choices: Vec<OpenRouterChatChoice>,
-    pub usage: Option<OpenRouterCompletionUsage>,
-}
\ No newline at end of file
@@ -0,0 +1,108 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use mongodb::bson::oid::ObjectId;
+use crate::error::{Code, Error};
+use crate::model::{DailyUsage, DEFAULT_ORGANIZATION_ID, Organization};
+use crate::providers::analytics::AnalyticsProvider;
+use crate::store::api_client::ApiClients;
+use crate::store::cache::Caches;
+use crate::store::repository::Repositories;
+use crate::store::Store;
+
+pub struct OrganizationProvider {
+    store: Store,
+    repo: Repositories,
+    cache: Caches,
+    api: ApiClients,
+}
+
+
+impl OrganizationProvider {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store: store.clone(),
+            repo: store.repositories.clone(),
+            cache: store.caches.clone(),
+            api: store.api_clients.clone(),
+        }
+    }
+}
+
+/// 组织在一段时间内的用量
+#[derive(Debug, Clone, Default)]
+pub struct OrganizationUsage {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub active_user_count: u64,
+    pub user_message_count: u64,
+    pub ai_message_count: u64,
+    pub daily: Vec<DailyUsage>,
+}
+
+impl OrganizationProvider {
+    /// 创建默认组织, 并把多租户之前没有 org_id 的用户和消息归入默认组织
+    pub async fn ensure_default_organization(&self) -> Result<(), Error> {
+        let org = Organization {
+            id: DEFAULT_ORGANIZATION_ID.to_string(),
+            name: "default".to_string(),
+            daily_message_limit: None,
+            burst_message_limit: None,
+            message_retention_days: None,
+            created_at: Utc::now().naive_utc(),
+            updated_at: None,
+        };
+        self.repo.organization.insert_if_absent(org).await?;
+        let count = self.repo.user.backfill_org_id(DEFAULT_ORGANIZATION_ID.to_string()).await?;
+        debug!("backfill user.org_id: {}", count);
+        let count = self.repo.message.backfill_org_id(DEFAULT_ORGANIZATION_ID.to_string()).await?;
+        debug!("backfill message.org_id: {}", count);
+        Ok(())
+    }
+
+    pub async fn get_organization(&self, org_id: String) -> Result<Option<Organization>, Error> {
+        self.repo.organization.find_by_id(org_id).await
+    }
+
+    /// 创建组织, 组织名已存在时返回 Code::OrganizationAlreadyExists
+    pub async fn create_organization(&self, name: String, daily_message_limit: Option<i64>, burst_message_limit: Option<i64>) -> Result<Organization, Error> {
+        let org = Organization {
+            id: ObjectId::new().to_hex(),
+            name,
+            daily_message_limit,
+            burst_message_limit,
+            message_retention_days: None,
+            created_at: Utc::now().naive_utc(),
+            updated_at: None,
+        };
+        self.repo.organization.insert(org).await
+    }
+
+    pub async fn list_organizations(&self) -> Result<Vec<Organization>, Error> {
+        self.repo.organization.list().await
+    }
+
+    pub async fn update_organization_quota(&self, org_id: String, daily_message_limit: Option<i64>, burst_message_limit: Option<i64>) -> Result<Organization, Error> {
+        self.repo.organization.update_quota(org_id, daily_message_limit, burst_message_limit).await?
+            .ok_or(Error::Feedback(Code::OrganizationNotFound))
+    }
+
+    /// None 表示使用服务端默认值, 0 表示永久保留
+    pub async fn update_organization_retention(&self, org_id: String, message_retention_days: Option<i64>) -> Result<Organization, Error> {
+        self.repo.organization.update_retention(org_id, message_retention_days).await?
+            .ok_or(Error::Feedback(Code::OrganizationNotFound))
+    }
+
+    /// 统计组织在 [start, end) 内的消息, 日期按服务端默认时区划分, 默认为最近 30 天
+    pub async fn get_organization_usage(&self, org_id: String, start: Option<NaiveDate>, end: Option<NaiveDate>) -> Result<OrganizationUsage, Error> {
+        let analytics = AnalyticsProvider::new(self.store.clone());
+        let range = analytics.date_range(start, end)?;
+        let usage = analytics.get_daily_usage(org_id, range).await?;
+        Ok(OrganizationUsage {
+            start: range.start,
+            end: range.end,
+            active_user_count: usage.active_user_count,
+            user_message_count: usage.user_message_count,
+            ai_message_count: usage.ai_message_count,
+            daily: usage.daily,
+        })
+    }
+}
@@ -0,0 +1,52 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use crate::error::{Code, Error};
+use crate::model::{OrganizationId, Persona};
+use crate::store::repository::Repositories;
+use crate::store::Store;
+
+pub struct PersonaProvider {
+    repo: Repositories,
+}
+
+impl PersonaProvider {
+    pub fn new(store: Store) -> Self {
+        Self {
+            repo: store.repositories.clone(),
+        }
+    }
+}
+
+impl PersonaProvider {
+    pub async fn get_persona(&self, org_id: OrganizationId, name: String) -> Result<Option<Persona>, Error> {
+        self.repo.persona.find_in_org_by_name(org_id, name).await
+    }
+
+    pub async fn list_personas(&self, org_id: OrganizationId) -> Result<Vec<Persona>, Error> {
+        self.repo.persona.list(org_id).await
+    }
+
+    /// 组织内已有同名角色时返回 Code::PersonaAlreadyExists
+    pub async fn create_persona(&self, org_id: OrganizationId, name: String, prompt: String) -> Result<Persona, Error> {
+        let persona = Persona {
+            id: ObjectId::new().to_hex(),
+            org_id,
+            name,
+            prompt,
+            created_at: Utc::now().naive_utc(),
+            updated_at: None,
+        };
+        self.repo.persona.insert(persona).await
+    }
+
+    pub async fn update_persona_prompt(&self, org_id: OrganizationId, persona_id: String, prompt: String) -> Result<Persona, Error> {
+        self.repo.persona.update_prompt(org_id, persona_id, prompt).await?
+            .ok_or(Error::Feedback(Code::PersonaNotFound))
+    }
+
+    /// 选择了该角色的用户在对话时不再使用角色设定
+    pub async fn delete_persona(&self, org_id: OrganizationId, persona_id: String) -> Result<Persona, Error> {
+        self.repo.persona.delete(org_id, persona_id).await?
+            .ok_or(Error::Feedback(Code::PersonaNotFound))
+    }
+}
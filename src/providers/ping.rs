@@ -1,14 +1,16 @@
-use sqlx::Connection;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use log::warn;
 use crate::error::Error;
-
+use crate::model::DependencyStatus;
 use crate::store::api_client::ApiClients;
 use crate::store::cache::Caches;
-use crate::store::database::Databases;
+use crate::store::repository::Repositories;
 use crate::store::Store;
 
 pub struct PingProvider {
     store: Store,
-    db: Databases,
+    repo: Repositories,
     cache: Caches,
     api: ApiClients,
 }
@@ -17,7 +19,7 @@ impl PingProvider {
     pub fn new(store: Store) -> Self {
         Self {
             store: store.clone(),
-            db: store.databases.clone(),
+            repo: store.repositories.clone(),
             cache: store.caches.clone(),
             api: store.api_clients.clone(),
         }
@@ -25,22 +27,53 @@ impl PingProvider {
 }
 
 impl PingProvider {
-    pub async fn ping_mysql(&self) -> Result<String, Error> {
-        // let mut conn = self.db.user();
-        // conn.ping().await?;
-        Ok("pong".to_string())
+    /// MongoDB 的 ping 命令, 或 postgres 的 SELECT 1
+    pub async fn ping_database(&self) -> Result<(), Error> {
+        self.repo.health.ping().await
+    }
+
+    /// Redis 的 PING, 未配置 Redis 时总是可用
+    pub async fn ping_cache(&self) -> Result<(), Error> {
+        self.cache.ping().await
     }
 
-    pub async fn ping_pgsql(&self) -> Result<String, Error> {
-        // let mut conn = self.db.default.acquire().await?;
-        // conn.ping().await?;
-        Ok("pong".to_string())
+    pub async fn ping_upstream(&self) -> Result<(), Error> {
+        self.api.chat.ping().await
     }
 
-    pub async fn ping_redis(&self) -> Result<String, Error> {
-        // let mut conn = self.cache.default.clone();
-        // let reply: RedisResult<String> = redis::cmd("PING").query_async(&mut conn).await;
-        // assert_eq!("PONG", reply.unwrap());
-        Ok("pong".to_string())
+    /// 并发检查各项依赖, 每项最多等待 HEALTH_CHECK_TIMEOUT_SECS 秒
+    pub async fn check_dependencies(&self) -> Vec<DependencyStatus> {
+        let timeout = Duration::from_secs(self.store.config.health_check_timeout_secs);
+        let database = check("database", self.store.config.database_backend.as_str(), timeout, self.ping_database());
+        let cache = check("cache", self.cache.backend_name(), timeout, self.ping_cache());
+        let (database, cache) = futures::join!(database, cache);
+        let mut checks = vec![database, cache];
+        if self.store.config.readiness_check_upstream {
+            checks.push(check("upstream", "openrouter", timeout, self.ping_upstream()).await);
+        }
+        checks
+    }
+}
+
+async fn check(name: &str, backend: &str, timeout: Duration, ping: impl Future<Output = Result<(), Error>>) -> DependencyStatus {
+    let started_at = Instant::now();
+    // 就绪探针无需登录, 驱动返回的原始错误可能带有主机名等内部信息, 只记录在日志中
+    let error = match tokio::time::timeout(timeout, ping).await {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => {
+            warn!("readiness check {} ({}) failed: {}", name, backend, err);
+            Some("不可用".to_string())
+        }
+        Err(_) => {
+            warn!("readiness check {} ({}) timed out after {:?}", name, backend, timeout);
+            Some("超时".to_string())
+        }
+    };
+    DependencyStatus {
+        name: name.to_string(),
+        backend: backend.to_string(),
+        ok: error.is_none(),
+        latency_ms: started_at.elapsed().as_millis() as u64,
+        error,
     }
 }
@@ -0,0 +1,354 @@
+use std::time::Duration;
+use mongodb::bson::DateTime as BsonDateTime;
+use mongodb::bson::oid::ObjectId;
+use crate::metrics;
+use crate::error::Error;
+use crate::model::{Organization, parse_oid, QuotaDoc, User};
+use crate::providers::organization::OrganizationProvider;
+use crate::store::api_client::ApiClients;
+use crate::store::cache::Caches;
+use crate::store::repository::{QuotaStore, Repositories};
+use crate::store::Store;
+
+/// 乐观锁冲突时的最大重试次数
+const MAX_RETRIES: usize = 64;
+
+pub struct QuotaProvider {
+    store: Store,
+    repo: Repositories,
+    cache: Caches,
+    api: ApiClients,
+}
+
+
+impl QuotaProvider {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store: store.clone(),
+            repo: store.repositories.clone(),
+            cache: store.caches.clone(),
+            api: store.api_clients.clone(),
+        }
+    }
+}
+
+impl QuotaProvider {
+    /// 组织设置了额度时使用组织的额度, 否则使用服务端默认值
+    pub fn limits(&self, org: Option<Organization>) -> QuotaLimits {
+        let org = org.as_ref();
+        QuotaLimits {
+            daily: org.and_then(|org| org.daily_message_limit)
+                .unwrap_or(self.store.config.daily_message_limit),
+            burst: org.and_then(|org| org.burst_message_limit)
+                .map(|limit| limit.max(0) as usize)
+                .unwrap_or(self.store.config.burst_message_limit),
+            burst_window: Duration::from_secs(self.store.config.burst_window_secs),
+        }
+    }
+
+    /// 为用户预留一条消息额度, 额度不足时返回 None
+    pub async fn reserve(&self, user: User, day: String) -> Result<Option<QuotaReservation>, Error> {
+        let user_id = parse_oid(user.id.as_str())?;
+        let org = OrganizationProvider::new(self.store.clone()).get_organization(user.org_id.clone()).await?;
+        let reservation = reserve_in(self.repo.quota.as_ref(), user_id, day, BsonDateTime::now(), self.limits(org)).await?;
+        if reservation.is_none() {
+            metrics::inc(&metrics::RATE_LIMIT_REJECTIONS_TOTAL, &[], 1.0);
+        }
+        Ok(reservation)
+    }
+
+    /// 上游调用失败时释放预留的额度
+    pub async fn release(&self, reservation: QuotaReservation) -> Result<(), Error> {
+        release_in(self.repo.quota.as_ref(), reservation).await
+    }
+
+    /// 消息保存成功后提交预留的额度
+    pub async fn commit(&self, reservation: QuotaReservation) -> Result<(), Error> {
+        commit_in(self.repo.quota.as_ref(), reservation).await
+    }
+
+    /// 用户当天已使用的额度, 不含尚未提交的预留
+    pub async fn get_used_today(&self, user: User, day: String) -> Result<u64, Error> {
+        let user_id = parse_oid(user.id.as_str())?;
+        let quota = self.repo.quota.load(user_id).await?;
+        Ok(quota.map(|quota| quota.used(day.as_str())).unwrap_or_default())
+    }
+
+    pub async fn delete_user_quota(&self, user: User) -> Result<(), Error> {
+        let user_id = parse_oid(user.id.as_str())?;
+        self.repo.quota.delete(user_id).await
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaLimits {
+    pub daily: i64,
+    pub burst: usize,
+    pub burst_window: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaReservation {
+    pub user_id: ObjectId,
+    pub day: String,
+    pub reserved_at: BsonDateTime,
+}
+
+impl QuotaDoc {
+    fn empty(user_id: ObjectId, day: String) -> Self {
+        Self {
+            _id: user_id,
+            day,
+            day_count: 0,
+            recent: vec![],
+            pending: 0,
+            version: 0,
+        }
+    }
+
+    /// 日期只向后滚动: 早于已记录日期的请求(如切换到更靠西的时区)仍计入已记录的日期
+    fn used(&self, day: &str) -> u64 {
+        if day <= self.day.as_str() {
+            (self.day_count - self.pending).max(0) as u64
+        } else {
+            0
+        }
+    }
+
+    fn try_reserve(&mut self, day: &str, now: BsonDateTime, limits: QuotaLimits) -> bool {
+        if day > self.day.as_str() {
+            // 跨日前预留的额度属于前一天, 之后提交或释放时不再影响新的一天
+            self.day = day.to_string();
+            self.day_count = 0;
+            self.pending = 0;
+        }
+        let window_start = now.timestamp_millis() - limits.burst_window.as_millis() as i64;
+        self.recent.retain(|reserved_at| reserved_at.timestamp_millis() >= window_start);
+        if self.day_count >= limits.daily || self.recent.len() >= limits.burst {
+            return false;
+        }
+        self.day_count += 1;
+        self.pending += 1;
+        self.recent.push(now);
+        self.version += 1;
+        true
+    }
+
+    fn release(&mut self, reservation: &QuotaReservation) {
+        if self.day == reservation.day {
+            self.day_count = (self.day_count - 1).max(0);
+            self.pending = (self.pending - 1).max(0);
+        }
+        if let Some(index) = self.recent.iter().position(|reserved_at| *reserved_at == reservation.reserved_at) {
+            self.recent.remove(index);
+        }
+        self.version += 1;
+    }
+
+    fn commit(&mut self, reservation: &QuotaReservation) {
+        if self.day == reservation.day {
+            self.pending = (self.pending - 1).max(0);
+        }
+        self.version += 1;
+    }
+}
+
+pub async fn reserve_in<S: QuotaStore + ?Sized>(store: &S, user_id: ObjectId, day: String, now: BsonDateTime, limits: QuotaLimits) -> Result<Option<QuotaReservation>, Error> {
+    for _ in 0..MAX_RETRIES {
+        let current = store.load(user_id).await?;
+        let (reserved, reserved_day) = if let Some(current) = current {
+            let mut quota = current.clone();
+            if !quota.try_reserve(day.as_str(), now, limits) {
+                return Ok(None);
+            }
+            let reserved_day = quota.day.clone();
+            (store.swap(current.version, quota).await?, reserved_day)
+        } else {
+            let mut quota = QuotaDoc::empty(user_id, day.clone());
+            if !quota.try_reserve(day.as_str(), now, limits) {
+                return Ok(None);
+            }
+            (store.insert(quota).await?, day.clone())
+        };
+        if reserved {
+            // 预留计入的日期, 可能晚于传入的日期
+            return Ok(Some(QuotaReservation {
+                user_id,
+                day: reserved_day,
+                reserved_at: now,
+            }));
+        }
+    }
+    Err(Error::ServerError(format!("预留额度冲突次数过多: {}", user_id)))
+}
+
+pub async fn release_in<S: QuotaStore + ?Sized>(store: &S, reservation: QuotaReservation) -> Result<(), Error> {
+    update_in(store, reservation.user_id, |quota| quota.release(&reservation)).await
+}
+
+pub async fn commit_in<S: QuotaStore + ?Sized>(store: &S, reservation: QuotaReservation) -> Result<(), Error> {
+    update_in(store, reservation.user_id, |quota| quota.commit(&reservation)).await
+}
+
+async fn update_in<S, F>(store: &S, user_id: ObjectId, update: F) -> Result<(), Error>
+where
+    S: QuotaStore + ?Sized,
+    F: Fn(&mut QuotaDoc),
+{
+    for _ in 0..MAX_RETRIES {
+        let Some(current) = store.load(user_id).await? else {
+            return Ok(());
+        };
+        let mut quota = current.clone();
+        update(&mut quota);
+        if store.swap(current.version, quota).await? {
+            return Ok(());
+        }
+    }
+    Err(Error::ServerError(format!("更新额度冲突次数过多: {}", user_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::store::repository::memory::MemoryQuotas;
+    use super::*;
+
+    const DAY: &str = "2024-01-01";
+
+    fn limits(daily: i64, burst: usize) -> QuotaLimits {
+        QuotaLimits {
+            daily,
+            burst,
+            burst_window: Duration::from_secs(30),
+        }
+    }
+
+    async fn reserve_concurrently(store: Arc<MemoryQuotas>, user_id: ObjectId, requests: usize, limits: QuotaLimits) -> Vec<QuotaReservation> {
+        let mut handles = vec![];
+        for i in 0..requests {
+            let store = store.clone();
+            // 每个请求的时间点不同, 避免相同时间点互相覆盖
+            let now = BsonDateTime::from_millis(1_700_000_000_000 + i as i64);
+            handles.push(tokio::spawn(async move {
+                reserve_in(store.as_ref(), user_id, DAY.to_string(), now, limits).await
+            }));
+        }
+        let mut reservations = vec![];
+        for handle in handles {
+            if let Some(reservation) = handle.await.unwrap().unwrap() {
+                reservations.push(reservation);
+            }
+        }
+        reservations
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_requests_do_not_exceed_burst_limit() {
+        let store = Arc::new(MemoryQuotas::default());
+        let user_id = ObjectId::new();
+        let reservations = reserve_concurrently(store.clone(), user_id, 32, limits(20, 3)).await;
+        assert_eq!(reservations.len(), 3);
+        let quota = store.load(user_id).await.unwrap().unwrap();
+        assert_eq!(quota.day_count, 3);
+        assert_eq!(quota.pending, 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_requests_do_not_exceed_daily_limit() {
+        let store = Arc::new(MemoryQuotas::default());
+        let user_id = ObjectId::new();
+        let reservations = reserve_concurrently(store.clone(), user_id, 64, limits(20, 100)).await;
+        assert_eq!(reservations.len(), 20);
+        let quota = store.load(user_id).await.unwrap().unwrap();
+        assert_eq!(quota.day_count, 20);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn released_reservation_frees_a_slot() {
+        let store = Arc::new(MemoryQuotas::default());
+        let user_id = ObjectId::new();
+        let limits = limits(20, 3);
+        let mut reservations = reserve_concurrently(store.clone(), user_id, 8, limits).await;
+        assert_eq!(reservations.len(), 3);
+
+        release_in(store.as_ref(), reservations.pop().unwrap()).await.unwrap();
+        for reservation in reservations {
+            commit_in(store.as_ref(), reservation).await.unwrap();
+        }
+        let quota = store.load(user_id).await.unwrap().unwrap();
+        assert_eq!(quota.day_count, 2);
+        assert_eq!(quota.pending, 0);
+
+        let now = BsonDateTime::from_millis(1_700_000_001_000);
+        let reservation = reserve_in(store.as_ref(), user_id, DAY.to_string(), now, limits).await.unwrap();
+        assert!(reservation.is_some());
+        let reservation = reserve_in(store.as_ref(), user_id, DAY.to_string(), now, limits).await.unwrap();
+        assert!(reservation.is_none());
+    }
+
+    #[tokio::test]
+    async fn burst_window_and_day_reset() {
+        let store = MemoryQuotas::default();
+        let user_id = ObjectId::new();
+        let limits = limits(3, 3);
+        let start = 1_700_000_000_000;
+        for i in 0..3 {
+            let now = BsonDateTime::from_millis(start + i);
+            assert!(reserve_in(&store, user_id, DAY.to_string(), now, limits).await.unwrap().is_some());
+        }
+        // 窗口过后仍受每日额度限制
+        let now = BsonDateTime::from_millis(start + 31_000);
+        assert!(reserve_in(&store, user_id, DAY.to_string(), now, limits).await.unwrap().is_none());
+        // 第二天额度重置
+        let reservation = reserve_in(&store, user_id, "2024-01-02".to_string(), now, limits).await.unwrap();
+        assert!(reservation.is_some());
+        let quota = store.load(user_id).await.unwrap().unwrap();
+        assert_eq!(quota.day, "2024-01-02");
+        assert_eq!(quota.day_count, 1);
+        assert_eq!(quota.recent.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn earlier_day_does_not_reset_daily_limit() {
+        let store = MemoryQuotas::default();
+        let user_id = ObjectId::new();
+        let limits = limits(2, 100);
+        let now = BsonDateTime::from_millis(1_700_000_000_000);
+        for day in ["2024-01-02", "2024-01-01"] {
+            let reservation = reserve_in(&store, user_id, day.to_string(), now, limits).await.unwrap().unwrap();
+            assert_eq!(reservation.day, "2024-01-02");
+        }
+        // 在两个时区之间来回切换不会重置额度
+        for day in ["2024-01-01", "2024-01-02", "2024-01-01"] {
+            assert!(reserve_in(&store, user_id, day.to_string(), now, limits).await.unwrap().is_none());
+        }
+        let quota = store.load(user_id).await.unwrap().unwrap();
+        assert_eq!(quota.day, "2024-01-02");
+        assert_eq!(quota.day_count, 2);
+    }
+
+    #[tokio::test]
+    async fn in_flight_reservations_across_midnight() {
+        let store = MemoryQuotas::default();
+        let user_id = ObjectId::new();
+        let limits = limits(20, 100);
+        let now = BsonDateTime::from_millis(1_700_000_000_000);
+        let committed = reserve_in(&store, user_id, DAY.to_string(), now, limits).await.unwrap().unwrap();
+        let released = reserve_in(&store, user_id, DAY.to_string(), now, limits).await.unwrap().unwrap();
+
+        let next_day = "2024-01-02";
+        let reservation = reserve_in(&store, user_id, next_day.to_string(), now, limits).await.unwrap().unwrap();
+        commit_in(&store, committed).await.unwrap();
+        release_in(&store, released).await.unwrap();
+        let quota = store.load(user_id).await.unwrap().unwrap();
+        assert_eq!((quota.day_count, quota.pending), (1, 1));
+        assert_eq!(quota.used(next_day), 0);
+
+        commit_in(&store, reservation).await.unwrap();
+        let quota = store.load(user_id).await.unwrap().unwrap();
+        assert_eq!((quota.day_count, quota.pending), (1, 0));
+        assert_eq!(quota.used(next_day), 1);
+        assert_eq!(quota.used(DAY), 1);
+    }
+}
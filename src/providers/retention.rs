@@ -0,0 +1,181 @@
+use chrono::{DateTime, Duration, Utc};
+use crate::error::Error;
+use crate::model::{MAX_RETENTION_DAYS, Organization, User};
+use crate::providers::user::UserProvider;
+use crate::store::api_client::ApiClients;
+use crate::store::cache::Caches;
+use crate::store::repository::Repositories;
+use crate::store::Store;
+
+pub struct RetentionProvider {
+    store: Store,
+    repo: Repositories,
+    cache: Caches,
+    api: ApiClients,
+}
+
+
+impl RetentionProvider {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store: store.clone(),
+            repo: store.repositories.clone(),
+            cache: store.caches.clone(),
+            api: store.api_clients.clone(),
+        }
+    }
+}
+
+/// 早于该时间的消息已过期; 超过 MAX_RETENTION_DAYS (如限制前保存的值) 时为 None, 按永久保留处理
+fn retention_cutoff(now: DateTime<Utc>, retention_days: i64) -> Option<DateTime<Utc>> {
+    if retention_days > MAX_RETENTION_DAYS {
+        return None;
+    }
+    now.checked_sub_signed(Duration::days(retention_days))
+}
+
+/// 用户过期的消息
+#[derive(Debug, Clone)]
+pub struct ExpiredMessages {
+    pub user: User,
+    pub retention_days: i64,
+    /// 早于该时间的消息已过期
+    pub cutoff: DateTime<Utc>,
+    pub count: u64,
+}
+
+impl RetentionProvider {
+    /// 组织生效的保留天数, 未设置时使用服务端默认值, None 表示永久保留
+    pub fn organization_retention_days(&self, org: &Organization) -> Option<i64> {
+        let days = org.message_retention_days.unwrap_or(self.store.config.message_retention_days);
+        Some(days).filter(|days| *days > 0)
+    }
+
+    /// 用户生效的保留天数, 依次使用用户, 组织和服务端的设置, None 表示永久保留
+    pub fn user_retention_days(&self, org: &Organization, user: &User) -> Option<i64> {
+        match user.message_retention_days {
+            Some(days) => Some(days).filter(|days| *days > 0),
+            None => self.organization_retention_days(org),
+        }
+    }
+
+    /// 统计组织内各用户已过期但尚未清理的消息, 不删除任何数据
+    pub async fn get_expired_messages(&self, org: Organization, now: DateTime<Utc>) -> Result<Vec<ExpiredMessages>, Error> {
+        let mut expired = vec![];
+        for user in UserProvider::new(self.store.clone()).list_all_org_users(org.id.clone()).await? {
+            let Some(retention_days) = self.user_retention_days(&org, &user) else {
+                continue;
+            };
+            let Some(cutoff) = retention_cutoff(now, retention_days) else {
+                continue;
+            };
+            let count = self.repo.message.count_before(user.clone(), cutoff).await?;
+            if count > 0 {
+                expired.push(ExpiredMessages { user, retention_days, cutoff, count });
+            }
+        }
+        Ok(expired)
+    }
+
+    /// 删除组织内已过期的消息, 返回删除的消息数
+    pub async fn purge_organization(&self, org: Organization, now: DateTime<Utc>) -> Result<u64, Error> {
+        let mut deleted = 0;
+        for user in UserProvider::new(self.store.clone()).list_all_org_users(org.id.clone()).await? {
+            let Some(retention_days) = self.user_retention_days(&org, &user) else {
+                continue;
+            };
+            let Some(cutoff) = retention_cutoff(now, retention_days) else {
+                continue;
+            };
+            deleted += self.repo.message.delete_before(user, cutoff).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// 删除所有组织已过期的消息, 由定时任务调用
+    pub async fn purge_expired_messages(&self) -> Result<u64, Error> {
+        let now = Utc::now();
+        let mut deleted = 0;
+        for org in self.repo.organization.list().await? {
+            deleted += self.purge_organization(org, now).await?;
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+    use crate::conf::Config;
+    use crate::model::{DEFAULT_ORGANIZATION_ID, Message, MessageRoleType, Role};
+    use crate::providers::Providers;
+    use super::*;
+
+    async fn setup(message_retention_days: i64) -> Providers {
+        let store = Store::memory_for_test(Config {
+            message_retention_days,
+            ..Default::default()
+        }).await;
+        Providers::new(&store)
+    }
+
+    async fn insert_messages(pvd: &Providers, user: &User, ages_in_days: &[i64]) {
+        let now = Utc::now();
+        let messages = ages_in_days.iter().map(|days| Message {
+            id: ObjectId::new().to_hex(),
+            org_id: user.org_id.clone(),
+            user_id: user.id.clone(),
+            type_: MessageRoleType::User,
+            text: format!("{} days ago", days),
+            created_at: (now - Duration::days(*days)).naive_utc(),
+            created_by: user.id.clone(),
+            updated_at: None,
+            updated_by: None,
+            model: None,
+            usage: None,
+            latency_ms: None,
+        }).collect();
+        pvd.retention().repo.message.insert_many(messages).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn user_setting_overrides_organization_and_default() {
+        let pvd = setup(90).await;
+        let org_id = DEFAULT_ORGANIZATION_ID.to_string();
+        let alice = pvd.user().create_user(org_id.clone(), "alice".to_string(), Role::User).await.unwrap();
+        let bob = pvd.user().create_user(org_id.clone(), "bob".to_string(), Role::User).await.unwrap();
+        let bob = pvd.user().set_user_message_retention(bob, Some(0)).await.unwrap();
+        insert_messages(&pvd, &alice, &[1, 30, 100, 200]).await;
+        insert_messages(&pvd, &bob, &[1, 100, 200]).await;
+
+        let retention = pvd.retention();
+        let org = pvd.organization().get_organization(org_id.clone()).await.unwrap().unwrap();
+        let expired = retention.get_expired_messages(org.clone(), Utc::now()).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].user.id, alice.id);
+        assert_eq!(expired[0].retention_days, 90);
+        assert_eq!(expired[0].count, 2);
+
+        let org = pvd.organization().update_organization_retention(org_id, Some(20)).await.unwrap();
+        let expired = retention.get_expired_messages(org, Utc::now()).await.unwrap();
+        assert_eq!(expired[0].count, 3);
+
+        assert_eq!(retention.purge_expired_messages().await.unwrap(), 3);
+        assert_eq!(retention.repo.message.count(alice, None, None).await.unwrap(), 1);
+        assert_eq!(retention.repo.message.count(bob, None, None).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn out_of_range_retention_keeps_messages_without_panicking() {
+        let pvd = setup(0).await;
+        let org_id = DEFAULT_ORGANIZATION_ID.to_string();
+        let alice = pvd.user().create_user(org_id.clone(), "alice".to_string(), Role::User).await.unwrap();
+        let alice = pvd.user().set_user_message_retention(alice, Some(100_000_000)).await.unwrap();
+        insert_messages(&pvd, &alice, &[1, 100]).await;
+        let retention = pvd.retention();
+        let org = pvd.organization().get_organization(org_id).await.unwrap().unwrap();
+        assert!(retention.get_expired_messages(org, Utc::now()).await.unwrap().is_empty());
+        assert_eq!(retention.purge_expired_messages().await.unwrap(), 0);
+        assert_eq!(retention_cutoff(Utc::now(), MAX_RETENTION_DAYS).map(|cutoff| cutoff < Utc::now()), Some(true));
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use anyhow::Context;
+use calamine::{DataType, Reader, Xlsx};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use crate::error::Error;
+use crate::model::{ImportMessagesOutput, ImportRowError, ImportUserSummary, Message, MessageRoleType, TranscriptFormat, TranscriptRecord, User};
+use crate::providers::user::UserProvider;
+use crate::store::api_client::ApiClients;
+use crate::store::cache::Caches;
+use crate::store::repository::{Direction, Repositories};
+use crate::store::Store;
+
+pub struct TranscriptProvider {
+    store: Store,
+    repo: Repositories,
+    cache: Caches,
+    api: ApiClients,
+}
+
+
+impl TranscriptProvider {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store: store.clone(),
+            repo: store.repositories.clone(),
+            cache: store.caches.clone(),
+            api: store.api_clients.clone(),
+        }
+    }
+}
+
+/// 导出时每次读取的消息数
+const EXPORT_PAGE_SIZE: u64 = 500;
+/// 导入时每次写入的消息数
+const IMPORT_BATCH_SIZE: usize = 500;
+/// 导入报告中最多列出的错误数
+const MAX_REPORTED_ERRORS: usize = 100;
+/// csv 和 xlsx 的表头
+const TRANSCRIPT_COLUMNS: [&str; 5] = ["user_name", "type", "text", "created_at", "model"];
+
+/// 带行号的一条导入记录, 解析失败时为错误信息
+pub type TranscriptRow = (u64, Result<TranscriptRecord, String>);
+
+impl TranscriptProvider {
+    /// 按时间顺序读取用户的全部消息
+    async fn get_all_user_messages(&self, user: User) -> Result<Vec<Message>, Error> {
+        let mut messages: Vec<Message> = vec![];
+        loop {
+            let page = self.repo.message.find_page(user.clone(), messages.last().cloned(), Direction::Newer, EXPORT_PAGE_SIZE).await?;
+            let done = (page.len() as u64) < EXPORT_PAGE_SIZE;
+            messages.extend(page);
+            if done {
+                break;
+            }
+        }
+        Ok(messages)
+    }
+
+    pub async fn export_messages(&self, users: Vec<User>, format: TranscriptFormat) -> Result<String, Error> {
+        let mut records = vec![];
+        for user in users {
+            for msg in self.get_all_user_messages(user.clone()).await? {
+                records.push(TranscriptRecord {
+                    user_name: user.name.clone(),
+                    type_: msg.type_,
+                    text: msg.text,
+                    created_at: Some(msg.created_at),
+                    model: msg.model,
+                });
+            }
+        }
+        render_transcript(format, &records)
+    }
+
+    /// 解析导入文件, 单行的错误放在对应的行中, 整个文件无法读取时返回 ParamsError
+    pub fn parse_transcript(&self, format: TranscriptFormat, data: &[u8]) -> Result<Vec<TranscriptRow>, Error> {
+        match format {
+            TranscriptFormat::Jsonl => parse_jsonl(data),
+            TranscriptFormat::Csv => parse_csv(data),
+            TranscriptFormat::Xlsx => parse_xlsx(data),
+            TranscriptFormat::Markdown => Err(Error::ParamsError("markdown 只支持导出".to_string())),
+        }
+    }
+
+    /// 导入到 org_id, 不存在的用户创建在该组织; 任何一行有错误或 dry_run 时只返回报告, 不写入
+    pub async fn import_messages(&self, org_id: String, rows: Vec<TranscriptRow>, dry_run: bool) -> Result<ImportMessagesOutput, Error> {
+        let row_count = rows.len() as u64;
+        let mut errors = vec![];
+        let mut records: BTreeMap<String, Vec<(u64, TranscriptRecord)>> = BTreeMap::new();
+        for (row, record) in rows {
+            match record.and_then(validate_record) {
+                Ok(record) => records.entry(record.user_name.clone()).or_default().push((row, record)),
+                Err(message) => errors.push(ImportRowError { row, message }),
+            }
+        }
+        let mut users = vec![];
+        let mut existing = HashMap::new();
+        for (user_name, items) in records.iter() {
+            let user = self.repo.user.find_in_org_by_name(org_id.clone(), user_name.clone()).await?;
+            users.push(ImportUserSummary {
+                user_name: user_name.clone(),
+                new_user: user.is_none(),
+                message_count: items.len() as u64,
+            });
+            existing.insert(user_name.clone(), user);
+        }
+        errors.sort_by_key(|err| err.row);
+        let imported = !dry_run && errors.is_empty();
+        if imported {
+            for (user_name, items) in records {
+                let user = match existing.remove(&user_name).flatten() {
+                    Some(user) => user,
+                    None => UserProvider::new(self.store.clone()).get_or_create_user_by_name(org_id.clone(), user_name).await?,
+                };
+                let now = Utc::now().naive_utc();
+                let messages = items.into_iter()
+                    .map(|(_, record)| Message {
+                        id: ObjectId::new().to_hex(),
+                        org_id: user.org_id.clone(),
+                        user_id: user.id.clone(),
+                        type_: record.type_,
+                        text: record.text,
+                        created_at: record.created_at.unwrap_or(now),
+                        created_by: user.id.clone(),
+                        updated_at: None,
+                        updated_by: None,
+                        model: record.model,
+                        usage: None,
+                        latency_ms: None,
+                    })
+                    .collect::<Vec<Message>>();
+                for batch in messages.chunks(IMPORT_BATCH_SIZE) {
+                    self.repo.message.insert_many(batch.to_vec()).await?;
+                }
+            }
+        }
+        let error_count = errors.len() as u64;
+        errors.truncate(MAX_REPORTED_ERRORS);
+        Ok(ImportMessagesOutput {
+            dry_run,
+            imported,
+            row_count,
+            message_count: users.iter().map(|user| user.message_count).sum(),
+            users,
+            error_count,
+            errors,
+        })
+    }
+}
+
+fn validate_record(record: TranscriptRecord) -> Result<TranscriptRecord, String> {
+    let user_name = User::validate_name(record.user_name.as_str()).map_err(|err| err.to_string())?;
+    if record.text.trim().is_empty() {
+        return Err("text 不能为空".to_string());
+    }
+    Ok(TranscriptRecord {
+        user_name,
+        model: record.model.filter(|model| !model.is_empty()),
+        ..record
+    })
+}
+
+fn render_transcript(format: TranscriptFormat, records: &[TranscriptRecord]) -> Result<String, Error> {
+    match format {
+        TranscriptFormat::Jsonl => {
+            let mut out = String::new();
+            for record in records {
+                out.push_str(serde_json::to_string(record)?.as_str());
+                out.push('\n');
+            }
+            Ok(out)
+        }
+        TranscriptFormat::Csv => {
+            // 没有记录时也输出表头
+            let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+            writer.write_record(TRANSCRIPT_COLUMNS).with_context(|| "write csv header".to_string())?;
+            for record in records {
+                writer.serialize(record).with_context(|| format!("write csv: {:?}", record))?;
+            }
+            let data = writer.into_inner().map_err(|err| anyhow::anyhow!("flush csv: {}", err))?;
+            Ok(String::from_utf8(data).with_context(|| "csv to utf-8".to_string())?)
+        }
+        TranscriptFormat::Markdown => {
+            let mut out = String::new();
+            let mut current_user = None;
+            for record in records {
+                if current_user != Some(record.user_name.as_str()) {
+                    out.push_str(format!("## {}\n\n", record.user_name).as_str());
+                    current_user = Some(record.user_name.as_str());
+                }
+                let created_at = record.created_at
+                    .map(|created_at| format!(" {} UTC", created_at.format("%Y-%m-%d %H:%M:%S")))
+                    .unwrap_or_default();
+                let model = record.model.as_ref().map(|model| format!(" ({})", model)).unwrap_or_default();
+                out.push_str(format!("**{}**{}{}\n\n{}\n\n", record.type_, created_at, model, record.text).as_str());
+            }
+            Ok(out)
+        }
+        TranscriptFormat::Xlsx => Err(Error::ParamsError("xlsx 只支持导入".to_string())),
+    }
+}
+
+fn parse_jsonl(data: &[u8]) -> Result<Vec<TranscriptRow>, Error> {
+    let text = std::str::from_utf8(data).map_err(|_| Error::ParamsError("文件需为 UTF-8 编码".to_string()))?;
+    Ok(text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i as u64 + 1, serde_json::from_str::<TranscriptRecord>(line).map_err(|err| err.to_string())))
+        .collect())
+}
+
+fn parse_csv(data: &[u8]) -> Result<Vec<TranscriptRow>, Error> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+    let headers = reader.headers()
+        .map_err(|err| Error::ParamsError(format!("无法读取 csv 表头: {}", err)))?
+        .clone();
+    let mut rows = vec![];
+    for (i, record) in reader.records().enumerate() {
+        let row = match &record {
+            Ok(record) => record.position().map(|pos| pos.line()).unwrap_or(i as u64 + 2),
+            Err(err) => err.position().map(|pos| pos.line()).unwrap_or(i as u64 + 2),
+        };
+        let record = record
+            .and_then(|record| record.deserialize::<TranscriptRecord>(Some(&headers)))
+            .map_err(|err| err.to_string());
+        rows.push((row, record));
+    }
+    Ok(rows)
+}
+
+/// 只读取第一个工作表, 第一行为表头
+fn parse_xlsx(data: &[u8]) -> Result<Vec<TranscriptRow>, Error> {
+    let mut workbook = Xlsx::new(Cursor::new(data.to_vec()))
+        .map_err(|err| Error::ParamsError(format!("无法读取 xlsx: {}", err)))?;
+    let range = workbook.worksheet_range_at(0)
+        .ok_or(Error::ParamsError("xlsx 没有工作表".to_string()))?
+        .map_err(|err| Error::ParamsError(format!("无法读取 xlsx: {}", err)))?;
+    let mut sheet_rows = range.rows();
+    let Some(header) = sheet_rows.next() else {
+        return Ok(vec![]);
+    };
+    let columns = header.iter()
+        .enumerate()
+        .map(|(i, cell)| (cell.to_string().trim().to_string(), i))
+        .collect::<HashMap<String, usize>>();
+    let mut rows = vec![];
+    for (i, cells) in sheet_rows.enumerate() {
+        if cells.iter().all(|cell| cell.is_empty()) {
+            continue;
+        }
+        let cell = |name: &str| columns.get(name).and_then(|i| cells.get(*i)).unwrap_or(&DataType::Empty);
+        rows.push((i as u64 + 2, parse_xlsx_record(cell)));
+    }
+    Ok(rows)
+}
+
+fn parse_xlsx_record<'a>(cell: impl Fn(&str) -> &'a DataType) -> Result<TranscriptRecord, String> {
+    let text = |name: &str| Some(cell(name).to_string()).filter(|value| !value.is_empty());
+    let type_ = text("type").unwrap_or_default();
+    let created_at = match cell("created_at") {
+        DataType::Empty => None,
+        DataType::DateTime(value) | DataType::Float(value) => Some(excel_datetime(*value)?),
+        value => Some(parse_datetime(value.to_string().as_str())?),
+    };
+    Ok(TranscriptRecord {
+        user_name: text("user_name").unwrap_or_default(),
+        type_: type_.parse::<MessageRoleType>().map_err(|_| format!("type 需为 user 或 ai: {}", type_))?,
+        text: text("text").unwrap_or_default(),
+        created_at,
+        model: text("model"),
+    })
+}
+
+/// Excel 日期序列号的有效范围: 1970-01-01 至 9999-12-31
+const EXCEL_MIN_SERIAL: f64 = 25_569.0;
+const EXCEL_MAX_SERIAL: f64 = 2_958_466.0;
+
+/// Excel 的日期序列号, 以 1899-12-30 为第 0 天, 超出 1970 至 9999 年时报错
+fn excel_datetime(value: f64) -> Result<NaiveDateTime, String> {
+    let out_of_range = || format!("created_at 超出 1970-01-01 至 9999-12-31: {}", value);
+    if !(EXCEL_MIN_SERIAL..EXCEL_MAX_SERIAL).contains(&value) {
+        return Err(out_of_range());
+    }
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30).unwrap_or_default().and_hms_opt(0, 0, 0).unwrap_or_default();
+    epoch.checked_add_signed(Duration::milliseconds((value * 86_400_000.0).round() as i64))
+        .filter(|dt| dt.year() <= 9999)
+        .ok_or_else(out_of_range)
+}
+
+fn parse_datetime(value: &str) -> Result<NaiveDateTime, String> {
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(value.trim(), fmt).ok())
+        .ok_or(format!("created_at 需为 YYYY-MM-DD HH:MM:SS: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::Config;
+    use crate::model::{DEFAULT_ORGANIZATION_ID, Role};
+    use crate::providers::Providers;
+    use super::*;
+
+    fn record(user_name: &str, type_: MessageRoleType, text: &str) -> TranscriptRecord {
+        TranscriptRecord {
+            user_name: user_name.to_string(),
+            type_,
+            text: text.to_string(),
+            created_at: NaiveDateTime::parse_from_str("2024-01-01 08:00:00", "%Y-%m-%d %H:%M:%S").ok(),
+            model: None,
+        }
+    }
+
+    async fn setup() -> Providers {
+        Providers::new(&Store::memory_for_test(Config::default()).await)
+    }
+
+    #[test]
+    fn jsonl_and_csv_round_trip() {
+        let records = vec![
+            record("alice", MessageRoleType::User, "hello, \"world\"\nsecond line"),
+            TranscriptRecord {
+                model: Some("default-model".to_string()),
+                ..record("alice", MessageRoleType::AI, "hi")
+            },
+        ];
+        for format in [TranscriptFormat::Jsonl, TranscriptFormat::Csv] {
+            let data = render_transcript(format, &records).unwrap();
+            let parsed = match format {
+                TranscriptFormat::Jsonl => parse_jsonl(data.as_bytes()).unwrap(),
+                _ => parse_csv(data.as_bytes()).unwrap(),
+            };
+            let parsed = parsed.into_iter().map(|(_, record)| record.unwrap()).collect::<Vec<TranscriptRecord>>();
+            assert_eq!(parsed, records, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn xlsx_serial_dates_out_of_range_are_row_errors() {
+        let parse = |created_at: DataType| {
+            let cells = [DataType::String("alice".to_string()), DataType::String("user".to_string()), DataType::String("hi".to_string()), created_at];
+            parse_xlsx_record(|name| match name {
+                "user_name" => &cells[0],
+                "type" => &cells[1],
+                "text" => &cells[2],
+                "created_at" => &cells[3],
+                _ => &DataType::Empty,
+            })
+        };
+        let record = parse(DataType::Float(45292.5)).unwrap();
+        assert_eq!(record.created_at, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(12, 0, 0));
+        for value in [1e9, -1e9, 100.0, f64::NAN, f64::INFINITY] {
+            let err = parse(DataType::Float(value)).unwrap_err();
+            assert!(err.contains("超出"), "{}: {}", value, err);
+        }
+        assert!(parse(DataType::DateTime(2_958_465.9)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn import_is_all_or_nothing() {
+        let pvd = setup().await;
+        let transcript = pvd.transcript();
+        let org_id = DEFAULT_ORGANIZATION_ID.to_string();
+        pvd.user().create_user(org_id.clone(), "alice".to_string(), Role::User).await.unwrap();
+        let data = "user_name,type,text,created_at,model\n\
+            alice,user,hello,2024-01-01T08:00:00,\n\
+            bob,ai,hi,,default-model\n\
+            bob,robot,hi,,\n\
+            x,user,hi,,\n";
+        let rows = transcript.parse_transcript(TranscriptFormat::Csv, data.as_bytes()).unwrap();
+        let report = transcript.import_messages(org_id.clone(), rows.clone(), false).await.unwrap();
+        assert!(!report.imported);
+        assert_eq!(report.row_count, 4);
+        assert_eq!(report.errors.iter().map(|err| err.row).collect::<Vec<u64>>(), vec![4, 5]);
+        assert!(pvd.user().get_org_user_by_name(DEFAULT_ORGANIZATION_ID.to_string(), "bob".to_string()).await.unwrap().is_none());
+
+        let rows = rows.into_iter().filter(|(row, _)| *row < 4).collect::<Vec<TranscriptRow>>();
+        let report = transcript.import_messages(org_id.clone(), rows.clone(), true).await.unwrap();
+        assert!(!report.imported);
+        assert_eq!(report.message_count, 2);
+        assert_eq!(report.users.iter().map(|user| (user.user_name.as_str(), user.new_user)).collect::<Vec<(&str, bool)>>(),
+                   vec![("alice", false), ("bob", true)]);
+        assert!(pvd.user().get_org_user_by_name(DEFAULT_ORGANIZATION_ID.to_string(), "bob".to_string()).await.unwrap().is_none());
+
+        let report = transcript.import_messages(org_id, rows, false).await.unwrap();
+        assert!(report.imported);
+        let bob = pvd.user().get_org_user_by_name(DEFAULT_ORGANIZATION_ID.to_string(), "bob".to_string()).await.unwrap().unwrap();
+        let exported = transcript.export_messages(vec![bob], TranscriptFormat::Jsonl).await.unwrap();
+        let messages = parse_jsonl(exported.as_bytes()).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1.as_ref().unwrap().model.as_deref(), Some("default-model"));
+    }
+
+    #[tokio::test]
+    async fn import_resolves_users_within_the_organization() {
+        let pvd = setup().await;
+        let transcript = pvd.transcript();
+        let alice = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "alice".to_string(), Role::User).await.unwrap();
+        let org = pvd.organization().create_organization("acme".to_string(), None, None).await.unwrap();
+        let rows = transcript.parse_transcript(TranscriptFormat::Csv, "user_name,type,text,created_at,model\nalice,user,hello,,\n".as_bytes()).unwrap();
+        let report = transcript.import_messages(org.id.clone(), rows, false).await.unwrap();
+        assert!(report.imported);
+        assert!(report.users[0].new_user);
+        let imported = pvd.user().get_org_user_by_name(org.id.clone(), "alice".to_string()).await.unwrap().unwrap();
+        assert_ne!(imported.id, alice.id);
+        assert!(transcript.export_messages(vec![alice], TranscriptFormat::Jsonl).await.unwrap().is_empty());
+    }
+}
@@ -1,17 +1,17 @@
-use anyhow::Context;
-use chrono::{NaiveDateTime, Utc};
-use mongodb::bson::{DateTime, doc};
+use std::time::Duration;
+use chrono::Utc;
+use log::warn;
 use mongodb::bson::oid::ObjectId;
-use crate::error::Error;
-use crate::model::{User, UserDoc};
+use crate::error::{Code, Error};
+use crate::model::{ListUsersInput, Role, User, UserPreferencesPatch, DEFAULT_ORGANIZATION_ID};
 use crate::store::api_client::ApiClients;
 use crate::store::cache::Caches;
-use crate::store::database::Databases;
+use crate::store::repository::Repositories;
 use crate::store::Store;
 
 pub struct UserProvider {
     store: Store,
-    db: Databases,
+    repo: Repositories,
     cache: Caches,
     api: ApiClients,
 }
@@ -21,35 +21,202 @@ impl UserProvider {
     pub fn new(store: Store) -> Self {
         Self {
             store: store.clone(),
-            db: store.databases.clone(),
+            repo: store.repositories.clone(),
             cache: store.caches.clone(),
             api: store.api_clients.clone(),
         }
     }
 }
 
+/// 遍历组织用户时每页的用户数
+const USER_PAGE_SIZE: u64 = 100;
+
 impl UserProvider {
-    pub async fn get_user_by_name(self, user_name: String) -> Result<Option<User>, Error>{
-        let user = self.db.user().find_one(doc! {"name": user_name.clone()}, None).await
-            .with_context(|| format!("find_one by name: {}", user_name))?;
-        if let Some(user) = user {
-            Ok(Some(user.clone().to_entity().with_context(||format!("found user to_entity: {:?}", user))?))
-        } else {
-            let user = UserDoc {
-                _id: ObjectId::new(),
-                name: user_name,
-                created_at: DateTime::now(),
-                updated_at: None,
-            };
-            let res = self.db.user().insert_one(user, None).await
-                .with_context(|| "insert_one".to_string())?;
-            let user = self.db.user().find_one(doc! {"_id": res.inserted_id.clone()}, None).await
-                .with_context(|| format!("find_one by _id {}", res.inserted_id))?;
-            if let Some(user) = user {
-                Ok(Some(user.clone().to_entity().with_context(||format!("new user to_entity: {:?}", user))?))
-            } else {
-                Ok(None)
+    /// 不限定组织, 仅用于认证
+    pub async fn get_user_by_id(&self, user_id: String) -> Result<Option<User>, Error> {
+        self.repo.user.find_by_id(user_id).await
+    }
+
+    pub async fn get_org_user_by_id(&self, org_id: String, user_id: String) -> Result<Option<User>, Error> {
+        self.repo.user.find_in_org_by_id(org_id, user_id).await
+    }
+
+    /// 用户名在组织内唯一. JWT 认证的每个请求都会调用, 缓存中只保存用户名到 id 的映射,
+    /// 用户本身每次按 id 从存储读取, 其他实例对 disabled, role 等的修改立即生效
+    pub async fn get_org_user_by_name(&self, org_id: String, user_name: String) -> Result<Option<User>, Error> {
+        let cache = self.cache.user_id();
+        let key = user_cache_key(org_id.as_str(), user_name.as_str());
+        match cache.get::<String>(key.as_str()).await {
+            Ok(Some(user_id)) => {
+                // 缓存的用户已被删除或改名时按用户名重新查找
+                let user = self.repo.user.find_in_org_by_id(org_id.clone(), user_id).await?
+                    .filter(|user| user.name == user_name);
+                if user.is_some() {
+                    return Ok(user);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => warn!("get cached user id {}: {:?}", key, err),
+        }
+        let user = self.repo.user.find_in_org_by_name(org_id, user_name).await?;
+        if let Some(user) = &user {
+            let ttl = Duration::from_secs(self.store.config.user_cache_ttl_secs);
+            if let Err(err) = cache.set(key.as_str(), &user.id, ttl).await {
+                warn!("cache user id {}: {:?}", key, err);
+            }
+        }
+        Ok(user)
+    }
+
+    /// 用户改名或删除后清除缓存, 改名时旧名和新名都需要清除
+    async fn evict_cached_user(&self, org_id: &str, user_name: &str) {
+        let key = user_cache_key(org_id, user_name);
+        if let Err(err) = self.cache.user_id().delete(key.as_str()).await {
+            warn!("evict cached user id {}: {:?}", key, err);
+        }
+    }
+
+    /// 创建用户, 用户名已存在时返回 Code::UserAlreadyExists
+    pub async fn create_user(&self, org_id: String, user_name: String, role: Role) -> Result<User, Error> {
+        self.repo.user.insert(new_user(org_id, user_name, role)).await
+    }
+
+    /// ADMIN_USERS 中的用户名为保留用户名, 不能注册
+    pub fn is_reserved_admin_name(&self, user_name: &str) -> bool {
+        self.store.config.admin_users.iter().any(|name| name == user_name)
+    }
+
+    /// 在默认组织创建 ADMIN_USERS 中尚不存在的用户, 角色为 admin, 返回新创建的用户
+    /// 已存在的同名用户不提升角色, 避免在配置前抢注的用户获得管理员权限, 需要时由管理员手动设置
+    pub async fn seed_admin_users(&self) -> Result<Vec<User>, Error> {
+        let mut created = vec![];
+        for name in self.store.config.admin_users.iter() {
+            match self.get_org_user_by_name(DEFAULT_ORGANIZATION_ID.to_string(), name.clone()).await? {
+                Some(user) if user.role != Role::Admin => {
+                    warn!("ADMIN_USERS 中的用户 {} 已存在且不是 admin, 未修改其角色", name);
+                }
+                Some(_) => {}
+                None => {
+                    let user = self.repo.user.insert_or_get(new_user(DEFAULT_ORGANIZATION_ID.to_string(), name.clone(), Role::Admin)).await?;
+                    created.push(user);
+                }
+            }
+        }
+        Ok(created)
+    }
+
+    /// 自动开通用户: 组织内没有该用户时创建, 并发创建时以先创建的为准
+    pub async fn get_or_create_user_by_name(&self, org_id: String, user_name: String) -> Result<User, Error> {
+        if let Some(user) = self.get_org_user_by_name(org_id.clone(), user_name.clone()).await? {
+            return Ok(user);
+        }
+        self.repo.user.insert_or_get(new_user(org_id, user_name, Role::User)).await
+    }
+
+    /// 基于存储中的最新数据修改, 传入的 user 可能已过期(如来自用户缓存)
+    async fn update_user(&self, user: User, update: impl FnOnce(&mut User)) -> Result<User, Error> {
+        self.try_update_user(user, |user| {
+            update(user);
+            Ok(())
+        }).await
+    }
+
+    /// 同 update_user, 修改返回错误时不保存
+    async fn try_update_user(&self, user: User, update: impl FnOnce(&mut User) -> Result<(), Error>) -> Result<User, Error> {
+        let mut user = self.get_org_user_by_id(user.org_id, user.id).await?
+            .ok_or(Error::Feedback(Code::UserNotFound))?;
+        let org_id = user.org_id.clone();
+        let old_name = user.name.clone();
+        update(&mut user)?;
+        user.updated_at = Some(Utc::now().naive_utc());
+        let res = self.repo.user.update(user).await;
+        self.evict_cached_user(org_id.as_str(), old_name.as_str()).await;
+        let user = res?.ok_or(Error::Feedback(Code::UserNotFound))?;
+        if user.name != old_name {
+            self.evict_cached_user(org_id.as_str(), user.name.as_str()).await;
+        }
+        Ok(user)
+    }
+
+    /// 修改时区受 TIMEZONE_CHANGE_INTERVAL_HOURS 限制
+    pub async fn update_user_timezone(&self, user: User, timezone: String) -> Result<User, Error> {
+        self.try_update_user(user, |user| user.preferences.set_timezone(Some(timezone), Utc::now().naive_utc())).await
+    }
+
+    /// 修改在重新读取的用户上合并, 不会覆盖期间其他请求对偏好的修改
+    pub async fn update_user_preferences(&self, user: User, patch: UserPreferencesPatch) -> Result<User, Error> {
+        self.try_update_user(user, |user| patch.apply(&mut user.preferences, Utc::now().naive_utc())).await
+    }
+
+    pub async fn list_users(&self, org_id: String, req: ListUsersInput) -> Result<(Vec<User>, u64), Error> {
+        self.repo.user.list(org_id, req).await
+    }
+
+    /// 组织内的全部用户, 用于后台任务和导出
+    pub async fn list_all_org_users(&self, org_id: String) -> Result<Vec<User>, Error> {
+        let mut users = vec![];
+        for page in 1.. {
+            let (items, total) = self.repo.user.list(org_id.clone(), ListUsersInput {
+                page,
+                page_size: USER_PAGE_SIZE,
+                name: None,
+                disabled: None,
+                created_after: None,
+                created_before: None,
+            }).await?;
+            let done = items.len() < USER_PAGE_SIZE as usize || page * USER_PAGE_SIZE >= total;
+            users.extend(items);
+            if done {
+                break;
             }
         }
+        Ok(users)
+    }
+
+    pub async fn count_org_users(&self, org_id: String) -> Result<u64, Error> {
+        self.repo.user.count_in_org(org_id).await
     }
-}
\ No newline at end of file
+
+    /// 改名, 新用户名已存在时返回 Code::UserAlreadyExists
+    pub async fn rename_user(&self, user: User, name: String) -> Result<User, Error> {
+        self.update_user(user, |user| user.name = name).await
+    }
+
+    pub async fn set_user_disabled(&self, user: User, disabled: bool) -> Result<User, Error> {
+        self.update_user(user, |user| user.disabled = disabled).await
+    }
+
+    pub async fn set_user_role(&self, user: User, role: Role) -> Result<User, Error> {
+        self.update_user(user, |user| user.role = role).await
+    }
+
+    /// None 表示使用组织的设置, 0 表示永久保留
+    pub async fn set_user_message_retention(&self, user: User, message_retention_days: Option<i64>) -> Result<User, Error> {
+        self.update_user(user, |user| user.message_retention_days = message_retention_days).await
+    }
+
+    pub async fn delete_user(&self, user: User) -> Result<u64, Error> {
+        let (org_id, user_name) = (user.org_id.clone(), user.name.clone());
+        let deleted = self.repo.user.delete(user).await?;
+        self.evict_cached_user(org_id.as_str(), user_name.as_str()).await;
+        Ok(deleted)
+    }
+}
+
+fn user_cache_key(org_id: &str, user_name: &str) -> String {
+    format!("{}:{}", org_id, user_name)
+}
+
+fn new_user(org_id: String, user_name: String, role: Role) -> User {
+    User {
+        id: ObjectId::new().to_hex(),
+        org_id,
+        name: user_name,
+        preferences: Default::default(),
+        role,
+        disabled: false,
+        message_retention_days: None,
+        created_at: Utc::now().naive_utc(),
+        updated_at: None,
+    }
+}
@@ -1,13 +1,27 @@
 use std::ops::Deref;
+use std::str::FromStr;
 use rocket::form::Form;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use rocket::serde::json::Json;
 
-use rocket::State;
+use rocket::{Shutdown, State};
+use rocket::futures::future::ready;
+use rocket::futures::stream::BoxStream;
+use rocket::futures::StreamExt;
+use rocket::response::stream::{Event, EventStream};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{self, Responder};
+use rocket::Request;
+use okapi::openapi3::Responses;
+use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::openapi;
+use rocket_okapi::response::OpenApiResponderInner;
+use crate::auth::MetricsScraper;
 use crate::error::{Code, Error};
-use crate::model::{Context, GetAiChatResponseInput, GetAiChatResponseOutput, GetChatStatusTodayOutput, GetUserChatHistoryOutput};
+use crate::model::{ApiKey, Context, HealthOutput, DownloadAnalyticsQuery, GetModelUsageOutput, GetModelUsageQuery, GetTopUsersOutput, GetTopUsersQuery, ExportMessagesQuery, ImportMessagesOutput, ImportMessagesQuery, TranscriptFormat, CreateApiKeyInput, CreateApiKeyOutput, DeleteUserChatHistoryInput, DeleteUserChatHistoryOutput, DeleteUserInput, DeleteUserOutput, GetAiChatResponseInput, GetAiChatResponseOutput, GetChatStatusTodayOutput, GetUserChatHistoryInput, GetUserChatHistoryOutput, GetUserChatHistoryQuery, GetUserChatHistoryV2Output, GetUserStatsOutput, ListApiKeysOutput, ListUsersInput, ListUsersOutput, ListUsersQuery, CreateOrganizationInput, CreatePersonaInput, CreateUserInput, DeletePersonaInput, GetOrganizationUsageOutput, GetOrganizationUsageQuery, GetRetentionReportOutput, GetRetentionReportQuery, ListOrganizationsOutput, ListPersonasOutput, Organization, Persona, SetOrganizationRetentionInput, SetUserRetentionInput, UpdateOrganizationQuotaInput, UpdatePersonaInput, RegisterUserInput, RegisterUserOutput, RenameUserInput, RevokeApiKeyInput, SetUserDisabledInput, SetUserRoleInput, SetUserTimezoneInput, SortOrder, UpdateUserPreferencesInput, User, UserPreferences};
 
-use crate::services::Services;
+use crate::services::{PingService, Services, UserService};
 use crate::error::Error::ParamsError;
 use crate::providers::Providers;
 use crate::store::Store;
@@ -25,53 +39,520 @@ pub async fn favicon() -> &'static str {
     "favicon.ico"
 }
 
+/// # Liveness
+///
+/// 进程存活即返回 200, 不检查依赖
+#[openapi(tag = "Health")]
+#[get("/healthz")]
+pub async fn healthz() -> Json<HealthOutput> {
+    Json(PingService::liveness())
+}
+
+/// # Readiness
+///
+/// 检查数据库, 缓存 (以及开启 READINESS_CHECK_UPSTREAM 时的 OpenRouter), 任何一项不可用时返回 503
+#[openapi(tag = "Health")]
+#[get("/readyz")]
+pub async fn readyz(store: &State<Store>) -> (Status, Json<HealthOutput>) {
+    let pvd = Providers::new(store);
+    let res = PingService::readiness(pvd).await;
+    let status = if res.status == "ok" { Status::Ok } else { Status::ServiceUnavailable };
+    (status, Json(res))
+}
+
+/// # Metrics
+///
+/// Prometheus 文本格式的请求, 上游调用, token, 限流和 MongoDB 指标; 需开启 METRICS_ENABLED, 设置了 METRICS_TOKEN 时需带上 Authorization: Bearer <METRICS_TOKEN>
+#[openapi(tag = "Health")]
+#[get("/metrics")]
+pub async fn get_metrics(_scraper: MetricsScraper) -> (ContentType, String) {
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    (content_type, crate::metrics::render())
+}
+
+#[catch(401)]
+pub fn unauthorized() -> Error {
+    Error::Unauthorized
+}
+
+#[catch(403)]
+pub fn forbidden() -> Error {
+    Error::Forbidden
+}
+
 
 /// # Get AI Chat Response
 #[openapi(tag = "Chat")]
 #[post("/api/v1/get_ai_chat_response", data="<req>")]
-pub async fn get_ai_chat_response(store: &State<Store>, req: Json<GetAiChatResponseInput>) -> Result<Json<GetAiChatResponseOutput>, Error> {
+pub async fn get_ai_chat_response(store: &State<Store>, ctx: Context, req: Json<GetAiChatResponseInput>) -> Result<Json<GetAiChatResponseOutput>, Error> {
     let req = req.into_inner();
+    ctx.check_user_name(req.user_name.as_deref())?;
     let pvd = Providers::new(store);
-    let user = pvd.user().get_user_by_name(req.user_name.clone()).await?;
-    if let Some(user) = user {
-        let ctx = Context::new(user);
-        let svc = Services::new(ctx, pvd);
-        let res = svc.chat().get_ai_chat_response(req).await?;
-        Ok(Json(res))
-    } else {
-        Err(Error::Feedback(Code::UserNotFound))
+    let svc = Services::new(ctx, pvd);
+    let res = svc.chat().get_ai_chat_response(req).await?;
+    Ok(Json(res))
+}
+
+/// v1 和 v2 聊天记录共用的分页参数, last_n 为旧参数, 等同于 limit
+fn history_input(query: GetUserChatHistoryQuery) -> Result<GetUserChatHistoryInput, Error> {
+    Ok(GetUserChatHistoryInput {
+        user_name: query.user_name,
+        before: query.before,
+        after: query.after,
+        limit: query.limit.or(query.last_n).unwrap_or(20),
+        order: query.order.as_deref().map(SortOrder::from_str).transpose()?.unwrap_or_default(),
+    })
+}
+
+/// v1 历史接口下一页的游标所在的响应头
+pub const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+
+/// 在响应头中带上下一页的游标, 响应体不变
+pub struct WithNextCursor<R>(R, Option<String>);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for WithNextCursor<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        let mut res = self.0.respond_to(req)?;
+        if let Some(next_cursor) = self.1 {
+            res.set_header(Header::new(NEXT_CURSOR_HEADER, next_cursor));
+        }
+        Ok(res)
     }
+}
 
+impl<R: OpenApiResponderInner> OpenApiResponderInner for WithNextCursor<R> {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        R::responses(gen)
+    }
 }
 
 /// # Get User Chat History
+///
+/// 返回消息数组, 还有更多消息时下一页的游标在 X-Next-Cursor 响应头中; 需要对象形式的响应使用 v2 接口
 #[openapi(tag = "Chat")]
-#[get("/api/v1/get_user_chat_history?<user_name>&<last_n>")]
-pub async fn get_user_chat_history(store: &State<Store>, user_name: String, last_n: i64) -> Result<Json<GetUserChatHistoryOutput>, Error> {
-    let pvd = Providers::new(store);
-    let user = pvd.user().get_user_by_name(user_name).await?;
-    if let Some(user) = user {
-        let ctx = Context::new(user);
-        let svc = Services::new(ctx, pvd);
-        let res = svc.chat().get_user_chat_history(last_n).await?;
-        Ok(Json(res))
-    } else {
-        Err(Error::Feedback(Code::UserNotFound))
-    }
+#[get("/api/v1/get_user_chat_history?<query..>")]
+pub async fn get_user_chat_history(store: &State<Store>, ctx: Context, query: GetUserChatHistoryQuery) -> Result<WithNextCursor<Json<GetUserChatHistoryOutput>>, Error> {
+    let req = history_input(query)?;
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let (messages, next_cursor) = svc.chat().get_user_chat_history(req).await?;
+    Ok(WithNextCursor(Json(messages), next_cursor))
+}
+
+/// # Get User Chat History V2
+#[openapi(tag = "Chat")]
+#[get("/api/v2/get_user_chat_history?<query..>")]
+pub async fn get_user_chat_history_v2(store: &State<Store>, ctx: Context, query: GetUserChatHistoryQuery) -> Result<Json<GetUserChatHistoryV2Output>, Error> {
+    let req = history_input(query)?;
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.chat().get_user_chat_history_v2(req).await?;
+    Ok(Json(res))
+}
+
+/// # Subscribe User Chat Messages
+///
+/// Server-Sent Events, 每条新消息为一个 message 事件, 事件 id 为消息 id; 重连时用 after 指定最后收到的消息 id 补发断线期间的消息
+#[openapi(tag = "Chat")]
+#[get("/api/v1/subscribe_user_chat_messages?<user_name>&<after>")]
+pub async fn subscribe_user_chat_messages(store: &State<Store>, ctx: Context, user_name: Option<String>, after: Option<String>, shutdown: Shutdown) -> Result<EventStream<BoxStream<'static, Event>>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let messages = svc.chat().subscribe_user_chat_messages(user_name, after).await?;
+    // 出错时发送一个 error 事件后结束, 由客户端带上 after 重连
+    let events = messages
+        .take_until(shutdown)
+        .scan(false, |failed, msg| {
+            if *failed {
+                return ready(None);
+            }
+            *failed = msg.is_err();
+            ready(Some(match msg {
+                Ok(msg) => Event::json(&msg).id(msg.id.clone()).event("message"),
+                Err(err) => Event::data(err.to_string()).event("error"),
+            }))
+        })
+        .boxed();
+    Ok(EventStream::from(events))
 }
 
 /// # Get Chat Status Today
 #[openapi(tag = "Chat")]
 #[get("/api/v1/get_chat_status_today?<user_name>")]
-pub async fn get_chat_status_today(store: &State<Store>, user_name: String) -> Result<Json<GetChatStatusTodayOutput>, Error> {
-    let pvd = Providers::new(store);
-    let user = pvd.user().get_user_by_name(user_name).await?;
-    if let Some(user) = user {
-        let ctx = Context::new(user);
-        let svc = Services::new(ctx, pvd);
-        let res = svc.chat().get_chat_status_today().await?;
-        Ok(Json(res))
-    } else {
-        Err(Error::Feedback(Code::UserNotFound))
+pub async fn get_chat_status_today(store: &State<Store>, ctx: Context, user_name: Option<String>) -> Result<Json<GetChatStatusTodayOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.chat().get_chat_status_today(user_name).await?;
+    Ok(Json(res))
+}
+
+/// # Register User
+#[openapi(tag = "User")]
+#[post("/api/v1/register_user", data="<req>")]
+pub async fn register_user(store: &State<Store>, req: Json<RegisterUserInput>) -> Result<Json<RegisterUserOutput>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let res = UserService::register_user(pvd, req).await?;
+    Ok(Json(res))
+}
+
+/// # Set User Timezone
+#[openapi(tag = "User")]
+#[post("/api/v1/set_user_timezone", data="<req>")]
+pub async fn set_user_timezone(store: &State<Store>, ctx: Context, req: Json<SetUserTimezoneInput>) -> Result<Json<User>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.user().set_user_timezone(req).await?;
+    Ok(Json(res))
+}
+
+/// # Get User Preferences
+#[openapi(tag = "User")]
+#[get("/api/v1/get_user_preferences")]
+pub async fn get_user_preferences(store: &State<Store>, ctx: Context) -> Result<Json<UserPreferences>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.user().get_user_preferences().await?;
+    Ok(Json(res))
+}
+
+/// # Update User Preferences
+#[openapi(tag = "User")]
+#[patch("/api/v1/update_user_preferences", data="<req>")]
+pub async fn update_user_preferences(store: &State<Store>, ctx: Context, req: Json<UpdateUserPreferencesInput>) -> Result<Json<UserPreferences>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.user().update_user_preferences(req).await?;
+    Ok(Json(res))
+}
+
+/// # List Personas
+#[openapi(tag = "User")]
+#[get("/api/v1/list_personas")]
+pub async fn list_personas(store: &State<Store>, ctx: Context) -> Result<Json<ListPersonasOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.user().list_personas().await?;
+    Ok(Json(res))
+}
+
+/// # Create API Key
+#[openapi(tag = "Auth")]
+#[post("/api/v1/create_api_key", data="<req>")]
+pub async fn create_api_key(store: &State<Store>, ctx: Context, req: Json<CreateApiKeyInput>) -> Result<Json<CreateApiKeyOutput>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.auth().create_api_key(req).await?;
+    Ok(Json(res))
+}
+
+/// # List API Keys
+#[openapi(tag = "Auth")]
+#[get("/api/v1/list_api_keys")]
+pub async fn list_api_keys(store: &State<Store>, ctx: Context) -> Result<Json<ListApiKeysOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.auth().list_api_keys().await?;
+    Ok(Json(res))
+}
+
+/// # Revoke API Key
+#[openapi(tag = "Auth")]
+#[post("/api/v1/revoke_api_key", data="<req>")]
+pub async fn revoke_api_key(store: &State<Store>, ctx: Context, req: Json<RevokeApiKeyInput>) -> Result<Json<ApiKey>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.auth().revoke_api_key(req).await?;
+    Ok(Json(res))
+}
+
+/// 查询参数中的时间, 支持 RFC 3339 或 YYYY-MM-DD (UTC)
+fn parse_datetime_param(name: &str, value: Option<String>) -> Result<Option<NaiveDateTime>, Error> {
+    let Some(value) = value.filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value.as_str()) {
+        return Ok(Some(dt.naive_utc()));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value.as_str(), "%Y-%m-%d") {
+        return Ok(Some(date.and_time(Default::default())));
     }
+    Err(ParamsError(format!("{} 需为 RFC 3339 时间或 YYYY-MM-DD: {}", name, value)))
+}
+
+/// # List Users
+#[openapi(tag = "Admin")]
+#[get("/api/v1/admin/list_users?<query..>")]
+pub async fn admin_list_users(store: &State<Store>, ctx: Context, query: ListUsersQuery) -> Result<Json<ListUsersOutput>, Error> {
+    let req = ListUsersInput {
+        page: query.page.unwrap_or(1),
+        page_size: query.page_size.unwrap_or(20),
+        name: query.name,
+        disabled: query.disabled,
+        created_after: parse_datetime_param("created_after", query.created_after)?,
+        created_before: parse_datetime_param("created_before", query.created_before)?,
+    };
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().list_users(req).await?;
+    Ok(Json(res))
+}
+
+/// # Get User Stats
+#[openapi(tag = "Admin")]
+#[get("/api/v1/admin/get_user_stats?<user_id>")]
+pub async fn admin_get_user_stats(store: &State<Store>, ctx: Context, user_id: String) -> Result<Json<GetUserStatsOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().get_user_stats(user_id).await?;
+    Ok(Json(res))
+}
+
+/// # Rename User
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/rename_user", data="<req>")]
+pub async fn admin_rename_user(store: &State<Store>, ctx: Context, req: Json<RenameUserInput>) -> Result<Json<User>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().rename_user(req).await?;
+    Ok(Json(res))
+}
+
+/// # Enable Or Disable User
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/set_user_disabled", data="<req>")]
+pub async fn admin_set_user_disabled(store: &State<Store>, ctx: Context, req: Json<SetUserDisabledInput>) -> Result<Json<User>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().set_user_disabled(req).await?;
+    Ok(Json(res))
+}
+
+/// # Set User Role
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/set_user_role", data="<req>")]
+pub async fn admin_set_user_role(store: &State<Store>, ctx: Context, req: Json<SetUserRoleInput>) -> Result<Json<User>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().set_user_role(req).await?;
+    Ok(Json(res))
+}
+
+/// # Delete User Chat History
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/delete_user_chat_history", data="<req>")]
+pub async fn admin_delete_user_chat_history(store: &State<Store>, ctx: Context, req: Json<DeleteUserChatHistoryInput>) -> Result<Json<DeleteUserChatHistoryOutput>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().delete_user_chat_history(req).await?;
+    Ok(Json(res))
+}
+
+/// # Delete User
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/delete_user", data="<req>")]
+pub async fn admin_delete_user(store: &State<Store>, ctx: Context, req: Json<DeleteUserInput>) -> Result<Json<DeleteUserOutput>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().delete_user(req).await?;
+    Ok(Json(res))
+}
+
+/// # Create User
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/create_user", data="<req>")]
+pub async fn admin_create_user(store: &State<Store>, ctx: Context, req: Json<CreateUserInput>) -> Result<Json<User>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().create_user(req).await?;
+    Ok(Json(res))
+}
+
+/// # Create Persona
+#[openapi(tag = "Persona")]
+#[post("/api/v1/admin/create_persona", data="<req>")]
+pub async fn admin_create_persona(store: &State<Store>, ctx: Context, req: Json<CreatePersonaInput>) -> Result<Json<Persona>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().create_persona(req).await?;
+    Ok(Json(res))
+}
+
+/// # Update Persona
+#[openapi(tag = "Persona")]
+#[post("/api/v1/admin/update_persona", data="<req>")]
+pub async fn admin_update_persona(store: &State<Store>, ctx: Context, req: Json<UpdatePersonaInput>) -> Result<Json<Persona>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().update_persona(req).await?;
+    Ok(Json(res))
+}
+
+/// # Delete Persona
+#[openapi(tag = "Persona")]
+#[post("/api/v1/admin/delete_persona", data="<req>")]
+pub async fn admin_delete_persona(store: &State<Store>, ctx: Context, req: Json<DeletePersonaInput>) -> Result<Json<Persona>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().delete_persona(req).await?;
+    Ok(Json(res))
+}
+
+/// # Create Organization
+#[openapi(tag = "Organization")]
+#[post("/api/v1/admin/create_organization", data="<req>")]
+pub async fn admin_create_organization(store: &State<Store>, ctx: Context, req: Json<CreateOrganizationInput>) -> Result<Json<Organization>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().create_organization(req).await?;
+    Ok(Json(res))
+}
+
+/// # List Organizations
+#[openapi(tag = "Organization")]
+#[get("/api/v1/admin/list_organizations")]
+pub async fn admin_list_organizations(store: &State<Store>, ctx: Context) -> Result<Json<ListOrganizationsOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().list_organizations().await?;
+    Ok(Json(res))
+}
+
+/// # Update Organization Quota
+#[openapi(tag = "Organization")]
+#[post("/api/v1/admin/update_organization_quota", data="<req>")]
+pub async fn admin_update_organization_quota(store: &State<Store>, ctx: Context, req: Json<UpdateOrganizationQuotaInput>) -> Result<Json<Organization>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().update_organization_quota(req).await?;
+    Ok(Json(res))
+}
+
+/// # Set Organization Message Retention
+#[openapi(tag = "Organization")]
+#[post("/api/v1/admin/set_organization_retention", data="<req>")]
+pub async fn admin_set_organization_retention(store: &State<Store>, ctx: Context, req: Json<SetOrganizationRetentionInput>) -> Result<Json<Organization>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().set_organization_retention(req).await?;
+    Ok(Json(res))
+}
+
+/// # Get Organization Usage
+#[openapi(tag = "Organization")]
+#[get("/api/v1/admin/get_organization_usage?<query..>")]
+pub async fn admin_get_organization_usage(store: &State<Store>, ctx: Context, query: GetOrganizationUsageQuery) -> Result<Json<GetOrganizationUsageOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().get_organization_usage(query).await?;
+    Ok(Json(res))
+}
+
+/// # Get Model Usage
+///
+/// 各模型的 AI 消息数, token 用量和平均生成耗时
+#[openapi(tag = "Organization")]
+#[get("/api/v1/admin/get_model_usage?<query..>")]
+pub async fn admin_get_model_usage(store: &State<Store>, ctx: Context, query: GetModelUsageQuery) -> Result<Json<GetModelUsageOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().get_model_usage(query).await?;
+    Ok(Json(res))
+}
+
+/// # Get Top Users
+///
+/// 区间内发送消息最多的用户
+#[openapi(tag = "Organization")]
+#[get("/api/v1/admin/get_top_users?<query..>")]
+pub async fn admin_get_top_users(store: &State<Store>, ctx: Context, query: GetTopUsersQuery) -> Result<Json<GetTopUsersOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().get_top_users(query).await?;
+    Ok(Json(res))
+}
+
+/// # Download Analytics
+///
+/// 以 CSV 下载 daily (每天的消息数和活跃用户数), models (各模型用量) 或 top_users 报表
+#[openapi(tag = "Organization")]
+#[get("/api/v1/admin/download_analytics?<query..>")]
+pub async fn admin_download_analytics(store: &State<Store>, ctx: Context, query: DownloadAnalyticsQuery) -> Result<(ContentType, String), Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let content = svc.admin().download_analytics(query).await?;
+    Ok((ContentType::CSV, content))
+}
+
+/// # Set User Message Retention
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/set_user_retention", data="<req>")]
+pub async fn admin_set_user_retention(store: &State<Store>, ctx: Context, req: Json<SetUserRetentionInput>) -> Result<Json<User>, Error> {
+    let req = req.into_inner();
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().set_user_retention(req).await?;
+    Ok(Json(res))
+}
+
+/// # Get Retention Report
+///
+/// 预览按当前保留策略会被清理的消息, 不会删除任何数据
+#[openapi(tag = "Admin")]
+#[get("/api/v1/admin/get_retention_report?<query..>")]
+pub async fn admin_get_retention_report(store: &State<Store>, ctx: Context, query: GetRetentionReportQuery) -> Result<Json<GetRetentionReportOutput>, Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().get_retention_report(query).await?;
+    Ok(Json(res))
+}
+
+/// # Export Messages
+///
+/// 导出为 jsonl, csv 或 markdown, 未指定 user_name 时导出组织内所有用户
+#[openapi(tag = "Admin")]
+#[get("/api/v1/admin/export_messages?<query..>")]
+pub async fn admin_export_messages(store: &State<Store>, ctx: Context, query: ExportMessagesQuery) -> Result<(ContentType, String), Error> {
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let (format, content) = svc.admin().export_messages(query).await?;
+    let content_type = match format {
+        TranscriptFormat::Csv => ContentType::CSV,
+        TranscriptFormat::Markdown => ContentType::new("text", "markdown"),
+        _ => ContentType::new("application", "x-ndjson"),
+    };
+    Ok((content_type, content))
+}
+
+/// # Import Messages
+///
+/// 请求体为 jsonl, csv 或 xlsx 文件, 列为 user_name, type, text, created_at, model; 任何一行有错误时都不会写入
+#[openapi(tag = "Admin")]
+#[post("/api/v1/admin/import_messages?<query..>", data="<data>")]
+pub async fn admin_import_messages(store: &State<Store>, ctx: Context, query: ImportMessagesQuery, data: Data<'_>) -> Result<Json<ImportMessagesOutput>, Error> {
+    let limit = store.config.import_max_bytes;
+    let data = data.open(limit.bytes()).into_bytes().await?;
+    if !data.is_complete() {
+        return Err(ParamsError(format!("文件不能超过 {} 字节", limit)));
+    }
+    let pvd = Providers::new(store);
+    let svc = Services::new(ctx, pvd);
+    let res = svc.admin().import_messages(query, data.into_inner()).await?;
+    Ok(Json(res))
 }
@@ -0,0 +1,489 @@
+use anyhow::Context as AnyhowContext;
+use crate::error::{Code, Error};
+use chrono::{NaiveDate, Utc};
+use mongodb::bson::oid::ObjectId;
+use crate::error::Error::ParamsError;
+use crate::model::{MAX_RETENTION_DAYS, AnalyticsReport, Context, DownloadAnalyticsQuery, GetModelUsageOutput, GetModelUsageQuery, GetTopUsersOutput, GetTopUsersQuery, ExportMessagesQuery, ImportMessagesOutput, ImportMessagesQuery, TranscriptFormat, CreateOrganizationInput, CreatePersonaInput, CreateUserInput, DeletePersonaInput, DeleteUserChatHistoryInput, DeleteUserChatHistoryOutput, DeleteUserInput, DeleteUserOutput, GetOrganizationUsageOutput, GetOrganizationUsageQuery, GetRetentionReportOutput, GetRetentionReportQuery, GetUserStatsOutput, ListOrganizationsOutput, ListUsersInput, ListUsersOutput, Organization, Permission, Persona, RenameUserInput, Role, SetOrganizationRetentionInput, SetUserDisabledInput, SetUserRetentionInput, SetUserRoleInput, UpdateOrganizationQuotaInput, UpdatePersonaInput, User, UserRetentionReport};
+use crate::providers::Providers;
+
+/// 每页最多返回的用户数
+const MAX_PAGE_SIZE: u64 = 100;
+/// 角色名的最大长度
+const MAX_PERSONA_NAME_LEN: usize = 32;
+/// 角色设定的最大长度
+const MAX_PERSONA_PROMPT_LEN: usize = 2000;
+
+pub struct AdminService {
+    ctx: Context,
+    pvd: Providers,
+}
+
+impl AdminService {
+    pub fn new(context: Context, providers: Providers) -> Self {
+        Self {
+            ctx: context,
+            pvd: providers,
+        }
+    }
+}
+
+impl AdminService {
+    /// 只能管理当前组织内的用户
+    async fn get_user(&self, user_id: String) -> Result<User, Error> {
+        check_id("用户", user_id.as_str())?;
+        self.pvd.user().get_org_user_by_id(self.ctx.org_id.clone(), user_id).await?
+            .ok_or(Error::Feedback(Code::UserNotFound))
+    }
+
+    pub async fn list_users(&self, req: ListUsersInput) -> Result<ListUsersOutput, Error> {
+        self.ctx.check_permission(Permission::ViewUsers)?;
+        let page = req.page.max(1);
+        let page_size = req.page_size.clamp(1, MAX_PAGE_SIZE);
+        // 跳过的条数需要能放进 i64
+        if (page - 1).checked_mul(page_size).filter(|skip| *skip <= i64::MAX as u64).is_none() {
+            return Err(ParamsError(format!("page 超出范围: {}", page)));
+        }
+        let (users, total) = self.pvd.user().list_users(self.ctx.org_id.clone(), ListUsersInput { page, page_size, ..req }).await
+            .with_context(|| "list_users".to_string())?;
+        Ok(ListUsersOutput {
+            users,
+            total,
+            page,
+            page_size,
+        })
+    }
+
+    pub async fn get_user_stats(&self, user_id: String) -> Result<GetUserStatsOutput, Error> {
+        self.ctx.check_permission(Permission::ViewUsers)?;
+        let user = self.get_user(user_id).await?;
+        let chat = self.pvd.chat().get_user_chat_stats(user.clone()).await
+            .with_context(|| format!("get_user_chat_stats: {:?}", user.clone()))?;
+        let api_key_count = self.pvd.auth().count_user_api_keys(user.clone()).await
+            .with_context(|| format!("count_user_api_keys: {:?}", user.clone()))?;
+        Ok(GetUserStatsOutput {
+            user,
+            chat,
+            api_key_count,
+        })
+    }
+
+    pub async fn rename_user(&self, req: RenameUserInput) -> Result<User, Error> {
+        self.ctx.check_permission(Permission::ManageUsers)?;
+        let name = User::validate_name(req.name.as_str())?;
+        let user = self.get_user(req.user_id).await?;
+        self.pvd.user().rename_user(user, name).await
+    }
+
+    pub async fn set_user_disabled(&self, req: SetUserDisabledInput) -> Result<User, Error> {
+        self.ctx.check_permission(Permission::ManageUsers)?;
+        let user = self.get_user(req.user_id).await?;
+        if user.id == self.ctx.user.id && req.disabled {
+            return Err(Error::ParamsError("不能禁用自己".to_string()));
+        }
+        self.pvd.user().set_user_disabled(user, req.disabled).await
+    }
+
+    pub async fn set_user_role(&self, req: SetUserRoleInput) -> Result<User, Error> {
+        self.ctx.check_permission(Permission::ManageRoles)?;
+        let role = req.role.parse::<Role>()?;
+        let user = self.get_user(req.user_id).await?;
+        if user.id == self.ctx.user.id {
+            return Err(Error::ParamsError("不能修改自己的角色".to_string()));
+        }
+        self.pvd.user().set_user_role(user, role).await
+    }
+
+    pub async fn set_user_retention(&self, req: SetUserRetentionInput) -> Result<User, Error> {
+        self.ctx.check_permission(Permission::ManageUsers)?;
+        check_retention_days(req.message_retention_days)?;
+        let user = self.get_user(req.user_id).await?;
+        self.pvd.user().set_user_message_retention(user, req.message_retention_days).await
+    }
+
+    pub async fn delete_user_chat_history(&self, req: DeleteUserChatHistoryInput) -> Result<DeleteUserChatHistoryOutput, Error> {
+        self.ctx.check_permission(Permission::DeleteAnyUserChat)?;
+        let user = self.get_user(req.user_id).await?;
+        let deleted_messages = self.pvd.chat().delete_user_chat_messages(user.clone()).await
+            .with_context(|| format!("delete_user_chat_messages: {:?}", user.clone()))?;
+        Ok(DeleteUserChatHistoryOutput {
+            user_id: user.id,
+            deleted_messages,
+        })
+    }
+
+    /// 导出指定用户或组织内所有用户的消息
+    pub async fn export_messages(&self, req: ExportMessagesQuery) -> Result<(TranscriptFormat, String), Error> {
+        self.ctx.check_permission(Permission::ReadAnyUserChat)?;
+        let format = match req.format.filter(|format| !format.is_empty()) {
+            Some(format) => format.parse::<TranscriptFormat>()?,
+            None => TranscriptFormat::Jsonl,
+        };
+        if format == TranscriptFormat::Xlsx {
+            return Err(ParamsError("xlsx 只支持导入".to_string()));
+        }
+        let org = self.get_organization(req.org_id).await?;
+        let users = match req.user_name.filter(|user_name| !user_name.is_empty()) {
+            Some(user_name) => vec![self.pvd.user().get_org_user_by_name(org.id.clone(), user_name).await?
+                .ok_or(Error::Feedback(Code::UserNotFound))?],
+            None => {
+                self.ctx.check_permission(Permission::ViewUsers)?;
+                self.pvd.user().list_all_org_users(org.id.clone()).await
+                    .with_context(|| format!("list_all_org_users: {}", org.id))?
+            }
+        };
+        let content = self.pvd.transcript().export_messages(users, format).await
+            .with_context(|| format!("export_messages: {}", org.id))?;
+        Ok((format, content))
+    }
+
+    /// 导入聊天记录, 不存在的用户创建在目标组织
+    pub async fn import_messages(&self, req: ImportMessagesQuery, data: Vec<u8>) -> Result<ImportMessagesOutput, Error> {
+        self.ctx.check_permission(Permission::ManageUsers)?;
+        let format = req.format.parse::<TranscriptFormat>()?;
+        let org = self.get_organization(req.org_id).await?;
+        let transcript = self.pvd.transcript();
+        let rows = transcript.parse_transcript(format, data.as_slice())?;
+        let res = transcript.import_messages(org.id.clone(), rows, req.dry_run.unwrap_or(false)).await
+            .with_context(|| format!("import_messages: {}", org.id))?;
+        Ok(res)
+    }
+
+    /// 删除用户及其消息, API Key 和额度计数
+    pub async fn delete_user(&self, req: DeleteUserInput) -> Result<DeleteUserOutput, Error> {
+        self.ctx.check_permission(Permission::DeleteUsers)?;
+        let user = self.get_user(req.user_id).await?;
+        if user.id == self.ctx.user.id {
+            return Err(Error::ParamsError("不能删除自己".to_string()));
+        }
+        let deleted_messages = self.pvd.chat().delete_user_chat_messages(user.clone()).await
+            .with_context(|| format!("delete_user_chat_messages: {:?}", user.clone()))?;
+        let deleted_api_keys = self.pvd.auth().delete_user_api_keys(user.clone()).await
+            .with_context(|| format!("delete_user_api_keys: {:?}", user.clone()))?;
+        self.pvd.quota().delete_user_quota(user.clone()).await
+            .with_context(|| format!("delete_user_quota: {:?}", user.clone()))?;
+        self.pvd.user().delete_user(user.clone()).await
+            .with_context(|| format!("delete_user: {:?}", user.clone()))?;
+        Ok(DeleteUserOutput {
+            user_id: user.id,
+            deleted_messages,
+            deleted_api_keys,
+        })
+    }
+
+    /// 在当前组织或指定组织创建用户
+    pub async fn create_user(&self, req: CreateUserInput) -> Result<User, Error> {
+        self.ctx.check_permission(Permission::ManageUsers)?;
+        let name = User::validate_name(req.name.as_str())?;
+        let org = self.get_organization(req.org_id).await?;
+        let role = match req.role {
+            Some(role) => role.parse::<Role>()?,
+            None => Role::User,
+        };
+        if role != Role::User {
+            self.ctx.check_permission(Permission::ManageRoles)?;
+        }
+        self.pvd.user().create_user(org.id, name, role).await
+    }
+}
+
+impl AdminService {
+    /// 只能管理当前组织内的角色
+    pub async fn create_persona(&self, req: CreatePersonaInput) -> Result<Persona, Error> {
+        self.ctx.check_permission(Permission::ManagePersonas)?;
+        let name = check_persona_text("角色名", req.name, MAX_PERSONA_NAME_LEN)?;
+        let prompt = check_persona_text("角色设定", req.prompt, MAX_PERSONA_PROMPT_LEN)?;
+        self.pvd.persona().create_persona(self.ctx.org_id.clone(), name, prompt).await
+    }
+
+    pub async fn update_persona(&self, req: UpdatePersonaInput) -> Result<Persona, Error> {
+        self.ctx.check_permission(Permission::ManagePersonas)?;
+        check_id("角色", req.persona_id.as_str())?;
+        let prompt = check_persona_text("角色设定", req.prompt, MAX_PERSONA_PROMPT_LEN)?;
+        self.pvd.persona().update_persona_prompt(self.ctx.org_id.clone(), req.persona_id, prompt).await
+    }
+
+    pub async fn delete_persona(&self, req: DeletePersonaInput) -> Result<Persona, Error> {
+        self.ctx.check_permission(Permission::ManagePersonas)?;
+        check_id("角色", req.persona_id.as_str())?;
+        self.pvd.persona().delete_persona(self.ctx.org_id.clone(), req.persona_id).await
+    }
+}
+
+impl AdminService {
+    /// 未指定时为当前组织, 指定其他组织时需要 ManageOrganizations 权限
+    async fn get_organization(&self, org_id: Option<String>) -> Result<Organization, Error> {
+        let org_id = org_id.filter(|org_id| !org_id.is_empty()).unwrap_or(self.ctx.org_id.clone());
+        check_id("组织", org_id.as_str())?;
+        self.ctx.check_organization(org_id.as_str())?;
+        self.pvd.organization().get_organization(org_id).await?
+            .ok_or(Error::Feedback(Code::OrganizationNotFound))
+    }
+
+    pub async fn create_organization(&self, req: CreateOrganizationInput) -> Result<Organization, Error> {
+        self.ctx.check_permission(Permission::ManageOrganizations)?;
+        let name = req.name.trim().to_string();
+        if name.is_empty() {
+            return Err(ParamsError("组织名不能为空".to_string()));
+        }
+        check_limits(req.daily_message_limit, req.burst_message_limit)?;
+        self.pvd.organization().create_organization(name, req.daily_message_limit, req.burst_message_limit).await
+    }
+
+    pub async fn list_organizations(&self) -> Result<ListOrganizationsOutput, Error> {
+        self.ctx.check_permission(Permission::ManageOrganizations)?;
+        self.pvd.organization().list_organizations().await
+    }
+
+    pub async fn update_organization_quota(&self, req: UpdateOrganizationQuotaInput) -> Result<Organization, Error> {
+        self.ctx.check_permission(Permission::ManageOrganizations)?;
+        check_limits(req.daily_message_limit, req.burst_message_limit)?;
+        let org = self.get_organization(Some(req.org_id)).await?;
+        self.pvd.organization().update_organization_quota(org.id, req.daily_message_limit, req.burst_message_limit).await
+    }
+
+    pub async fn set_organization_retention(&self, req: SetOrganizationRetentionInput) -> Result<Organization, Error> {
+        self.ctx.check_permission(Permission::ManageOrganizations)?;
+        check_retention_days(req.message_retention_days)?;
+        let org = self.get_organization(Some(req.org_id)).await?;
+        self.pvd.organization().update_organization_retention(org.id, req.message_retention_days).await
+    }
+
+    /// 预览按当前保留策略会被清理的消息
+    pub async fn get_retention_report(&self, req: GetRetentionReportQuery) -> Result<GetRetentionReportOutput, Error> {
+        self.ctx.check_permission(Permission::ViewUsers)?;
+        let organization = self.get_organization(req.org_id).await?;
+        let retention = self.pvd.retention();
+        let expired = retention.get_expired_messages(organization.clone(), Utc::now()).await
+            .with_context(|| format!("get_expired_messages: {}", organization.id))?;
+        let users = expired.into_iter()
+            .map(|item| UserRetentionReport {
+                user_id: item.user.id,
+                user_name: item.user.name,
+                message_retention_days: item.retention_days,
+                cutoff: item.cutoff.to_rfc3339(),
+                expired_messages: item.count,
+            })
+            .collect::<Vec<UserRetentionReport>>();
+        Ok(GetRetentionReportOutput {
+            message_retention_days: retention.organization_retention_days(&organization),
+            organization,
+            expired_messages: users.iter().map(|user| user.expired_messages).sum(),
+            users,
+        })
+    }
+
+    pub async fn get_organization_usage(&self, req: GetOrganizationUsageQuery) -> Result<GetOrganizationUsageOutput, Error> {
+        self.ctx.check_permission(Permission::ViewUsers)?;
+        let organization = self.get_organization(req.org_id).await?;
+        let start = parse_date_param("start", req.start)?;
+        let end = parse_date_param("end", req.end)?;
+        let usage = self.pvd.organization().get_organization_usage(organization.id.clone(), start, end).await
+            .with_context(|| format!("get_organization_usage: {}", organization.id))?;
+        let user_count = self.pvd.user().count_org_users(organization.id.clone()).await
+            .with_context(|| format!("count_org_users: {}", organization.id))?;
+        Ok(GetOrganizationUsageOutput {
+            organization,
+            start: usage.start.to_string(),
+            end: usage.end.to_string(),
+            user_count,
+            active_user_count: usage.active_user_count,
+            user_message_count: usage.user_message_count,
+            ai_message_count: usage.ai_message_count,
+            daily: usage.daily,
+        })
+    }
+
+    /// 各模型的 token 用量和平均耗时
+    pub async fn get_model_usage(&self, req: GetModelUsageQuery) -> Result<GetModelUsageOutput, Error> {
+        self.ctx.check_permission(Permission::ViewUsers)?;
+        let organization = self.get_organization(req.org_id).await?;
+        let analytics = self.pvd.analytics();
+        let range = analytics.date_range(parse_date_param("start", req.start)?, parse_date_param("end", req.end)?)?;
+        let models = analytics.get_model_usage(organization.id.clone(), range).await
+            .with_context(|| format!("get_model_usage: {}", organization.id))?;
+        Ok(GetModelUsageOutput {
+            organization,
+            start: range.start.to_string(),
+            end: range.end.to_string(),
+            models,
+        })
+    }
+
+    /// 发送消息最多的用户
+    pub async fn get_top_users(&self, req: GetTopUsersQuery) -> Result<GetTopUsersOutput, Error> {
+        self.ctx.check_permission(Permission::ViewUsers)?;
+        let organization = self.get_organization(req.org_id).await?;
+        let analytics = self.pvd.analytics();
+        let range = analytics.date_range(parse_date_param("start", req.start)?, parse_date_param("end", req.end)?)?;
+        let users = analytics.get_top_users(organization.id.clone(), range, req.limit).await
+            .with_context(|| format!("get_top_users: {}", organization.id))?;
+        Ok(GetTopUsersOutput {
+            organization,
+            start: range.start.to_string(),
+            end: range.end.to_string(),
+            users,
+        })
+    }
+
+    /// 把统计报表渲染为 CSV
+    pub async fn download_analytics(&self, req: DownloadAnalyticsQuery) -> Result<String, Error> {
+        self.ctx.check_permission(Permission::ViewUsers)?;
+        let report = req.report.parse::<AnalyticsReport>()?;
+        let organization = self.get_organization(req.org_id).await?;
+        let analytics = self.pvd.analytics();
+        let range = analytics.date_range(parse_date_param("start", req.start)?, parse_date_param("end", req.end)?)?;
+        let content = match report {
+            AnalyticsReport::Daily => {
+                let usage = analytics.get_daily_usage(organization.id.clone(), range).await
+                    .with_context(|| format!("get_daily_usage: {}", organization.id))?;
+                analytics.render_daily_csv(&usage.daily)?
+            }
+            AnalyticsReport::Models => {
+                let models = analytics.get_model_usage(organization.id.clone(), range).await
+                    .with_context(|| format!("get_model_usage: {}", organization.id))?;
+                analytics.render_model_csv(&models)?
+            }
+            AnalyticsReport::TopUsers => {
+                let users = analytics.get_top_users(organization.id.clone(), range, req.limit).await
+                    .with_context(|| format!("get_top_users: {}", organization.id))?;
+                analytics.render_top_user_csv(&users)?
+            }
+        };
+        Ok(content)
+    }
+}
+
+/// 请求中的用户和组织 id, 格式不对时返回参数错误而不是交给存储层
+fn check_id(name: &str, id: &str) -> Result<(), Error> {
+    if ObjectId::parse_str(id).is_err() {
+        return Err(ParamsError(format!("无效的{} id: {}", name, id)));
+    }
+    Ok(())
+}
+
+/// 去掉首尾空白, 不能为空且不能超过 max_len 个字符
+fn check_persona_text(name: &str, value: String, max_len: usize) -> Result<String, Error> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(ParamsError(format!("{}不能为空", name)));
+    }
+    if value.chars().count() > max_len {
+        return Err(ParamsError(format!("{}不能超过 {} 个字符", name, max_len)));
+    }
+    Ok(value.to_string())
+}
+
+fn check_limits(daily_message_limit: Option<i64>, burst_message_limit: Option<i64>) -> Result<(), Error> {
+    if daily_message_limit.is_some_and(|limit| limit < 0) || burst_message_limit.is_some_and(|limit| limit < 0) {
+        return Err(ParamsError("额度不能为负数".to_string()));
+    }
+    Ok(())
+}
+
+fn check_retention_days(message_retention_days: Option<i64>) -> Result<(), Error> {
+    if message_retention_days.is_some_and(|days| days < 0) {
+        return Err(ParamsError("保留天数不能为负数".to_string()));
+    }
+    if message_retention_days.is_some_and(|days| days > MAX_RETENTION_DAYS) {
+        return Err(ParamsError(format!("保留天数不能超过 {} 天", MAX_RETENTION_DAYS)));
+    }
+    Ok(())
+}
+
+fn parse_date_param(name: &str, value: Option<String>) -> Result<Option<NaiveDate>, Error> {
+    let Some(value) = value.filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    NaiveDate::parse_from_str(value.as_str(), "%Y-%m-%d")
+        .map(Some)
+        .map_err(|_| ParamsError(format!("{} 需为 YYYY-MM-DD: {}", name, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::Config;
+    use crate::model::{DEFAULT_ORGANIZATION_ID, UpdateUserPreferencesInput};
+    use crate::services::UserService;
+    use crate::store::Store;
+    use super::*;
+
+    async fn setup() -> AdminService {
+        let store = Store::memory_for_test(Config::default()).await;
+        let pvd = Providers::new(&store);
+        let admin = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "admin".to_string(), Role::Admin).await.unwrap();
+        AdminService::new(Context::new(admin), pvd)
+    }
+
+    fn list_input(page: u64, page_size: u64) -> ListUsersInput {
+        ListUsersInput {
+            page,
+            page_size,
+            name: None,
+            disabled: None,
+            created_after: None,
+            created_before: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_range_pages_are_params_errors() {
+        let svc = setup().await;
+        let res = svc.list_users(list_input(0, 0)).await.unwrap();
+        assert_eq!((res.page, res.page_size, res.users.len()), (1, 1, 1));
+        assert!(svc.list_users(list_input(1000, MAX_PAGE_SIZE)).await.unwrap().users.is_empty());
+        assert!(matches!(svc.list_users(list_input(u64::MAX, MAX_PAGE_SIZE)).await, Err(Error::ParamsError(_))));
+        assert!(matches!(svc.list_users(list_input(u64::MAX / MAX_PAGE_SIZE, MAX_PAGE_SIZE)).await, Err(Error::ParamsError(_))));
+    }
+
+    #[tokio::test]
+    async fn malformed_ids_are_params_errors() {
+        let svc = setup().await;
+        assert!(matches!(svc.get_user_stats("not-an-id".to_string()).await, Err(Error::ParamsError(_))));
+        assert!(matches!(svc.get_user_stats(ObjectId::new().to_hex()).await, Err(Error::Feedback(Code::UserNotFound))));
+        assert!(matches!(svc.set_user_disabled(SetUserDisabledInput {
+            user_id: "../admin".to_string(),
+            disabled: true,
+        }).await, Err(Error::ParamsError(_))));
+        assert!(matches!(svc.get_organization(Some("bad".to_string())).await, Err(Error::ParamsError(_))));
+    }
+
+    fn persona_input(name: &str, prompt: &str) -> CreatePersonaInput {
+        CreatePersonaInput {
+            name: name.to_string(),
+            prompt: prompt.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn personas_are_owned_by_the_organization() {
+        let svc = setup().await;
+        let tutor = svc.create_persona(persona_input(" tutor ", "You are a patient math tutor.")).await.unwrap();
+        assert_eq!(tutor.name, "tutor");
+        assert!(matches!(svc.create_persona(persona_input("tutor", "again")).await, Err(Error::Feedback(Code::PersonaAlreadyExists))));
+        assert!(matches!(svc.create_persona(persona_input("empty", " ")).await, Err(Error::ParamsError(_))));
+
+        // 其他组织可以有同名角色, 但不能修改或删除本组织的角色
+        let org = svc.pvd.organization().create_organization("acme".to_string(), None, None).await.unwrap();
+        let acme_admin = svc.pvd.user().create_user(org.id.clone(), "admin".to_string(), Role::Admin).await.unwrap();
+        let acme = AdminService::new(Context::new(acme_admin), svc.pvd.clone());
+        acme.create_persona(persona_input("tutor", "You are a strict tutor.")).await.unwrap();
+        let update = UpdatePersonaInput { persona_id: tutor.id.clone(), prompt: "hijacked".to_string() };
+        assert!(matches!(acme.update_persona(update).await, Err(Error::Feedback(Code::PersonaNotFound))));
+        assert!(matches!(acme.delete_persona(DeletePersonaInput { persona_id: tutor.id.clone() }).await, Err(Error::Feedback(Code::PersonaNotFound))));
+
+        let bob = svc.pvd.user().create_user(org.id, "bob".to_string(), Role::User).await.unwrap();
+        let bob_admin = AdminService::new(Context::new(bob.clone()), svc.pvd.clone());
+        assert!(matches!(bob_admin.create_persona(persona_input("pirate", "Arr.")).await, Err(Error::Forbidden)));
+        let bob = UserService::new(Context::new(bob), svc.pvd.clone());
+        let personas = bob.list_personas().await.unwrap();
+        assert_eq!(personas.iter().map(|persona| persona.prompt.as_str()).collect::<Vec<&str>>(), vec!["You are a strict tutor."]);
+        let select = |name: &str| UpdateUserPreferencesInput { default_persona: Some(name.to_string()), ..Default::default() };
+        assert_eq!(bob.update_user_preferences(select("tutor")).await.unwrap().default_persona.as_deref(), Some("tutor"));
+        assert!(matches!(bob.update_user_preferences(select("pirate")).await, Err(Error::Feedback(Code::PersonaNotFound))));
+
+        let update = UpdatePersonaInput { persona_id: tutor.id.clone(), prompt: "You are a kind tutor.".to_string() };
+        assert_eq!(svc.update_persona(update).await.unwrap().prompt, "You are a kind tutor.");
+        svc.delete_persona(DeletePersonaInput { persona_id: tutor.id }).await.unwrap();
+        assert!(svc.pvd.persona().list_personas(DEFAULT_ORGANIZATION_ID.to_string()).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Context as AnyhowContext;
+use crate::error::{Code, Error};
+use crate::model::{ApiKey, Context, CreateApiKeyInput, CreateApiKeyOutput, ListApiKeysOutput, RevokeApiKeyInput};
+use crate::providers::Providers;
+
+pub struct AuthService {
+    ctx: Context,
+    pvd: Providers,
+}
+
+impl AuthService {
+    pub fn new(context: Context, providers: Providers) -> Self {
+        Self {
+            ctx: context,
+            pvd: providers,
+        }
+    }
+}
+
+impl AuthService {
+    pub async fn create_api_key(&self, req: CreateApiKeyInput) -> Result<CreateApiKeyOutput, Error> {
+        let name = req.name.trim().to_string();
+        if name.is_empty() {
+            return Err(Error::ParamsError("name 不能为空".to_string()));
+        }
+        let (api_key, key) = self.pvd.auth().create_api_key(self.ctx.user.clone(), name).await
+            .with_context(|| format!("create_api_key: {:?}", self.ctx.user.clone()))?;
+        Ok(CreateApiKeyOutput { api_key, key })
+    }
+
+    pub async fn list_api_keys(&self) -> Result<ListApiKeysOutput, Error> {
+        let api_keys = self.pvd.auth().list_api_keys(self.ctx.user.clone()).await
+            .with_context(|| format!("list_api_keys: {:?}", self.ctx.user.clone()))?;
+        Ok(api_keys)
+    }
+
+    pub async fn revoke_api_key(&self, req: RevokeApiKeyInput) -> Result<ApiKey, Error> {
+        let api_key = self.pvd.auth().revoke_api_key(self.ctx.user.clone(), req.id).await?;
+        api_key.ok_or(Error::Feedback(Code::RecordNotFound))
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt::format;
+use std::time::Instant;
 use anyhow::Context as AnyhowContext;
 use chrono::{NaiveDateTime, Utc};
 use log::debug;
@@ -6,8 +7,15 @@ use mongodb::bson;
 use mongodb::bson::oid::ObjectId;
 use redis::ToRedisArgs;
 use crate::error::{Code, Error};
-use crate::model::{Context, GetAiChatResponseInput, GetAiChatResponseOutput, GetChatStatusTodayOutput, GetUserChatHistoryOutput, Message, MessageRoleType, NewMessage, UserChatMessage};
+use crate::model::{Context, GetAiChatResponseInput, GetAiChatResponseOutput, GetChatStatusTodayOutput, GetUserChatHistoryInput, GetUserChatHistoryOutput, GetUserChatHistoryV2Output, Message, MessageRoleType, NewMessage, Permission, SortOrder, User, UserChatMessage, UserChatMessageV2};
 use crate::providers::Providers;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use crate::store::repository::Direction;
+use crate::telemetry::{self, Span, SpanKind};
+
+/// 每页最多返回的消息数
+const MAX_HISTORY_LIMIT: u64 = 100;
 
 pub struct ChatService {
     ctx: Context,
@@ -26,46 +34,127 @@ impl ChatService {
 
 impl ChatService {
     pub async fn get_ai_chat_response(&self, req: GetAiChatResponseInput) -> Result<GetAiChatResponseOutput, Error> {
-        let limited = self.pvd.chat().check_user_message_limited_in_30_seconds(self.ctx.user.clone()).await
-            .with_context(||format!("check_user_message_limited_in_30_seconds: {:?}", self.ctx.user.clone()))?;
-        if limited {
-            return Err(Error::Unauthorized);
-        }
-        let limited = self.pvd.chat().check_user_message_limited_in_daily(self.ctx.user.clone()).await
-            .with_context(||format!("check_user_message_limited_in_daily: {:?}", self.ctx.user.clone()))?;
-        if limited {
+        let span = Span::start("ChatService.get_ai_chat_response", SpanKind::Internal);
+        span.set_attribute("enduser.id", self.ctx.user.id.clone());
+        span.set_attribute("organization.id", self.ctx.org_id.clone());
+        span.instrument(self.ai_chat_response(req)).await
+    }
+
+    async fn ai_chat_response(&self, req: GetAiChatResponseInput) -> Result<GetAiChatResponseOutput, Error> {
+        // 选择的角色已被删除时不使用角色设定
+        let persona = match self.ctx.user.preferences.default_persona.clone() {
+            Some(name) => self.pvd.persona().get_persona(self.ctx.org_id.clone(), name).await?,
+            None => None,
+        };
+        let (today, _) = self.pvd.chat().get_user_today(self.ctx.user.clone());
+        let reservation = self.pvd.quota().reserve(self.ctx.user.clone(), today.date_naive().to_string()).await
+            .with_context(||format!("reserve quota: {:?}", self.ctx.user.clone()))?;
+        let Some(reservation) = reservation else {
+            telemetry::record("chat.outcome", "rate_limited");
             return Err(Error::Unauthorized);
-        }
+        };
 
         let request_content = req.message;
+        let preferences = self.ctx.user.preferences.clone();
+        // 偏好的模型已不在可选列表中时使用默认模型
+        let model = preferences.default_model.clone()
+            .filter(|model| self.pvd.openrouter().check_model(model.as_str()).is_ok());
         // todo: request conent middle out
-        let response_content = self.pvd.openrouter().chat(request_content.clone()).await
-            .with_context(|| format!("chat: {}", request_content.clone()))?;
+        let started_at = Instant::now();
+        let response = self.pvd.openrouter().chat(model, preferences.system_prompt(persona.as_ref()), request_content.clone()).await
+            .with_context(|| format!("chat: {}", request_content.clone()));
+        let latency_ms = started_at.elapsed().as_millis() as u64;
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                self.pvd.quota().release(reservation).await
+                    .with_context(|| "release quota".to_string())?;
+                return Err(err.into());
+            }
+        };
+        if preferences.disable_history {
+            self.pvd.quota().commit(reservation).await
+                .with_context(|| "commit quota".to_string())?;
+            return Ok(GetAiChatResponseOutput {
+                response: response.content,
+                cached: response.cached,
+            });
+        }
         let now = Utc::now();
         let created_at = NaiveDateTime::new(now.date_naive(), now.time());
         let user_message = NewMessage {
+            org_id: self.ctx.org_id.clone(),
             user_id: self.ctx.user.id.to_string(),
             type_: MessageRoleType::User,
             text: request_content.to_string(),
+            model: None,
+            usage: None,
+            latency_ms: None,
         };
+        // 命中缓存时没有消耗 token, 也不计入生成耗时的统计
         let ai_message = NewMessage {
+            org_id: self.ctx.org_id.clone(),
             user_id: self.ctx.user.id.to_string(),
             type_: MessageRoleType::AI,
-            text: response_content.to_string(),
+            text: response.content.clone(),
+            model: Some(response.model),
+            usage: response.usage.filter(|_| !response.cached),
+            latency_ms: Some(latency_ms).filter(|_| !response.cached),
         };
         let messages = vec![user_message, ai_message];
-        let count = self.pvd.chat().add_chat_message(messages).await
-            .with_context(|| "add_chat_message".to_string())?;
+        let count = match self.pvd.chat().add_chat_message(messages).await {
+            Ok(count) => count,
+            Err(err) => {
+                self.pvd.quota().release(reservation).await
+                    .with_context(|| "release quota".to_string())?;
+                return Err(anyhow::Error::from(err).context("add_chat_message").into());
+            }
+        };
         debug!("Added {count} chat messages");
+        telemetry::record("chat.outcome", "ok");
+        self.pvd.quota().commit(reservation).await
+            .with_context(|| "commit quota".to_string())?;
         let res = GetAiChatResponseOutput {
-            response: response_content,
+            response: response.content,
+            cached: response.cached,
         };
         Ok(res)
     }
 
-    pub async fn get_user_chat_history(&self, last_n: i64) -> Result<GetUserChatHistoryOutput, Error> {
-        let messages = self.pvd.chat().get_user_chat_messages(self.ctx.user.clone(), last_n).await
-            .with_context(||format!("get_user_chat_messages: {:?}", self.ctx.user.clone()))?;
+    /// 指定了其他用户时需要 ReadAnyUserChat 权限
+    async fn get_target_user(&self, user_name: Option<String>) -> Result<User, Error> {
+        if !self.ctx.check_other_user_permission(user_name.as_deref(), Permission::ReadAnyUserChat)? {
+            return Ok(self.ctx.user.clone());
+        }
+        let user_name = user_name.unwrap_or_default();
+        self.pvd.user().get_org_user_by_name(self.ctx.org_id.clone(), user_name.clone()).await
+            .with_context(|| format!("get_user_by_name: {}", user_name))?
+            .ok_or(Error::Feedback(Code::UserNotFound))
+    }
+
+    /// 按 req 的排序返回一页消息和下一页的 cursor
+    async fn get_user_chat_page(&self, req: GetUserChatHistoryInput) -> Result<(Vec<Message>, Option<String>), Error> {
+        let (cursor, direction) = match (req.before, req.after) {
+            (Some(_), Some(_)) => return Err(Error::ParamsError("before 和 after 只能指定一个".to_string())),
+            (Some(before), None) => (Some(before), Direction::Older),
+            (None, Some(after)) => (Some(after), Direction::Newer),
+            (None, None) if req.order == SortOrder::Asc => (None, Direction::Newer),
+            (None, None) => (None, Direction::Older),
+        };
+        let limit = req.limit.clamp(1, MAX_HISTORY_LIMIT);
+        let user = self.get_target_user(req.user_name).await?;
+        // 不包装错误, 游标无效时需要把 Code::MessageNotFound 返回给调用方
+        let (mut messages, next_cursor) = self.pvd.chat().get_user_chat_messages(user, cursor, direction, limit).await?;
+        // 翻页方向与要求的排序相反时, 例如 before + asc, 取到的一页需要倒过来
+        if (direction == Direction::Older) != (req.order == SortOrder::Desc) {
+            messages.reverse();
+        }
+        Ok((messages, next_cursor))
+    }
+
+    /// v1 接口保持返回消息数组, 下一页的游标单独返回, 由路由放在响应头中
+    pub async fn get_user_chat_history(&self, req: GetUserChatHistoryInput) -> Result<(GetUserChatHistoryOutput, Option<String>), Error> {
+        let (messages, next_cursor) = self.get_user_chat_page(req).await?;
         let mut res = vec![];
         for msg in messages.iter() {
             res.push(UserChatMessage {
@@ -73,16 +162,365 @@ impl ChatService {
                 text: msg.text.clone(),
             });
         }
-        Ok(res)
+        Ok((res, next_cursor))
+    }
+
+    pub async fn get_user_chat_history_v2(&self, req: GetUserChatHistoryInput) -> Result<GetUserChatHistoryV2Output, Error> {
+        let (messages, next_cursor) = self.get_user_chat_page(req).await?;
+        Ok(GetUserChatHistoryV2Output {
+            messages: messages.into_iter().map(UserChatMessageV2::from_entity).collect(),
+            next_cursor,
+        })
+    }
+
+    /// 订阅新消息, 指定了其他用户时需要 ReadAnyUserChat 权限
+    pub async fn subscribe_user_chat_messages(&self, user_name: Option<String>, after: Option<String>) -> Result<BoxStream<'static, Result<UserChatMessageV2, Error>>, Error> {
+        let user = self.get_target_user(user_name).await?;
+        // 不包装错误, after 无效时需要把 Code::MessageNotFound 返回给调用方
+        let messages = self.pvd.chat().subscribe_user_chat_messages(user, after).await?;
+        Ok(messages.map(|msg| msg.map(UserChatMessageV2::from_entity)).boxed())
     }
 
-    pub async fn get_chat_status_today(&self) -> Result<GetChatStatusTodayOutput, Error> {
-        let count = self.pvd.chat().get_user_chat_messages_count_today(self.ctx.user.clone()).await
-            .with_context(||format!("get_user_chat_messages_count_today: {:?}", self.ctx.user.clone()))?;
+    pub async fn get_chat_status_today(&self, user_name: Option<String>) -> Result<GetChatStatusTodayOutput, Error> {
+        let user = self.get_target_user(user_name).await?;
+        // 关闭了对话记录的用户没有消息可数, 因此以额度计数为准
+        let (today, next_reset_at) = self.pvd.chat().get_user_today(user.clone());
+        let count = self.pvd.quota().get_used_today(user.clone(), today.date_naive().to_string()).await
+            .with_context(||format!("get_used_today: {:?}", user.clone()))?;
         let res = GetChatStatusTodayOutput {
-            user_name: self.ctx.user.name.clone(),
+            user_name: user.name.clone(),
             chat_cnt: count,
+            timezone: next_reset_at.timezone().name().to_string(),
+            next_reset_at: next_reset_at.fixed_offset(),
         };
         Ok(res)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use async_openai::types::Role as ChatRole;
+    use crate::conf::Config;
+    use crate::model::{DEFAULT_ORGANIZATION_ID, Role, TokenUsage, UserPreferencesPatch};
+    use crate::store::api_client::{ChatCompletion, ChatCompletionMessage, ChatCompletionResult};
+    use crate::store::Store;
+    use super::*;
+
+    /// 记录请求并原样回显的上游
+    #[derive(Default)]
+    struct StubChat {
+        requests: Mutex<Vec<(String, Vec<ChatCompletionMessage>)>>,
+        fail: AtomicBool,
+        /// 返回空回复
+        blank: AtomicBool,
+    }
+
+    #[rocket::async_trait]
+    impl ChatCompletion for StubChat {
+        async fn complete(&self, model: String, messages: Vec<ChatCompletionMessage>) -> Result<ChatCompletionResult, Error> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(Error::UpstreamError("stub failure".to_string()));
+            }
+            let content = messages.last().map(|msg| msg.content.clone()).unwrap_or_default();
+            self.requests.lock().unwrap().push((model.clone(), messages));
+            Ok(ChatCompletionResult {
+                content: if self.blank.load(Ordering::SeqCst) { String::new() } else { format!("echo: {}", content) },
+                model,
+                usage: Some(TokenUsage {
+                    prompt_tokens: 3,
+                    completion_tokens: 5,
+                    total_tokens: 8,
+                }),
+                cached: false,
+            })
+        }
+    }
+
+    async fn setup() -> (Providers, Arc<StubChat>) {
+        setup_with_config(Config::default()).await
+    }
+
+    async fn setup_with_config(config: Config) -> (Providers, Arc<StubChat>) {
+        let chat = Arc::new(StubChat::default());
+        let mut store = Store::memory_for_test(Config {
+            allowed_models: vec!["default-model".to_string(), "other-model".to_string()],
+            chat_model: "default-model".to_string(),
+            ..config
+        }).await;
+        store.api_clients.chat = chat.clone();
+        (Providers::new(&store), chat)
+    }
+
+    async fn service(pvd: &Providers, name: &str, role: Role) -> ChatService {
+        let user = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), name.to_string(), role).await.unwrap();
+        ChatService::new(Context::new(user), pvd.clone())
+    }
+
+    async fn ask(svc: &ChatService, message: &str) -> Result<GetAiChatResponseOutput, Error> {
+        svc.get_ai_chat_response(GetAiChatResponseInput {
+            user_name: None,
+            message: message.to_string(),
+        }).await
+    }
+
+    fn history_input(user_name: Option<&str>) -> GetUserChatHistoryInput {
+        GetUserChatHistoryInput {
+            user_name: user_name.map(|name| name.to_string()),
+            before: None,
+            after: None,
+            limit: 10,
+            order: SortOrder::Desc,
+        }
+    }
+
+    fn texts((messages, _): &(GetUserChatHistoryOutput, Option<String>)) -> Vec<&str> {
+        messages.iter().map(|msg| msg.text.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn reply_is_saved_to_history() {
+        let (pvd, _) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        let res = ask(&svc, "hello").await.unwrap();
+        assert_eq!(res.response, "echo: hello");
+
+        let history = svc.get_user_chat_history(history_input(None)).await.unwrap().0;
+        let texts = history.iter().map(|msg| msg.text.as_str()).collect::<Vec<&str>>();
+        assert_eq!(texts, vec!["echo: hello", "hello"]);
+        assert_eq!(svc.get_chat_status_today(None).await.unwrap().chat_cnt, 1);
+    }
+
+    #[tokio::test]
+    async fn identical_prompts_are_answered_from_cache() {
+        let (pvd, chat) = setup_with_config(Config { response_cache_ttl_secs: 60, ..Default::default() }).await;
+        let alice = service(&pvd, "alice", Role::User).await;
+        let bob = service(&pvd, "bob", Role::User).await;
+        assert!(!ask(&alice, "what is simplylab?").await.unwrap().cached);
+        let res = ask(&bob, " what is  simplylab? ").await.unwrap();
+        assert!(res.cached);
+        assert_eq!(res.response, "echo: what is simplylab?");
+        assert_eq!(chat.requests.lock().unwrap().len(), 1);
+
+        let history = bob.get_user_chat_history_v2(history_input(None)).await.unwrap().messages;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].usage, None);
+        assert_eq!(bob.get_chat_status_today(None).await.unwrap().chat_cnt, 1);
+    }
+
+    #[tokio::test]
+    async fn blank_replies_are_not_cached() {
+        let (pvd, chat) = setup_with_config(Config { response_cache_ttl_secs: 60, ..Default::default() }).await;
+        let alice = service(&pvd, "alice", Role::User).await;
+        chat.blank.store(true, Ordering::SeqCst);
+        assert!(ask(&alice, "hello").await.is_err());
+        assert_eq!(alice.get_chat_status_today(None).await.unwrap().chat_cnt, 0);
+
+        chat.blank.store(false, Ordering::SeqCst);
+        let res = ask(&alice, "hello").await.unwrap();
+        assert!(!res.cached);
+        assert_eq!(res.response, "echo: hello");
+        assert_eq!(chat.requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn burst_limit_rejects_extra_messages() {
+        let (pvd, chat) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        for i in 0..3 {
+            ask(&svc, format!("message {}", i).as_str()).await.unwrap();
+        }
+        assert!(matches!(ask(&svc, "one too many").await, Err(Error::Unauthorized)));
+        assert_eq!(chat.requests.lock().unwrap().len(), 3);
+        assert_eq!(svc.get_chat_status_today(None).await.unwrap().chat_cnt, 3);
+    }
+
+    #[tokio::test]
+    async fn organization_daily_limit_overrides_default() {
+        let (pvd, _) = setup().await;
+        let org = pvd.organization().create_organization("acme".to_string(), Some(2), None).await.unwrap();
+        let user = pvd.user().create_user(org.id, "bob".to_string(), Role::User).await.unwrap();
+        let svc = ChatService::new(Context::new(user), pvd.clone());
+        ask(&svc, "first").await.unwrap();
+        ask(&svc, "second").await.unwrap();
+        assert!(matches!(ask(&svc, "third").await, Err(Error::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn upstream_failure_releases_quota() {
+        let (pvd, chat) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        chat.fail.store(true, Ordering::SeqCst);
+        assert!(ask(&svc, "hello").await.is_err());
+        assert_eq!(svc.get_chat_status_today(None).await.unwrap().chat_cnt, 0);
+        assert!(svc.get_user_chat_history(history_input(None)).await.unwrap().0.is_empty());
+
+        chat.fail.store(false, Ordering::SeqCst);
+        for i in 0..3 {
+            ask(&svc, format!("message {}", i).as_str()).await.unwrap();
+        }
+        assert_eq!(svc.get_chat_status_today(None).await.unwrap().chat_cnt, 3);
+    }
+
+    #[tokio::test]
+    async fn history_opt_out_skips_storage_but_counts_quota() {
+        let (pvd, _) = setup().await;
+        let user = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "alice".to_string(), Role::User).await.unwrap();
+        let preferences = UserPreferencesPatch {
+            disable_history: Some(true),
+            ..Default::default()
+        };
+        let user = pvd.user().update_user_preferences(user, preferences).await.unwrap();
+        let svc = ChatService::new(Context::new(user), pvd.clone());
+        ask(&svc, "secret").await.unwrap();
+        assert!(svc.get_user_chat_history(history_input(None)).await.unwrap().0.is_empty());
+        assert_eq!(svc.get_chat_status_today(None).await.unwrap().chat_cnt, 1);
+    }
+
+    #[tokio::test]
+    async fn preferences_select_model_and_system_prompt() {
+        let (pvd, chat) = setup().await;
+        pvd.persona().create_persona(DEFAULT_ORGANIZATION_ID.to_string(), "tutor".to_string(), "You are a patient math tutor.".to_string()).await.unwrap();
+        let user = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "alice".to_string(), Role::User).await.unwrap();
+        let preferences = UserPreferencesPatch {
+            display_name: Some(Some("Alice".to_string())),
+            default_model: Some(Some("other-model".to_string())),
+            default_persona: Some(Some("tutor".to_string())),
+            ..Default::default()
+        };
+        let user = pvd.user().update_user_preferences(user, preferences).await.unwrap();
+        let svc = ChatService::new(Context::new(user), pvd.clone());
+        ask(&svc, "hi").await.unwrap();
+
+        let requests = chat.requests.lock().unwrap();
+        let (model, messages) = &requests[0];
+        assert_eq!(model, "other-model");
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(messages[0].role, ChatRole::System));
+        assert!(messages[0].content.starts_with("You are a patient math tutor."));
+        assert!(messages[0].content.contains("Alice"));
+    }
+
+    #[tokio::test]
+    async fn reading_other_users_history_needs_permission() {
+        let (pvd, _) = setup().await;
+        let alice = service(&pvd, "alice", Role::User).await;
+        ask(&alice, "hello").await.unwrap();
+
+        let bob = service(&pvd, "bob", Role::User).await;
+        let res = bob.get_user_chat_history(history_input(Some("alice"))).await;
+        assert!(matches!(res, Err(Error::Forbidden)));
+
+        let support = service(&pvd, "support", Role::Support).await;
+        let history = support.get_user_chat_history(history_input(Some("alice"))).await.unwrap().0;
+        assert_eq!(history.len(), 2);
+
+        let org = pvd.organization().create_organization("acme".to_string(), None, None).await.unwrap();
+        let outsider = pvd.user().create_user(org.id, "outsider".to_string(), Role::Admin).await.unwrap();
+        let outsider = ChatService::new(Context::new(outsider), pvd.clone());
+        let res = outsider.get_user_chat_history(history_input(Some("alice"))).await;
+        assert!(matches!(res, Err(Error::Feedback(Code::UserNotFound))));
+    }
+
+    #[tokio::test]
+    async fn history_pages_with_cursor() {
+        let (pvd, _) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        for i in 0..3 {
+            ask(&svc, format!("m{}", i).as_str()).await.unwrap();
+        }
+
+        let page = svc.get_user_chat_history(GetUserChatHistoryInput { limit: 4, ..history_input(None) }).await.unwrap();
+        assert_eq!(texts(&page), vec!["echo: m2", "m2", "echo: m1", "m1"]);
+        let before = page.1.clone();
+        assert!(before.is_some());
+        let page = svc.get_user_chat_history(GetUserChatHistoryInput { limit: 4, before, ..history_input(None) }).await.unwrap();
+        assert_eq!(texts(&page), vec!["echo: m0", "m0"]);
+        assert_eq!(page.1, None);
+
+        let page = svc.get_user_chat_history(GetUserChatHistoryInput { limit: 2, order: SortOrder::Asc, ..history_input(None) }).await.unwrap();
+        assert_eq!(texts(&page), vec!["m0", "echo: m0"]);
+        let after = page.1.clone();
+        let page = svc.get_user_chat_history(GetUserChatHistoryInput { limit: 2, after, order: SortOrder::Asc, ..history_input(None) }).await.unwrap();
+        assert_eq!(texts(&page), vec!["m1", "echo: m1"]);
+    }
+
+    #[tokio::test]
+    async fn history_before_cursor_in_ascending_order() {
+        let (pvd, _) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        for i in 0..3 {
+            ask(&svc, format!("m{}", i).as_str()).await.unwrap();
+        }
+        let newest = svc.get_user_chat_history(GetUserChatHistoryInput { limit: 1, ..history_input(None) }).await.unwrap();
+        let page = svc.get_user_chat_history(GetUserChatHistoryInput {
+            limit: 3,
+            before: newest.1,
+            order: SortOrder::Asc,
+            ..history_input(None)
+        }).await.unwrap();
+        assert_eq!(texts(&page), vec!["m1", "echo: m1", "m2"]);
+        assert!(page.1.is_some());
+    }
+
+    #[tokio::test]
+    async fn history_rejects_bad_cursor() {
+        let (pvd, _) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        let res = svc.get_user_chat_history(GetUserChatHistoryInput {
+            before: Some("a".to_string()),
+            after: Some("b".to_string()),
+            ..history_input(None)
+        }).await;
+        assert!(matches!(res, Err(Error::ParamsError(_))));
+        let res = svc.get_user_chat_history(GetUserChatHistoryInput {
+            before: Some(ObjectId::new().to_hex()),
+            ..history_input(None)
+        }).await;
+        assert!(matches!(res, Err(Error::Feedback(Code::MessageNotFound))));
+    }
+
+    #[tokio::test]
+    async fn history_v2_includes_ids_and_model() {
+        let (pvd, _) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        ask(&svc, "hello").await.unwrap();
+        let res = svc.get_user_chat_history_v2(history_input(None)).await.unwrap();
+        let [ai, user] = res.messages.as_slice() else {
+            panic!("expected 2 messages: {:?}", res.messages);
+        };
+        assert_eq!(ai.type_, MessageRoleType::AI);
+        assert_eq!(ai.model.as_deref(), Some("default-model"));
+        assert_eq!(ai.usage.as_ref().map(|usage| usage.total_tokens), Some(8));
+        assert_eq!(user.type_, MessageRoleType::User);
+        assert_eq!(user.model, None);
+        assert_ne!(ai.id, user.id);
+        assert_eq!(ai.created_at, user.created_at);
+    }
+
+    #[tokio::test]
+    async fn subscribers_on_every_device_receive_new_messages() {
+        let (pvd, _) = setup().await;
+        let svc = service(&pvd, "alice", Role::User).await;
+        ask(&svc, "before").await.unwrap();
+        let mut phone = svc.subscribe_user_chat_messages(None, None).await.unwrap();
+        let mut laptop = svc.subscribe_user_chat_messages(None, None).await.unwrap();
+        ask(&svc, "hello").await.unwrap();
+        for device in [&mut phone, &mut laptop] {
+            let mut texts = vec![];
+            for _ in 0..2 {
+                let msg = tokio::time::timeout(std::time::Duration::from_secs(5), device.next()).await
+                    .unwrap().unwrap().unwrap();
+                texts.push(msg.text);
+            }
+            assert_eq!(texts, vec!["hello", "echo: hello"]);
+        }
+
+        // 重连时补发 after 之后的消息
+        let history = svc.get_user_chat_history_v2(history_input(None)).await.unwrap();
+        let mut resumed = svc.subscribe_user_chat_messages(None, Some(history.messages[2].id.clone())).await.unwrap();
+        let msg = resumed.next().await.unwrap().unwrap();
+        assert_eq!(msg.text, "hello");
+    }
+}
@@ -1,11 +1,17 @@
 use crate::model::Context;
 use crate::providers::Providers;
-use crate::services::ping::PingService;
+use crate::services::admin::AdminService;
+use crate::services::auth::AuthService;
+pub use crate::services::ping::PingService;
 use crate::services::chat::ChatService;
+pub use crate::services::user::UserService;
 use crate::store::Store;
 
 mod ping;
 mod chat;
+mod user;
+mod auth;
+mod admin;
 
 pub struct Services {
     ctx: Context,
@@ -27,4 +33,16 @@ impl Services {
     pub fn chat(&self) -> ChatService {
         ChatService::new(self.ctx.clone(), self.pvd.clone())
     }
+
+    pub fn user(&self) -> UserService {
+        UserService::new(self.ctx.clone(), self.pvd.clone())
+    }
+
+    pub fn auth(&self) -> AuthService {
+        AuthService::new(self.ctx.clone(), self.pvd.clone())
+    }
+
+    pub fn admin(&self) -> AdminService {
+        AdminService::new(self.ctx.clone(), self.pvd.clone())
+    }
 }
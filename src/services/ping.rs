@@ -1,5 +1,4 @@
-use crate::error::Error;
-use crate::model::Context;
+use crate::model::{Context, HealthOutput};
 use crate::providers::Providers;
 
 pub struct PingService {
@@ -17,21 +16,69 @@ impl PingService {
 }
 
 impl PingService {
-    pub async fn do_ping(&self) -> Result<String, Error> {
-        self.pvd.ping().ping_pgsql().await?;
-        self.pvd.ping().ping_redis().await?;
-        Ok("pong".to_string())
+    /// 存活探针: 能处理请求即视为存活, 不检查依赖, 避免依赖故障时实例被反复重启
+    pub fn liveness() -> HealthOutput {
+        HealthOutput {
+            status: "ok".to_string(),
+            checks: vec![],
+        }
+    }
+
+    /// 就绪探针: 所有依赖都可用时才接收流量, 无需登录
+    pub async fn readiness(pvd: Providers) -> HealthOutput {
+        let checks = pvd.ping().check_dependencies().await;
+        let status = if checks.iter().all(|check| check.ok) { "ok" } else { "unavailable" };
+        HealthOutput {
+            status: status.to_string(),
+            checks,
+        }
     }
-    pub async fn do_ping_mysql(&self) -> Result<String, Error> {
-        self.pvd.ping().ping_mysql().await?;
-        Ok("pong".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::conf::Config;
+    use crate::error::Error;
+    use crate::store::api_client::{ChatCompletion, ChatCompletionMessage, ChatCompletionResult};
+    use crate::store::Store;
+    use super::*;
+
+    struct DownChat;
+
+    #[rocket::async_trait]
+    impl ChatCompletion for DownChat {
+        async fn complete(&self, _model: String, _messages: Vec<ChatCompletionMessage>) -> Result<ChatCompletionResult, Error> {
+            Err(Error::UpstreamError("down".to_string()))
+        }
+
+        async fn ping(&self) -> Result<(), Error> {
+            Err(Error::UpstreamError("down".to_string()))
+        }
     }
-    pub async fn do_ping_pgsql(&self) -> Result<String, Error> {
-        self.pvd.ping().ping_pgsql().await?;
-        Ok("pong".to_string())
+
+    async fn providers(readiness_check_upstream: bool) -> Providers {
+        let mut store = Store::memory_for_test(Config {
+            database_backend: "memory".to_string(),
+            readiness_check_upstream,
+            ..Default::default()
+        }).await;
+        store.api_clients.chat = Arc::new(DownChat);
+        Providers::new(&store)
     }
-    pub async fn do_ping_redis(&self) -> Result<String, Error> {
-        self.pvd.ping().ping_redis().await?;
-        Ok("pong".to_string())
+
+    #[tokio::test]
+    async fn readiness_reports_each_dependency() {
+        let res = PingService::readiness(providers(false).await).await;
+        assert_eq!(res.status, "ok");
+        let names = res.checks.iter().map(|check| (check.name.as_str(), check.backend.as_str())).collect::<Vec<_>>();
+        assert_eq!(names, vec![("database", "memory"), ("cache", "memory")]);
+
+        let res = PingService::readiness(providers(true).await).await;
+        assert_eq!(res.status, "unavailable");
+        let upstream = res.checks.last().unwrap();
+        assert_eq!(upstream.name, "upstream");
+        assert!(!upstream.ok);
+        assert_eq!(upstream.error.as_deref(), Some("不可用"));
     }
 }
@@ -0,0 +1,199 @@
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
+use crate::error::{Code, Error};
+use crate::model::{Context, CreateApiKeyInput, ListPersonasOutput, RegisterUserInput, RegisterUserOutput, Role, SetUserTimezoneInput, UpdateUserPreferencesInput, User, UserPreferences, UserPreferencesPatch, DEFAULT_ORGANIZATION_ID};
+use crate::services::Services;
+use crate::providers::Providers;
+
+lazy_static! {
+    static ref LOCALE_RE: Regex = Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8})*$").unwrap();
+}
+
+/// 显示名, 回复语言等短文本的最大长度
+const MAX_PREFERENCE_LEN: usize = 64;
+
+pub struct UserService {
+    ctx: Context,
+    pvd: Providers,
+}
+
+impl UserService {
+    pub fn new(context: Context, providers: Providers) -> Self {
+        Self {
+            ctx: context,
+            pvd: providers,
+        }
+    }
+}
+
+impl UserService {
+    /// 在默认组织注册用户并创建首个 API Key, 注册时还没有 Context
+    pub async fn register_user(pvd: Providers, req: RegisterUserInput) -> Result<RegisterUserOutput, Error> {
+        let name = User::validate_name(req.name.as_str())?;
+        if pvd.user().is_reserved_admin_name(name.as_str()) {
+            return Err(Error::Feedback(Code::UserAlreadyExists));
+        }
+        let user = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), name, Role::User).await?;
+        let svc = Services::new(Context::new(user.clone()), pvd);
+        let res = svc.auth().create_api_key(CreateApiKeyInput { name: "default".to_string() }).await?;
+        Ok(RegisterUserOutput {
+            user,
+            api_key: res.api_key,
+            key: res.key,
+        })
+    }
+
+    pub async fn set_user_timezone(&self, req: SetUserTimezoneInput) -> Result<User, Error> {
+        let timezone = req.timezone.parse::<Tz>()
+            .map_err(|_| Error::ParamsError(format!("无法识别的时区: {}", req.timezone)))?;
+        // 不包装错误, 修改过于频繁时的参数错误直接返回给用户
+        self.pvd.user().update_user_timezone(self.ctx.user.clone(), timezone.name().to_string()).await
+    }
+
+    pub async fn get_user_preferences(&self) -> Result<UserPreferences, Error> {
+        Ok(self.ctx.user.preferences.clone())
+    }
+
+    /// 当前组织内可以选择的角色
+    pub async fn list_personas(&self) -> Result<ListPersonasOutput, Error> {
+        self.pvd.persona().list_personas(self.ctx.org_id.clone()).await
+    }
+
+    /// 只修改传入的项, 传空字符串表示清除该项
+    pub async fn update_user_preferences(&self, req: UpdateUserPreferencesInput) -> Result<UserPreferences, Error> {
+        let mut patch = UserPreferencesPatch::default();
+        if let Some(display_name) = req.display_name {
+            patch.display_name = Some(check_text("display_name", display_name, MAX_PREFERENCE_LEN)?);
+        }
+        if let Some(locale) = req.locale {
+            let locale = check_text("locale", locale, MAX_PREFERENCE_LEN)?;
+            if let Some(locale) = &locale {
+                if !LOCALE_RE.is_match(locale) {
+                    return Err(Error::ParamsError(format!("无法识别的语言标签: {}", locale)));
+                }
+            }
+            patch.locale = Some(locale);
+        }
+        if let Some(timezone) = req.timezone {
+            patch.timezone = Some(match check_text("timezone", timezone, MAX_PREFERENCE_LEN)? {
+                Some(timezone) => Some(timezone.parse::<Tz>()
+                    .map_err(|_| Error::ParamsError(format!("无法识别的时区: {}", timezone)))?
+                    .name().to_string()),
+                None => None,
+            });
+        }
+        if let Some(default_model) = req.default_model {
+            let default_model = check_text("default_model", default_model, MAX_PREFERENCE_LEN)?;
+            if let Some(default_model) = &default_model {
+                self.pvd.openrouter().check_model(default_model.as_str())?;
+            }
+            patch.default_model = Some(default_model);
+        }
+        if let Some(default_persona) = req.default_persona {
+            let default_persona = check_text("default_persona", default_persona, MAX_PREFERENCE_LEN)?;
+            if let Some(default_persona) = &default_persona {
+                self.pvd.persona().get_persona(self.ctx.org_id.clone(), default_persona.clone()).await?
+                    .ok_or(Error::Feedback(Code::PersonaNotFound))?;
+            }
+            patch.default_persona = Some(default_persona);
+        }
+        if let Some(response_language) = req.response_language {
+            patch.response_language = Some(check_text("response_language", response_language, MAX_PREFERENCE_LEN)?);
+        }
+        if let Some(disable_history) = req.disable_history {
+            patch.disable_history = Some(disable_history);
+        }
+        let user = self.pvd.user().update_user_preferences(self.ctx.user.clone(), patch).await?;
+        Ok(user.preferences)
+    }
+}
+
+/// 去掉首尾空白, 空字符串视为清除
+fn check_text(name: &str, value: String, max_len: usize) -> Result<Option<String>, Error> {
+    let value = value.trim();
+    if value.chars().count() > max_len {
+        return Err(Error::ParamsError(format!("{} 不能超过 {} 个字符", name, max_len)));
+    }
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::Config;
+    use crate::store::Store;
+    use super::*;
+
+    #[tokio::test]
+    async fn admin_user_names_cannot_be_squatted() {
+        let store = Store::memory_for_test(Config {
+            admin_users: vec!["root".to_string(), "boss".to_string()],
+            ..Default::default()
+        }).await;
+        let pvd = Providers::new(&store);
+        let register = |name: &str| UserService::register_user(pvd.clone(), RegisterUserInput { name: name.to_string() });
+        assert!(matches!(register("root").await, Err(Error::Feedback(Code::UserAlreadyExists))));
+
+        // 配置 ADMIN_USERS 之前已注册的同名用户不会被提升为 admin
+        let boss = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "boss".to_string(), Role::User).await.unwrap();
+        let created = pvd.user().seed_admin_users().await.unwrap();
+        assert_eq!(created.iter().map(|user| (user.name.as_str(), user.role)).collect::<Vec<_>>(), vec![("root", Role::Admin)]);
+        let boss = pvd.user().get_user_by_id(boss.id).await.unwrap().unwrap();
+        assert_eq!(boss.role, Role::User);
+        assert!(pvd.user().seed_admin_users().await.unwrap().is_empty());
+        assert!(register("alice").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn preference_updates_merge_onto_the_latest_user() {
+        let store = Store::memory_for_test(Config::default()).await;
+        let pvd = Providers::new(&store);
+        let user = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "alice".to_string(), Role::User).await.unwrap();
+        // 两个请求都持有修改前的用户
+        let first = UserService::new(Context::new(user.clone()), pvd.clone());
+        let second = UserService::new(Context::new(user), pvd.clone());
+        first.set_user_timezone(SetUserTimezoneInput { timezone: "Asia/Shanghai".to_string() }).await.unwrap();
+        first.update_user_preferences(UpdateUserPreferencesInput {
+            display_name: Some("Alice".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        let preferences = second.update_user_preferences(UpdateUserPreferencesInput {
+            locale: Some("zh-CN".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(preferences.timezone.as_deref(), Some("Asia/Shanghai"));
+        assert_eq!(preferences.display_name.as_deref(), Some("Alice"));
+        assert_eq!(preferences.locale.as_deref(), Some("zh-CN"));
+
+        let preferences = second.update_user_preferences(UpdateUserPreferencesInput {
+            display_name: Some(" ".to_string()),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(preferences.display_name, None);
+        assert_eq!(preferences.locale.as_deref(), Some("zh-CN"));
+    }
+
+    #[tokio::test]
+    async fn timezone_changes_are_throttled() {
+        let store = Store::memory_for_test(Config::default()).await;
+        let pvd = Providers::new(&store);
+        let user = pvd.user().create_user(DEFAULT_ORGANIZATION_ID.to_string(), "alice".to_string(), Role::User).await.unwrap();
+        let svc = UserService::new(Context::new(user.clone()), pvd.clone());
+        let set = |timezone: &str| svc.set_user_timezone(SetUserTimezoneInput { timezone: timezone.to_string() });
+        set("Pacific/Kiritimati").await.unwrap();
+        // 与当前相同不算修改
+        set("Pacific/Kiritimati").await.unwrap();
+        assert!(matches!(set("Etc/GMT+12").await, Err(Error::ParamsError(_))));
+        let res = svc.update_user_preferences(UpdateUserPreferencesInput {
+            timezone: Some("".to_string()),
+            ..Default::default()
+        }).await;
+        assert!(matches!(res, Err(Error::ParamsError(_))));
+        let user = pvd.user().get_user_by_id(user.id).await.unwrap().unwrap();
+        assert_eq!(user.preferences.timezone.as_deref(), Some("Pacific/Kiritimati"));
+    }
+}
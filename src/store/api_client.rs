@@ -1,21 +1,134 @@
+use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::Context;
+use async_openai::types::Role;
 use reqwest;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
 use url::Url;
 use crate::conf::Config;
+use crate::logger::{current_request_id, REQUEST_ID_HEADER};
+use crate::telemetry::{current_traceparent, TRACEPARENT_HEADER};
 use crate::error::Error;
+use crate::model::TokenUsage;
 
 #[derive(Clone)]
 pub struct ApiClients {
+    pub chat: Arc<dyn ChatCompletion>,
 }
 
 impl ApiClients {
     pub fn new(config: Config) -> Self {
         ApiClients {
+            chat: Arc::new(OpenRouterClient::new(config.openrouter_api_key)),
         }
     }
 }
 
+/// 对话补全的上游服务, 返回 AI 的回复
+#[rocket::async_trait]
+pub trait ChatCompletion: Send + Sync {
+    async fn complete(&self, model: String, messages: Vec<ChatCompletionMessage>) -> Result<ChatCompletionResult, Error>;
+
+    /// 探测上游是否可用, 不消耗 token; 不支持探测时视为可用
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionResult {
+    pub content: String,
+    /// 上游实际使用的模型, 可能与请求的模型不同
+    pub model: String,
+    pub usage: Option<TokenUsage>,
+    /// 命中响应缓存, 没有请求上游
+    #[serde(skip)]
+    pub cached: bool,
+}
+
+pub type ChatCompletionMessage = OpenRouterCreateChatCompletionRequestArgsMessage;
+
+impl TryFrom<OpenRouterCreateChatCompletionResponse> for ChatCompletionResult {
+    type Error = Error;
+
+    /// 没有 choices 或第一个 choice 没有内容时视为上游错误
+    fn try_from(response: OpenRouterCreateChatCompletionResponse) -> Result<Self, Error> {
+        let content = response.choices.into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or(Error::UpstreamError(format!("openrouter 没有返回回复内容: {}", response.id)))?;
+        Ok(ChatCompletionResult {
+            content,
+            model: response.model,
+            usage: response.usage.map(|usage| TokenUsage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                total_tokens: usage.total_tokens,
+            }),
+            cached: false,
+        })
+    }
+}
+
+pub struct OpenRouterClient {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl OpenRouterClient {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl ChatCompletion for OpenRouterClient {
+    async fn complete(&self, model: String, messages: Vec<ChatCompletionMessage>) -> Result<ChatCompletionResult, Error> {
+        let url = "https://openrouter.ai/api/v1/chat/completions";
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", format!("Bearer {}", self.api_key).parse()?);
+        headers.insert("Content-Type", "application/json".parse()?);
+        if let Some(request_id) = current_request_id() {
+            headers.insert(REQUEST_ID_HEADER, request_id.parse()?);
+        }
+        if let Some(traceparent) = current_traceparent() {
+            headers.insert(TRACEPARENT_HEADER, traceparent.parse()?);
+        }
+        let body = OpenRouterCreateChatCompletionRequestArgs {
+            model,
+            messages,
+        };
+        let response = self.client.post(url).headers(headers).json(&body)
+            .send().await.with_context(|| "send request to openrouter".to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            // 错误详情只记录在日志中, 不返回给用户
+            let body = response.text().await.unwrap_or_default();
+            warn!("openrouter responded {}: {}", status, body);
+            return Err(Error::UpstreamError(format!("openrouter 返回 {}", status)));
+        }
+        let response: OpenRouterCreateChatCompletionResponse = response
+            .json().await.with_context(|| "deserialize from openrouter".to_string())?;
+        debug!("response: {:?}", response);
+        ChatCompletionResult::try_from(response)
+    }
+
+    /// 查询 API Key 的信息, 同时校验网络和 Key
+    async fn ping(&self) -> Result<(), Error> {
+        let url = "https://openrouter.ai/api/v1/auth/key";
+        self.client.get(url).bearer_auth(self.api_key.as_str())
+            .send().await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| Error::UpstreamError(err.to_string()))?;
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct HttpClient {
     host: Url,
@@ -26,7 +139,7 @@ pub struct HttpClient {
 impl HttpClient {
     pub fn new(host: String, location: String) -> Self {
         let host = Url::parse(host.as_str()).expect("Invalid host");
-        println!("{:?}", host);
+        debug!("{:?}", host);
         HttpClient {
             host,
             location,
@@ -50,7 +163,76 @@ impl HttpClient {
             path.as_str()
         );
         let url = Url::parse(url.as_str())?;
-        println!("{:?}", url.to_string());
+        debug!("{:?}", url.to_string());
         Ok(url)
     }
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpenRouterCreateChatCompletionRequestArgsMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpenRouterCreateChatCompletionRequestArgs {
+    pub model: String,
+    pub messages: Vec<OpenRouterCreateChatCompletionRequestArgsMessage>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpenRouterChatChoiceMessage {
+    pub role: Role,
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpenRouterChatChoice {
+    pub message: OpenRouterChatChoiceMessage,
+}
+
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpenRouterCompletionUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+    /// 并非所有模型都返回
+    pub total_cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpenRouterCreateChatCompletionResponse {
+    pub id: String,
+    pub model: String,
+    pub created: u32,
+    pub object: String,
+    pub choices: Vec<OpenRouterChatChoice>,
+    pub usage: Option<OpenRouterCompletionUsage>,
+}
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn response(choices: serde_json::Value) -> OpenRouterCreateChatCompletionResponse {
+        serde_json::from_value(json!({
+            "id": "gen-1",
+            "model": "m",
+            "created": 0,
+            "object": "chat.completion",
+            "choices": choices,
+            "usage": null,
+        })).unwrap()
+    }
+
+    #[test]
+    fn replies_without_content_are_upstream_errors() {
+        let res = ChatCompletionResult::try_from(response(json!([])));
+        assert!(matches!(res, Err(Error::UpstreamError(_))));
+        let res = ChatCompletionResult::try_from(response(json!([{"message": {"role": "assistant", "content": null}}])));
+        assert!(matches!(res, Err(Error::UpstreamError(_))));
+        let res = ChatCompletionResult::try_from(response(json!([{"message": {"role": "assistant", "content": "hi"}}]))).unwrap();
+        assert_eq!(res.content, "hi");
+    }
+}
@@ -1,24 +1,198 @@
-use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
+use log::info;
+use lru_cache::LruCache;
 use redis::aio::ConnectionManager;
 use redis::Client;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use crate::conf::Config;
+use crate::error::Error;
 
+/// 缓存, 配置了 REDIS_URL 时使用 Redis (多个实例共享), 否则使用进程内的 LRU
 #[derive(Clone)]
 pub struct Caches {
-    // pub default: ConnectionManager,
+    backend: CacheBackend,
+    /// 所有键的前缀, 多个服务共用一个 Redis 时避免冲突
+    prefix: String,
+}
+
+#[derive(Clone)]
+enum CacheBackend {
+    Redis(ConnectionManager),
+    Memory(Arc<Mutex<LruCache<String, MemoryEntry>>>),
+}
+
+struct MemoryEntry {
+    value: String,
+    expires_at: Instant,
 }
 
 impl Caches {
-    pub async fn new(config: Config) -> Self {
-        println!("Caches init");
+    pub async fn new(config: Config) -> Result<Self, Error> {
+        info!("Caches init");
+        if config.redis_url.is_empty() {
+            return Ok(Self::memory(config.cache_key_prefix, config.cache_memory_capacity));
+        }
+        Ok(Caches {
+            backend: CacheBackend::Redis(connect(config.redis_url.as_str()).await?),
+            prefix: config.cache_key_prefix,
+        })
+    }
+
+    /// 进程内的 LRU, 最多保存 capacity 个键, 用于未配置 Redis 和测试
+    pub fn memory(prefix: String, capacity: usize) -> Self {
         Caches {
-            // default: connect(config).await,
+            backend: CacheBackend::Memory(Arc::new(Mutex::new(LruCache::new(capacity.max(1))))),
+            prefix,
+        }
+    }
+
+    /// 键为 `{prefix}:{namespace}:{key}` 的缓存
+    pub fn namespace(&self, namespace: &str) -> Cache {
+        Cache {
+            backend: self.backend.clone(),
+            prefix: format!("{}:{}:", self.prefix, namespace),
         }
     }
+
+    /// redis 或 memory
+    pub fn backend_name(&self) -> &'static str {
+        match self.backend {
+            CacheBackend::Redis(_) => "redis",
+            CacheBackend::Memory(_) => "memory",
+        }
+    }
+
+    /// 进程内的 LRU 总是可用
+    pub async fn ping(&self) -> Result<(), Error> {
+        if let CacheBackend::Redis(conn) = &self.backend {
+            redis::cmd("PING").query_async::<_, String>(&mut conn.clone()).await
+                .map_err(|err| Error::DatabasePingError(format!("Redis: {}", err)))?;
+        }
+        Ok(())
+    }
+
+    /// 组织内用户名到用户 id 的映射
+    pub fn user_id(&self) -> Cache {
+        self.namespace("user_id")
+    }
+
+    /// 按请求内容缓存的上游回复
+    pub fn chat_response(&self) -> Cache {
+        self.namespace("chat_response")
+    }
 }
 
-async fn connect(config: Config) -> ConnectionManager {
-    let client = Client::open(config.redis_url).unwrap();
-    ConnectionManager::new(client).await.unwrap()
+/// 同一命名空间下的缓存, 值序列化为 JSON 保存
+#[derive(Clone)]
+pub struct Cache {
+    backend: CacheBackend,
+    prefix: String,
+}
+
+impl Cache {
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// 不存在, 已过期或无法反序列化(如结构已变更)时返回 None
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, Error> {
+        let key = self.key(key);
+        let value: Option<String> = match &self.backend {
+            CacheBackend::Redis(conn) => {
+                redis::cmd("GET").arg(key.as_str())
+                    .query_async(&mut conn.clone()).await
+                    .with_context(|| format!("redis GET {}", key))?
+            }
+            CacheBackend::Memory(lru) => {
+                let mut lru = lru.lock().unwrap();
+                match lru.get_mut(key.as_str()) {
+                    Some(entry) if entry.expires_at > Instant::now() => Some(entry.value.clone()),
+                    Some(_) => {
+                        lru.remove(key.as_str());
+                        None
+                    }
+                    None => None,
+                }
+            }
+        };
+        Ok(value.and_then(|value| serde_json::from_str(value.as_str()).ok()))
+    }
+
+    /// ttl 为 0 时不写入
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T, ttl: Duration) -> Result<(), Error> {
+        if ttl.is_zero() {
+            return Ok(());
+        }
+        let key = self.key(key);
+        let value = serde_json::to_string(value)?;
+        match &self.backend {
+            CacheBackend::Redis(conn) => {
+                redis::cmd("SET").arg(key.as_str()).arg(value).arg("PX").arg(ttl.as_millis() as u64)
+                    .query_async::<_, ()>(&mut conn.clone()).await
+                    .with_context(|| format!("redis SET {}", key))?;
+            }
+            CacheBackend::Memory(lru) => {
+                lru.lock().unwrap().insert(key, MemoryEntry {
+                    value,
+                    expires_at: Instant::now() + ttl,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), Error> {
+        let key = self.key(key);
+        match &self.backend {
+            CacheBackend::Redis(conn) => {
+                redis::cmd("DEL").arg(key.as_str())
+                    .query_async::<_, ()>(&mut conn.clone()).await
+                    .with_context(|| format!("redis DEL {}", key))?;
+            }
+            CacheBackend::Memory(lru) => {
+                lru.lock().unwrap().remove(key.as_str());
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn connect(redis_url: &str) -> Result<ConnectionManager, Error> {
+    let client = Client::open(redis_url)
+        .map_err(|err| Error::Misconfigured(format!("REDIS_URL 无效: {}", err)))?;
+    let mut conn = ConnectionManager::new(client).await
+        .map_err(|err| Error::DatabaseConnectionError(format!("无法连接 Redis: {}", err)))?;
+    redis::cmd("PING").query_async::<_, String>(&mut conn).await
+        .map_err(|err| Error::DatabasePingError(format!("Redis: {}", err)))?;
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_cache_expires_and_evicts_least_recently_used() {
+        let caches = Caches::memory("test".to_string(), 2);
+        let users = caches.user_id();
+        let ttl = Duration::from_secs(60);
+        users.set("alice", &1, ttl).await.unwrap();
+        users.set("bob", &2, ttl).await.unwrap();
+        // 同名的键在不同命名空间互不影响
+        assert_eq!(caches.namespace("other").get::<i32>("alice").await.unwrap(), None);
+        assert_eq!(users.get::<i32>("alice").await.unwrap(), Some(1));
+        users.set("carol", &3, ttl).await.unwrap();
+        assert_eq!(users.get::<i32>("bob").await.unwrap(), None);
+        assert_eq!(users.get::<i32>("alice").await.unwrap(), Some(1));
+
+        users.delete("alice").await.unwrap();
+        assert_eq!(users.get::<i32>("alice").await.unwrap(), None);
+        users.set("dave", &4, Duration::from_millis(1)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(users.get::<i32>("dave").await.unwrap(), None);
+    }
 }
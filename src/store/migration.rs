@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use anyhow::Context;
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
+use log::{info, warn};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{Bson, DateTime, Document, doc};
+use mongodb::IndexModel;
+use mongodb::options::IndexOptions;
+use crate::error::Error;
+use crate::model::{MAX_USER_NAME_LEN, MigrationDoc, User};
+use crate::store::database::{Databases, is_duplicate_key_error};
+
+/// 一次 MongoDB 迁移, 目前的迁移都是创建索引.
+/// 已发布的迁移不能修改或删除, 新的迁移追加在 MIGRATIONS 末尾并使用更大的 version
+struct Migration {
+    version: i64,
+    name: &'static str,
+    collection: &'static str,
+    keys: fn() -> Document,
+    unique: bool,
+    /// 创建索引前执行, 如修正会导致唯一索引创建失败的已有数据
+    prepare: Option<Prepare>,
+}
+
+type Prepare = fn(&Databases) -> BoxFuture<'_, Result<(), Error>>;
+
+const MIGRATIONS: &[Migration] = &[
+    // 用户名在组织内唯一, 避免并发的首次请求创建出重名用户
+    Migration {
+        version: 1,
+        name: "user_org_id_name_unique",
+        collection: "user",
+        keys: || doc! {"org_id": 1, "name": 1},
+        unique: true,
+        prepare: Some(rename_duplicate_user_names),
+    },
+    Migration {
+        version: 2,
+        name: "organization_name_unique",
+        collection: "organization",
+        keys: || doc! {"name": 1},
+        unique: true,
+        prepare: None,
+    },
+    // 聊天记录: find({user_id}).sort({created_at: -1})
+    Migration {
+        version: 3,
+        name: "message_user_id_created_at",
+        collection: "message",
+        keys: || doc! {"user_id": 1, "created_at": -1},
+        unique: false,
+        prepare: None,
+    },
+    // 今日状态和额度: 按 type 和 created_at 计数
+    Migration {
+        version: 4,
+        name: "message_user_id_type_created_at",
+        collection: "message",
+        keys: || doc! {"user_id": 1, "type": 1, "created_at": -1},
+        unique: false,
+        prepare: None,
+    },
+    // 组织用量统计
+    Migration {
+        version: 5,
+        name: "message_org_id_created_at",
+        collection: "message",
+        keys: || doc! {"org_id": 1, "created_at": 1},
+        unique: false,
+        prepare: None,
+    },
+    // 每个请求都按 key_hash 查找 API Key
+    Migration {
+        version: 6,
+        name: "api_key_key_hash_unique",
+        collection: "api_key",
+        keys: || doc! {"key_hash": 1},
+        unique: true,
+        prepare: None,
+    },
+    // 角色名在组织内唯一
+    Migration {
+        version: 7,
+        name: "persona_org_id_name_unique",
+        collection: "persona",
+        keys: || doc! {"org_id": 1, "name": 1},
+        unique: true,
+        prepare: None,
+    },
+];
+
+impl Databases {
+    /// 按 version 顺序执行 _migrations 中没有记录的迁移, 返回本次执行的迁移名.
+    /// 创建索引是幂等的, 多个实例同时启动时重复执行不会出错
+    pub async fn migrate(&self) -> Result<Vec<String>, Error> {
+        let mut cursor = self.migration().find(None, None).await
+            .with_context(|| "find _migrations".to_string())?;
+        let mut applied = HashSet::new();
+        while let Some(doc) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            applied.insert(doc._id);
+        }
+        let mut names = vec![];
+        for migration in MIGRATIONS.iter().filter(|migration| !applied.contains(&migration.version)) {
+            info!("apply migration {}: {}", migration.version, migration.name);
+            if let Some(prepare) = migration.prepare {
+                prepare(self).await
+                    .with_context(|| format!("prepare migration {}: {}", migration.version, migration.name))?;
+            }
+            let index = IndexModel::builder()
+                .keys((migration.keys)())
+                .options(IndexOptions::builder().unique(migration.unique).build())
+                .build();
+            self.default.collection::<Document>(migration.collection).create_index(index, None).await
+                .with_context(|| format!("migration {}: {}", migration.version, migration.name))?;
+            let doc = MigrationDoc {
+                _id: migration.version,
+                name: migration.name.to_string(),
+                applied_at: DateTime::now(),
+            };
+            match self.migration().insert_one(doc, None).await {
+                Ok(_) => {}
+                // 其他实例已经记录了同一个迁移
+                Err(err) if is_duplicate_key_error(&err) => {}
+                Err(err) => return Err(anyhow::Error::from(err).context("insert _migrations").into()),
+            }
+            names.push(migration.name.to_string());
+        }
+        Ok(names)
+    }
+}
+
+/// 唯一索引之前, 并发的首次请求可能已在同一组织内创建出重名用户: 每组保留最早创建的用户, 其余改名并记录日志,
+/// 否则索引无法创建, 每次启动都会失败
+fn rename_duplicate_user_names(db: &Databases) -> BoxFuture<'_, Result<(), Error>> {
+    Box::pin(async move {
+        let users = db.default.collection::<Document>("user");
+        let pipeline = vec![
+            doc! {"$group": {"_id": {"org_id": "$org_id", "name": "$name"}, "ids": {"$push": "$_id"}, "count": {"$sum": 1}}},
+            doc! {"$match": {"count": {"$gt": 1}}},
+        ];
+        let mut cursor = users.aggregate(pipeline, None).await
+            .with_context(|| "aggregate duplicate user names".to_string())?;
+        let mut duplicates = vec![];
+        while let Some(group) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            let key = group.get_document("_id").cloned().unwrap_or_default();
+            // 没有 org_id 的用户属于默认组织, {"org_id": null} 同样能匹配到
+            let org_id = key.get("org_id").cloned().unwrap_or(Bson::Null);
+            let name = key.get_str("name").unwrap_or_default().to_string();
+            let ids = group.get_array("ids").map(|ids| ids.iter().filter_map(|id| id.as_object_id()).collect()).unwrap_or_default();
+            duplicates.push((org_id, name, ids));
+        }
+        for (org_id, name, ids) in duplicates {
+            let taken = users.distinct("name", doc! {"org_id": org_id.clone()}, None).await
+                .with_context(|| format!("distinct user names in organization {}", org_id))?;
+            let mut taken = taken.iter().filter_map(|name| name.as_str()).map(|name| name.to_string()).collect::<HashSet<String>>();
+            for (id, new_name) in duplicate_name_renames(name.as_str(), ids, &mut taken) {
+                warn!("用户名重复, 用户 {} 由 {} 改名为 {}", id, name, new_name);
+                users.update_one(doc! {"_id": id}, doc! {"$set": {"name": new_name.as_str()}}, None).await
+                    .with_context(|| format!("rename user {} to {}", id, new_name))?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// 重名用户中 ObjectId 最小(最早创建)的保留原名, 其余改名为 `{name}-{n}`, n 取组织内未被占用的最小值(从 2 开始).
+/// 原名中不允许的字符会被去掉, 过长时截断, 新名字能通过 User::validate_name
+fn duplicate_name_renames(name: &str, mut ids: Vec<ObjectId>, taken: &mut HashSet<String>) -> Vec<(ObjectId, String)> {
+    let mut renames = vec![];
+    let mut n = 2;
+    ids.sort();
+    for id in ids.into_iter().skip(1) {
+        let new_name = loop {
+            let new_name = User::name_with_suffix(name, format!("-{}", n).as_str());
+            n += 1;
+            if !taken.contains(&new_name) {
+                break new_name;
+            }
+        };
+        taken.insert(new_name.clone());
+        renames.push((id, new_name));
+    }
+    renames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_increasing() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version, "{} >= {}", pair[0].version, pair[1].version);
+        }
+    }
+
+    #[test]
+    fn duplicate_user_names_keep_the_oldest_user() {
+        let ids = (1..=3).map(|i| ObjectId::parse_str(format!("00000000000000000000000{}", i)).unwrap()).collect::<Vec<_>>();
+        let mut taken = HashSet::from(["alice".to_string(), "alice-2".to_string()]);
+        let renames = duplicate_name_renames("alice", vec![ids[2], ids[0], ids[1]], &mut taken);
+        assert_eq!(renames, vec![
+            (ids[1], "alice-3".to_string()),
+            (ids[2], "alice-4".to_string()),
+        ]);
+        assert!(taken.contains("alice-4"));
+
+        let long = "a".repeat(MAX_USER_NAME_LEN);
+        let renames = duplicate_name_renames(long.as_str(), vec![ids[0], ids[1]], &mut HashSet::new());
+        assert_eq!(renames[0].1, format!("{}-2", "a".repeat(MAX_USER_NAME_LEN - 2)));
+        for (_, name) in renames.iter().chain(duplicate_name_renames("x", vec![ids[0], ids[1]], &mut HashSet::new()).iter()) {
+            assert_eq!(User::validate_name(name).unwrap(), *name);
+        }
+    }
+}
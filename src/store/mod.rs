@@ -1,28 +1,67 @@
 use crate::conf::Config;
+use crate::error::Error;
 use crate::store::api_client::ApiClients;
 use crate::store::cache::Caches;
 use crate::store::database::Databases;
+use crate::store::postgres::PgDatabases;
+use crate::store::repository::Repositories;
 
 pub mod api_client;
 pub mod cache;
 pub mod database;
+pub mod migration;
+pub mod postgres;
+pub mod repository;
 
 #[derive(Clone)]
 pub struct Store {
     pub config: Config,
-    pub databases: Databases,
+    pub repositories: Repositories,
     pub caches: Caches,
     pub api_clients: ApiClients,
 }
 
 impl Store {
-    pub async fn new() -> Self {
-        let config = Config::new().await;
-        Store {
+    pub async fn new() -> Result<Self, Error> {
+        let config = Config::new().await?;
+        let repositories = match config.database_backend.as_str() {
+            "memory" => Repositories::memory(),
+            "mongo" => {
+                let db = Databases::new(config.clone()).await?;
+                if config.migrate_on_startup {
+                    db.migrate().await?;
+                }
+                Repositories::mongo(db)
+            }
+            "postgres" => {
+                let db = PgDatabases::new(config.clone()).await?;
+                if config.migrate_on_startup {
+                    db.migrate().await?;
+                }
+                Repositories::postgres(db)
+            }
+            backend => return Err(Error::Misconfigured(format!("不支持的 DATABASE_BACKEND: {}", backend))),
+        };
+        Ok(Store {
             config: config.clone(),
-            databases: Databases::new(config.clone()).await,
-            caches: Caches::new(config.clone()).await,
+            repositories,
+            caches: Caches::new(config.clone()).await?,
             api_clients: ApiClients::new(config.clone()),
-        }
+        })
+    }
+}
+
+#[cfg(test)]
+impl Store {
+    /// 测试用的内存存储和缓存, 已创建默认组织; 需要替换上游时修改 api_clients.chat
+    pub async fn memory_for_test(config: Config) -> Self {
+        let store = Store {
+            api_clients: ApiClients::new(config.clone()),
+            config,
+            repositories: Repositories::memory(),
+            caches: Caches::memory("test".to_string(), 100),
+        };
+        crate::providers::Providers::new(&store).organization().ensure_default_organization().await.unwrap();
+        store
     }
 }
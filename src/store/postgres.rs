@@ -0,0 +1,53 @@
+use std::time::Duration;
+use anyhow::Context;
+use log::{debug, info};
+use sqlx::PgPool;
+use sqlx::postgres::PgPoolOptions;
+use crate::conf::Config;
+use crate::error::Error;
+
+#[derive(Clone, Debug)]
+pub struct PgDatabases {
+    pub default: PgPool,
+}
+
+impl PgDatabases {
+    pub async fn new(config: Config) -> Result<Self, Error> {
+        info!("PgDatabases init");
+        let db = PgDatabases {
+            default: connect(config).await
+                .map_err(|err| Error::DatabaseConnectionError(err.to_string()))?,
+        };
+        debug!("{db:?}");
+        Ok(db)
+    }
+
+    /// 执行 migrations/postgres 下尚未执行的迁移, 迁移文件在编译时打包进二进制, 返回本次执行的迁移名
+    pub async fn migrate(&self) -> Result<Vec<String>, Error> {
+        let migrator = sqlx::migrate!("./migrations/postgres");
+        // 首次执行时 _sqlx_migrations 还不存在
+        let applied = sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations")
+            .fetch_all(&self.default).await
+            .unwrap_or_default();
+        migrator.run(&self.default).await
+            .with_context(|| "run postgres migrations".to_string())?;
+        Ok(migrator.iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .map(|migration| migration.description.to_string())
+            .collect())
+    }
+}
+
+pub async fn connect(config: Config) -> Result<PgPool, sqlx::Error> {
+    // postgres://{username}:{password}@{host}:5432/{database}
+    PgPoolOptions::new()
+        .max_connections(config.max_size)
+        .acquire_timeout(Duration::from_secs(config.database_connect_timeout_secs))
+        .connect(config.database_url.as_str())
+        .await
+}
+
+/// 违反唯一约束时的写入错误
+pub fn is_unique_violation(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(err) if err.is_unique_violation())
+}
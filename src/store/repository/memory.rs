@@ -0,0 +1,477 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use mongodb::bson::oid::ObjectId;
+use crate::error::{Code, Error};
+use crate::model::{ApiKey, DailyUsage, ListUsersInput, Message, MessageRoleType, Organization, Persona, QuotaDoc, User};
+use crate::store::repository::{ApiKeyRepository, Direction, HealthCheck, MessageRepository, MessageStream, MessageUsage, ModelUsage, OrganizationRepository, PersonaRepository, QuotaStore, UserActivity, UserRepository};
+
+#[derive(Default)]
+pub struct MemoryUsers {
+    users: Mutex<Vec<User>>,
+}
+
+impl MemoryUsers {
+    fn find(&self, predicate: impl Fn(&User) -> bool) -> Option<User> {
+        self.users.lock().unwrap().iter().find(|user| predicate(user)).cloned()
+    }
+}
+
+#[rocket::async_trait]
+impl UserRepository for MemoryUsers {
+    async fn find_by_id(&self, user_id: String) -> Result<Option<User>, Error> {
+        Ok(self.find(|user| user.id == user_id))
+    }
+
+    async fn find_in_org_by_id(&self, org_id: String, user_id: String) -> Result<Option<User>, Error> {
+        Ok(self.find(|user| user.id == user_id && user.org_id == org_id))
+    }
+
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<User>, Error> {
+        Ok(self.find(|user| user.name == name && user.org_id == org_id))
+    }
+
+    async fn insert(&self, user: User) -> Result<User, Error> {
+        let mut users = self.users.lock().unwrap();
+        if users.iter().any(|other| other.org_id == user.org_id && other.name == user.name) {
+            return Err(Error::Feedback(Code::UserAlreadyExists));
+        }
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn insert_or_get(&self, user: User) -> Result<User, Error> {
+        let mut users = self.users.lock().unwrap();
+        if let Some(other) = users.iter().find(|other| other.org_id == user.org_id && other.name == user.name) {
+            return Ok(other.clone());
+        }
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, Error> {
+        let mut users = self.users.lock().unwrap();
+        if users.iter().any(|other| other.org_id == user.org_id && other.name == user.name && other.id != user.id) {
+            return Err(Error::Feedback(Code::UserAlreadyExists));
+        }
+        let Some(current) = users.iter_mut().find(|other| other.id == user.id && other.org_id == user.org_id) else {
+            return Ok(None);
+        };
+        *current = user.clone();
+        Ok(Some(user))
+    }
+
+    async fn list(&self, org_id: String, req: ListUsersInput) -> Result<(Vec<User>, u64), Error> {
+        let name = req.name.filter(|name| !name.is_empty()).map(|name| name.to_lowercase());
+        let mut users = self.users.lock().unwrap().iter()
+            .filter(|user| user.org_id == org_id)
+            .filter(|user| name.as_ref().is_none_or(|name| user.name.to_lowercase().contains(name)))
+            .filter(|user| req.disabled.is_none_or(|disabled| user.disabled == disabled))
+            .filter(|user| req.created_after.is_none_or(|created_after| user.created_at >= created_after))
+            .filter(|user| req.created_before.is_none_or(|created_before| user.created_at < created_before))
+            .cloned()
+            .collect::<Vec<User>>();
+        users.sort_by(|a, b| (b.created_at, &b.id).cmp(&(a.created_at, &a.id)));
+        let total = users.len() as u64;
+        let users = users.into_iter()
+            .skip(req.page.saturating_sub(1).saturating_mul(req.page_size) as usize)
+            .take(req.page_size as usize)
+            .collect();
+        Ok((users, total))
+    }
+
+    async fn count_in_org(&self, org_id: String) -> Result<u64, Error> {
+        Ok(self.users.lock().unwrap().iter().filter(|user| user.org_id == org_id).count() as u64)
+    }
+
+    async fn delete(&self, user: User) -> Result<u64, Error> {
+        let mut users = self.users.lock().unwrap();
+        let len = users.len();
+        users.retain(|other| !(other.id == user.id && other.org_id == user.org_id));
+        Ok((len - users.len()) as u64)
+    }
+
+    async fn backfill_org_id(&self, org_id: String) -> Result<u64, Error> {
+        Ok(0)
+    }
+}
+
+/// 按插入顺序保存, 即按时间正序
+#[derive(Default)]
+pub struct MemoryMessages {
+    messages: Mutex<Vec<Message>>,
+}
+
+impl MemoryMessages {
+    /// 按 (created_at, id) 排序
+    fn find_by_user(&self, user: &User) -> Vec<Message> {
+        let mut messages = self.messages.lock().unwrap().iter()
+            .filter(|msg| msg.org_id == user.org_id && msg.user_id == user.id)
+            .cloned()
+            .collect::<Vec<Message>>();
+        messages.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        messages
+    }
+}
+
+#[rocket::async_trait]
+impl MessageRepository for MemoryMessages {
+    async fn insert_many(&self, messages: Vec<Message>) -> Result<usize, Error> {
+        let count = messages.len();
+        self.messages.lock().unwrap().extend(messages);
+        Ok(count)
+    }
+
+    async fn find_by_id(&self, user: User, message_id: String) -> Result<Option<Message>, Error> {
+        Ok(self.find_by_user(&user).into_iter().find(|msg| msg.id == message_id))
+    }
+
+    async fn find_page(&self, user: User, cursor: Option<Message>, direction: Direction, limit: u64) -> Result<Vec<Message>, Error> {
+        let cursor = cursor.map(|msg| (msg.created_at, msg.id));
+        let mut messages = self.find_by_user(&user).into_iter()
+            .filter(|msg| cursor.as_ref().is_none_or(|cursor| match direction {
+                Direction::Older => (msg.created_at, &msg.id) < (cursor.0, &cursor.1),
+                Direction::Newer => (msg.created_at, &msg.id) > (cursor.0, &cursor.1),
+            }))
+            .collect::<Vec<Message>>();
+        if direction == Direction::Older {
+            messages.reverse();
+        }
+        messages.truncate(limit as usize);
+        Ok(messages)
+    }
+
+    async fn count(&self, user: User, type_: Option<MessageRoleType>, since: Option<DateTime<Utc>>) -> Result<u64, Error> {
+        let count = self.find_by_user(&user).iter()
+            .filter(|msg| type_.as_ref().is_none_or(|type_| msg.type_ == *type_))
+            .filter(|msg| since.is_none_or(|since| msg.created_at >= since.naive_utc()))
+            .count();
+        Ok(count as u64)
+    }
+
+    async fn find_first_and_last(&self, user: User) -> Result<(Option<Message>, Option<Message>), Error> {
+        let messages = self.find_by_user(&user);
+        Ok((messages.first().cloned(), messages.last().cloned()))
+    }
+
+    async fn delete_by_user(&self, user: User) -> Result<u64, Error> {
+        let mut messages = self.messages.lock().unwrap();
+        let len = messages.len();
+        messages.retain(|msg| !(msg.org_id == user.org_id && msg.user_id == user.id));
+        Ok((len - messages.len()) as u64)
+    }
+
+    async fn watch(&self, user: User) -> Result<Option<MessageStream>, Error> {
+        Ok(None)
+    }
+
+    async fn count_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error> {
+        let count = self.find_by_user(&user).iter()
+            .filter(|msg| msg.created_at < before.naive_utc())
+            .count();
+        Ok(count as u64)
+    }
+
+    async fn delete_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error> {
+        let mut messages = self.messages.lock().unwrap();
+        let len = messages.len();
+        messages.retain(|msg| !(msg.org_id == user.org_id && msg.user_id == user.id && msg.created_at < before.naive_utc()));
+        Ok((len - messages.len()) as u64)
+    }
+
+    async fn org_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, tz: Tz) -> Result<MessageUsage, Error> {
+        let mut daily: BTreeMap<String, (DailyUsage, HashSet<String>)> = BTreeMap::new();
+        let mut usage = MessageUsage::default();
+        let mut active_users = HashSet::new();
+        let messages = self.messages.lock().unwrap();
+        let messages = messages.iter()
+            .filter(|msg| msg.org_id == org_id)
+            .filter(|msg| msg.created_at >= start.naive_utc() && msg.created_at < end.naive_utc());
+        for msg in messages {
+            let date = msg.created_at.and_utc().with_timezone(&tz).date_naive().to_string();
+            let (day, users) = daily.entry(date.clone())
+                .or_insert_with(|| (DailyUsage { date, ..Default::default() }, HashSet::new()));
+            match msg.type_ {
+                MessageRoleType::User => {
+                    day.user_message_count += 1;
+                    users.insert(msg.user_id.clone());
+                    day.active_user_count = users.len() as u64;
+                    active_users.insert(msg.user_id.clone());
+                    usage.user_message_count += 1;
+                }
+                MessageRoleType::AI => {
+                    day.ai_message_count += 1;
+                    usage.ai_message_count += 1;
+                }
+            }
+        }
+        usage.active_user_count = active_users.len() as u64;
+        usage.daily = daily.into_values().map(|(day, _)| day).collect();
+        Ok(usage)
+    }
+
+    async fn model_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<ModelUsage>, Error> {
+        let mut models: BTreeMap<String, ModelUsage> = BTreeMap::new();
+        let messages = self.messages.lock().unwrap();
+        let messages = messages.iter()
+            .filter(|msg| msg.org_id == org_id && msg.type_ == MessageRoleType::AI)
+            .filter(|msg| msg.created_at >= start.naive_utc() && msg.created_at < end.naive_utc());
+        for msg in messages {
+            let model = msg.model.clone().unwrap_or_default();
+            let usage = models.entry(model.clone()).or_insert_with(|| ModelUsage { model, ..Default::default() });
+            usage.message_count += 1;
+            if let Some(tokens) = &msg.usage {
+                usage.prompt_tokens += tokens.prompt_tokens as u64;
+                usage.completion_tokens += tokens.completion_tokens as u64;
+                usage.total_tokens += tokens.total_tokens as u64;
+            }
+            if let Some(latency_ms) = msg.latency_ms {
+                usage.latency_count += 1;
+                usage.latency_ms_sum += latency_ms;
+            }
+        }
+        let mut res = models.into_values().collect::<Vec<ModelUsage>>();
+        res.sort_by_key(|usage| Reverse(usage.message_count));
+        Ok(res)
+    }
+
+    async fn top_users(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, limit: u64) -> Result<Vec<UserActivity>, Error> {
+        let mut users: BTreeMap<String, UserActivity> = BTreeMap::new();
+        let messages = self.messages.lock().unwrap();
+        let messages = messages.iter()
+            .filter(|msg| msg.org_id == org_id)
+            .filter(|msg| msg.created_at >= start.naive_utc() && msg.created_at < end.naive_utc());
+        for msg in messages {
+            let activity = users.entry(msg.user_id.clone())
+                .or_insert_with(|| UserActivity { user_id: msg.user_id.clone(), ..Default::default() });
+            match msg.type_ {
+                MessageRoleType::User => activity.user_message_count += 1,
+                MessageRoleType::AI => activity.total_tokens += msg.usage.as_ref().map(|usage| usage.total_tokens as u64).unwrap_or_default(),
+            }
+        }
+        let mut res = users.into_values()
+            .filter(|activity| activity.user_message_count > 0)
+            .collect::<Vec<UserActivity>>();
+        res.sort_by_key(|activity| Reverse(activity.user_message_count));
+        res.truncate(limit as usize);
+        Ok(res)
+    }
+
+    async fn backfill_org_id(&self, org_id: String) -> Result<u64, Error> {
+        Ok(0)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryOrganizations {
+    orgs: Mutex<Vec<Organization>>,
+}
+
+#[rocket::async_trait]
+impl OrganizationRepository for MemoryOrganizations {
+    async fn find_by_id(&self, org_id: String) -> Result<Option<Organization>, Error> {
+        Ok(self.orgs.lock().unwrap().iter().find(|org| org.id == org_id).cloned())
+    }
+
+    async fn insert(&self, org: Organization) -> Result<Organization, Error> {
+        let mut orgs = self.orgs.lock().unwrap();
+        if orgs.iter().any(|other| other.name == org.name) {
+            return Err(Error::Feedback(Code::OrganizationAlreadyExists));
+        }
+        orgs.push(org.clone());
+        Ok(org)
+    }
+
+    async fn insert_if_absent(&self, org: Organization) -> Result<(), Error> {
+        let mut orgs = self.orgs.lock().unwrap();
+        if !orgs.iter().any(|other| other.id == org.id) {
+            orgs.push(org);
+        }
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Organization>, Error> {
+        let mut orgs = self.orgs.lock().unwrap().clone();
+        orgs.sort_by_key(|org| org.created_at);
+        Ok(orgs)
+    }
+
+    async fn update_quota(&self, org_id: String, daily_message_limit: Option<i64>, burst_message_limit: Option<i64>) -> Result<Option<Organization>, Error> {
+        let mut orgs = self.orgs.lock().unwrap();
+        let Some(org) = orgs.iter_mut().find(|org| org.id == org_id) else {
+            return Ok(None);
+        };
+        org.daily_message_limit = daily_message_limit;
+        org.burst_message_limit = burst_message_limit;
+        org.updated_at = Some(Utc::now().naive_utc());
+        Ok(Some(org.clone()))
+    }
+
+    async fn update_retention(&self, org_id: String, message_retention_days: Option<i64>) -> Result<Option<Organization>, Error> {
+        let mut orgs = self.orgs.lock().unwrap();
+        let Some(org) = orgs.iter_mut().find(|org| org.id == org_id) else {
+            return Ok(None);
+        };
+        org.message_retention_days = message_retention_days;
+        org.updated_at = Some(Utc::now().naive_utc());
+        Ok(Some(org.clone()))
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryPersonas {
+    personas: Mutex<Vec<Persona>>,
+}
+
+#[rocket::async_trait]
+impl PersonaRepository for MemoryPersonas {
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<Persona>, Error> {
+        Ok(self.personas.lock().unwrap().iter().find(|persona| persona.org_id == org_id && persona.name == name).cloned())
+    }
+
+    async fn insert(&self, persona: Persona) -> Result<Persona, Error> {
+        let mut personas = self.personas.lock().unwrap();
+        if personas.iter().any(|other| other.org_id == persona.org_id && other.name == persona.name) {
+            return Err(Error::Feedback(Code::PersonaAlreadyExists));
+        }
+        personas.push(persona.clone());
+        Ok(persona)
+    }
+
+    async fn list(&self, org_id: String) -> Result<Vec<Persona>, Error> {
+        let mut personas = self.personas.lock().unwrap().iter()
+            .filter(|persona| persona.org_id == org_id)
+            .cloned()
+            .collect::<Vec<Persona>>();
+        personas.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(personas)
+    }
+
+    async fn update_prompt(&self, org_id: String, persona_id: String, prompt: String) -> Result<Option<Persona>, Error> {
+        let mut personas = self.personas.lock().unwrap();
+        let Some(persona) = personas.iter_mut().find(|persona| persona.id == persona_id && persona.org_id == org_id) else {
+            return Ok(None);
+        };
+        persona.prompt = prompt;
+        persona.updated_at = Some(Utc::now().naive_utc());
+        Ok(Some(persona.clone()))
+    }
+
+    async fn delete(&self, org_id: String, persona_id: String) -> Result<Option<Persona>, Error> {
+        let mut personas = self.personas.lock().unwrap();
+        let Some(index) = personas.iter().position(|persona| persona.id == persona_id && persona.org_id == org_id) else {
+            return Ok(None);
+        };
+        Ok(Some(personas.remove(index)))
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryApiKeys {
+    /// API Key 及其摘要
+    api_keys: Mutex<Vec<(ApiKey, String)>>,
+}
+
+#[rocket::async_trait]
+impl ApiKeyRepository for MemoryApiKeys {
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<ApiKey, Error> {
+        self.api_keys.lock().unwrap().push((api_key.clone(), key_hash));
+        Ok(api_key)
+    }
+
+    async fn find_active_by_hash(&self, key_hash: String) -> Result<Option<ApiKey>, Error> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        let api_key = api_keys.iter_mut()
+            .find(|(api_key, hash)| *hash == key_hash && api_key.revoked_at.is_none())
+            .map(|(api_key, _)| {
+                api_key.last_used_at = Some(Utc::now().naive_utc());
+                api_key.clone()
+            });
+        Ok(api_key)
+    }
+
+    async fn list_by_user(&self, user_id: String) -> Result<Vec<ApiKey>, Error> {
+        let mut api_keys = self.api_keys.lock().unwrap().iter()
+            .filter(|(api_key, _)| api_key.user_id == user_id)
+            .map(|(api_key, _)| api_key.clone())
+            .collect::<Vec<ApiKey>>();
+        api_keys.sort_by_key(|api_key| Reverse(api_key.created_at));
+        Ok(api_keys)
+    }
+
+    async fn revoke(&self, user_id: String, api_key_id: String) -> Result<Option<ApiKey>, Error> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        let api_key = api_keys.iter_mut()
+            .find(|(api_key, _)| api_key.id == api_key_id && api_key.user_id == user_id)
+            .map(|(api_key, _)| {
+                api_key.revoked_at = Some(Utc::now().naive_utc());
+                api_key.clone()
+            });
+        Ok(api_key)
+    }
+
+    async fn count_active_by_user(&self, user_id: String) -> Result<u64, Error> {
+        let count = self.api_keys.lock().unwrap().iter()
+            .filter(|(api_key, _)| api_key.user_id == user_id && api_key.revoked_at.is_none())
+            .count();
+        Ok(count as u64)
+    }
+
+    async fn delete_by_user(&self, user_id: String) -> Result<u64, Error> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        let len = api_keys.len();
+        api_keys.retain(|(api_key, _)| api_key.user_id != user_id);
+        Ok((len - api_keys.len()) as u64)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryQuotas {
+    quotas: Mutex<HashMap<ObjectId, QuotaDoc>>,
+}
+
+#[rocket::async_trait]
+impl QuotaStore for MemoryQuotas {
+    async fn load(&self, user_id: ObjectId) -> Result<Option<QuotaDoc>, Error> {
+        let quota = self.quotas.lock().unwrap().get(&user_id).cloned();
+        // 让出执行权, 使并发请求在读取和写入之间交错, 与真实存储的行为一致
+        tokio::task::yield_now().await;
+        Ok(quota)
+    }
+
+    async fn insert(&self, quota: QuotaDoc) -> Result<bool, Error> {
+        let mut quotas = self.quotas.lock().unwrap();
+        if quotas.contains_key(&quota._id) {
+            return Ok(false);
+        }
+        quotas.insert(quota._id, quota);
+        Ok(true)
+    }
+
+    async fn swap(&self, expected_version: i64, quota: QuotaDoc) -> Result<bool, Error> {
+        let mut quotas = self.quotas.lock().unwrap();
+        match quotas.get(&quota._id) {
+            Some(current) if current.version == expected_version => {
+                quotas.insert(quota._id, quota);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete(&self, user_id: ObjectId) -> Result<(), Error> {
+        self.quotas.lock().unwrap().remove(&user_id);
+        Ok(())
+    }
+}
+
+pub struct MemoryHealth;
+
+#[rocket::async_trait]
+impl HealthCheck for MemoryHealth {
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
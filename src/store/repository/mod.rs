@@ -0,0 +1,216 @@
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::stream::BoxStream;
+use mongodb::bson::oid::ObjectId;
+use crate::error::Error;
+use crate::model::{ApiKey, DailyUsage, ListUsersInput, Message, MessageRoleType, Organization, Persona, QuotaDoc, User};
+use crate::store::database::Databases;
+use crate::store::postgres::PgDatabases;
+use crate::store::repository::memory::{MemoryApiKeys, MemoryHealth, MemoryMessages, MemoryOrganizations, MemoryPersonas, MemoryQuotas, MemoryUsers};
+use crate::store::repository::postgres::{PgApiKeys, PgHealth, PgMessages, PgOrganizations, PgPersonas, PgQuotas, PgUsers};
+
+pub mod mongo;
+pub mod memory;
+pub mod postgres;
+
+/// 各类数据的存储, 由 Store 在初始化时根据 DATABASE_BACKEND 选择实现
+#[derive(Clone)]
+pub struct Repositories {
+    pub user: Arc<dyn UserRepository>,
+    pub message: Arc<dyn MessageRepository>,
+    pub organization: Arc<dyn OrganizationRepository>,
+    pub persona: Arc<dyn PersonaRepository>,
+    pub api_key: Arc<dyn ApiKeyRepository>,
+    pub quota: Arc<dyn QuotaStore>,
+    pub health: Arc<dyn HealthCheck>,
+}
+
+impl Repositories {
+    pub fn mongo(db: Databases) -> Self {
+        Self {
+            user: Arc::new(db.user()),
+            message: Arc::new(db.message()),
+            organization: Arc::new(db.organization()),
+            persona: Arc::new(db.persona()),
+            api_key: Arc::new(db.api_key()),
+            quota: Arc::new(db.quota()),
+            health: Arc::new(db.default.clone()),
+        }
+    }
+
+    pub fn postgres(db: PgDatabases) -> Self {
+        Self {
+            user: Arc::new(PgUsers::new(db.default.clone())),
+            message: Arc::new(PgMessages::new(db.default.clone())),
+            organization: Arc::new(PgOrganizations::new(db.default.clone())),
+            persona: Arc::new(PgPersonas::new(db.default.clone())),
+            api_key: Arc::new(PgApiKeys::new(db.default.clone())),
+            quota: Arc::new(PgQuotas::new(db.default.clone())),
+            health: Arc::new(PgHealth::new(db.default.clone())),
+        }
+    }
+
+    /// 数据只保存在进程内, 用于测试和本地调试
+    pub fn memory() -> Self {
+        Self {
+            user: Arc::new(MemoryUsers::default()),
+            message: Arc::new(MemoryMessages::default()),
+            organization: Arc::new(MemoryOrganizations::default()),
+            persona: Arc::new(MemoryPersonas::default()),
+            api_key: Arc::new(MemoryApiKeys::default()),
+            quota: Arc::new(MemoryQuotas::default()),
+            health: Arc::new(MemoryHealth),
+        }
+    }
+}
+
+/// 用户的存储, 用户名在组织内唯一, 冲突时返回 Code::UserAlreadyExists
+#[rocket::async_trait]
+pub trait UserRepository: Send + Sync {
+    /// 不限定组织, id 无效时返回 None
+    async fn find_by_id(&self, user_id: String) -> Result<Option<User>, Error>;
+    async fn find_in_org_by_id(&self, org_id: String, user_id: String) -> Result<Option<User>, Error>;
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<User>, Error>;
+    async fn insert(&self, user: User) -> Result<User, Error>;
+    /// 按组织和用户名插入, 组织内已有同名用户时返回已有的用户, 并发调用时只会创建一个用户
+    async fn insert_or_get(&self, user: User) -> Result<User, Error>;
+    /// 按 id 和组织整体更新用户, 用户不存在时返回 None
+    async fn update(&self, user: User) -> Result<Option<User>, Error>;
+    /// 按创建时间倒序分页, 返回当前页和总数
+    async fn list(&self, org_id: String, req: ListUsersInput) -> Result<(Vec<User>, u64), Error>;
+    async fn count_in_org(&self, org_id: String) -> Result<u64, Error>;
+    async fn delete(&self, user: User) -> Result<u64, Error>;
+    /// 把多租户之前没有组织的用户归入指定组织
+    async fn backfill_org_id(&self, org_id: String) -> Result<u64, Error>;
+}
+
+/// 组织在一段时间内的消息统计
+#[derive(Debug, Clone, Default)]
+pub struct MessageUsage {
+    pub active_user_count: u64,
+    pub user_message_count: u64,
+    pub ai_message_count: u64,
+    pub daily: Vec<DailyUsage>,
+}
+
+/// 一个模型在一段时间内生成的 AI 消息, 未记录模型的消息 model 为空字符串
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelUsage {
+    pub model: String,
+    pub message_count: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    /// 记录了耗时的消息数和耗时之和, 用于计算平均耗时
+    pub latency_count: u64,
+    pub latency_ms_sum: u64,
+}
+
+/// 一个用户在一段时间内发送的消息数和消耗的 token
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserActivity {
+    pub user_id: String,
+    pub user_message_count: u64,
+    pub total_tokens: u64,
+}
+
+/// 翻页方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// 向更早的消息翻页, 结果按时间倒序
+    Older,
+    /// 向更新的消息翻页, 结果按时间正序
+    Newer,
+}
+
+/// 消息的存储, 查询都限定在用户所在组织内
+/// 按插入顺序推送的新消息
+pub type MessageStream = BoxStream<'static, Result<Message, Error>>;
+
+#[rocket::async_trait]
+pub trait MessageRepository: Send + Sync {
+    async fn insert_many(&self, messages: Vec<Message>) -> Result<usize, Error>;
+    /// id 无效时返回 None
+    async fn find_by_id(&self, user: User, message_id: String) -> Result<Option<Message>, Error>;
+    /// 从 cursor (不含) 开始沿 direction 取最多 limit 条消息, 按 (created_at, id) 排序, 结果按 direction 的顺序返回.
+    /// cursor 为空时从最新 (Older) 或最早 (Newer) 的消息开始
+    async fn find_page(&self, user: User, cursor: Option<Message>, direction: Direction, limit: u64) -> Result<Vec<Message>, Error>;
+    /// type_ 和 since 为空时不限定
+    async fn count(&self, user: User, type_: Option<MessageRoleType>, since: Option<DateTime<Utc>>) -> Result<u64, Error>;
+    /// 最早和最新的一条消息
+    async fn find_first_and_last(&self, user: User) -> Result<(Option<Message>, Option<Message>), Error>;
+    async fn delete_by_user(&self, user: User) -> Result<u64, Error>;
+    /// 订阅用户此后插入的消息, 存储不支持推送时返回 None, 由调用方轮询
+    async fn watch(&self, user: User) -> Result<Option<MessageStream>, Error>;
+    /// created_at 早于 before 的消息数
+    async fn count_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error>;
+    /// 删除 created_at 早于 before 的消息
+    async fn delete_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error>;
+    /// 统计组织在 [start, end) 内的消息, 日期按 tz 划分
+    async fn org_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, tz: Tz) -> Result<MessageUsage, Error>;
+    /// 按模型统计组织在 [start, end) 内的 AI 消息, 按消息数倒序
+    async fn model_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<ModelUsage>, Error>;
+    /// 组织在 [start, end) 内发送消息最多的 limit 个用户, 按消息数倒序
+    async fn top_users(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, limit: u64) -> Result<Vec<UserActivity>, Error>;
+    /// 把多租户之前没有组织的消息归入指定组织
+    async fn backfill_org_id(&self, org_id: String) -> Result<u64, Error>;
+}
+
+/// 组织的存储, 组织名唯一, 冲突时返回 Code::OrganizationAlreadyExists
+#[rocket::async_trait]
+pub trait OrganizationRepository: Send + Sync {
+    /// id 无效时返回 None
+    async fn find_by_id(&self, org_id: String) -> Result<Option<Organization>, Error>;
+    async fn insert(&self, org: Organization) -> Result<Organization, Error>;
+    /// 组织不存在时才插入, 用于创建默认组织
+    async fn insert_if_absent(&self, org: Organization) -> Result<(), Error>;
+    /// 按创建时间正序
+    async fn list(&self) -> Result<Vec<Organization>, Error>;
+    async fn update_quota(&self, org_id: String, daily_message_limit: Option<i64>, burst_message_limit: Option<i64>) -> Result<Option<Organization>, Error>;
+    async fn update_retention(&self, org_id: String, message_retention_days: Option<i64>) -> Result<Option<Organization>, Error>;
+}
+
+/// 角色设定的存储, 角色名在组织内唯一, 冲突时返回 Code::PersonaAlreadyExists
+#[rocket::async_trait]
+pub trait PersonaRepository: Send + Sync {
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<Persona>, Error>;
+    async fn insert(&self, persona: Persona) -> Result<Persona, Error>;
+    /// 按名字正序
+    async fn list(&self, org_id: String) -> Result<Vec<Persona>, Error>;
+    /// 角色不存在时返回 None
+    async fn update_prompt(&self, org_id: String, persona_id: String, prompt: String) -> Result<Option<Persona>, Error>;
+    /// 返回删除的角色, 不存在时返回 None
+    async fn delete(&self, org_id: String, persona_id: String) -> Result<Option<Persona>, Error>;
+}
+
+/// API Key 的存储, 只保存明文的 sha256 摘要
+#[rocket::async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<ApiKey, Error>;
+    /// 未吊销的 API Key, 找到时更新 last_used_at
+    async fn find_active_by_hash(&self, key_hash: String) -> Result<Option<ApiKey>, Error>;
+    /// 按创建时间倒序
+    async fn list_by_user(&self, user_id: String) -> Result<Vec<ApiKey>, Error>;
+    /// 吊销用户自己的 API Key, 不存在时返回 None
+    async fn revoke(&self, user_id: String, api_key_id: String) -> Result<Option<ApiKey>, Error>;
+    async fn count_active_by_user(&self, user_id: String) -> Result<u64, Error>;
+    async fn delete_by_user(&self, user_id: String) -> Result<u64, Error>;
+}
+
+/// 额度计数的存储, 所有修改都通过 version 比较后整体替换完成
+#[rocket::async_trait]
+pub trait QuotaStore: Send + Sync {
+    async fn load(&self, user_id: ObjectId) -> Result<Option<QuotaDoc>, Error>;
+    /// 插入新的计数, 已存在时返回 false
+    async fn insert(&self, quota: QuotaDoc) -> Result<bool, Error>;
+    /// 仅当当前 version 等于 expected_version 时替换, 否则返回 false
+    async fn swap(&self, expected_version: i64, quota: QuotaDoc) -> Result<bool, Error>;
+    async fn delete(&self, user_id: ObjectId) -> Result<(), Error>;
+}
+
+/// 存储的连通性检查, 用于就绪探针
+#[rocket::async_trait]
+pub trait HealthCheck: Send + Sync {
+    async fn ping(&self) -> Result<(), Error>;
+}
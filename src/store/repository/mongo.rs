@@ -0,0 +1,721 @@
+use std::collections::BTreeMap;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::{StreamExt, TryStreamExt};
+use mongodb::bson;
+use mongodb::bson::{Bson, DateTime as BsonDateTime, Document, doc};
+use mongodb::bson::oid::ObjectId;
+use mongodb::{Collection, Database};
+use mongodb::error::TRANSIENT_TRANSACTION_ERROR;
+use mongodb::options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument, UpdateOptions};
+use crate::error::{Code, Error};
+use crate::model::{ApiKey, ApiKeyDoc, DailyUsage, ListUsersInput, Message, MessageDoc, MessageRoleType, Organization, OrganizationDoc, parse_oid, Persona, PersonaDoc, QuotaDoc, User, UserDoc};
+use crate::store::database::{commit_transaction, is_change_stream_unsupported, is_duplicate_key_error, is_transaction_unsupported, TRANSACTION_ATTEMPTS};
+use crate::store::repository::{ApiKeyRepository, Direction, HealthCheck, MessageRepository, MessageStream, MessageUsage, ModelUsage, OrganizationRepository, PersonaRepository, QuotaStore, UserActivity, UserRepository};
+
+/// 限定在用户所在组织内的用户
+fn user_filter(user: &User) -> Result<Document, Error> {
+    Ok(doc! {
+        "_id": parse_oid(user.id.as_str())?,
+        "org_id": parse_oid(user.org_id.as_str())?,
+    })
+}
+
+/// 用户的消息, 限定在用户所在组织内
+fn message_filter(user: &User) -> Result<Document, Error> {
+    Ok(doc! {
+        "org_id": parse_oid(user.org_id.as_str())?,
+        "user_id": parse_oid(user.id.as_str())?,
+    })
+}
+
+async fn find_user(users: &Collection<UserDoc>, filter: Document) -> Result<Option<User>, Error> {
+    let user = users.find_one(filter.clone(), None).await
+        .with_context(|| format!("find_one by {}", filter))?;
+    if let Some(user) = user {
+        Ok(Some(user.clone().into_entity().with_context(||format!("found user into_entity: {:?}", user))?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[rocket::async_trait]
+impl UserRepository for Collection<UserDoc> {
+    async fn find_by_id(&self, user_id: String) -> Result<Option<User>, Error> {
+        let Ok(id) = parse_oid(user_id.as_str()) else {
+            return Ok(None);
+        };
+        find_user(self, doc! {"_id": id}).await
+    }
+
+    async fn find_in_org_by_id(&self, org_id: String, user_id: String) -> Result<Option<User>, Error> {
+        let Ok(id) = parse_oid(user_id.as_str()) else {
+            return Ok(None);
+        };
+        find_user(self, doc! {"_id": id, "org_id": parse_oid(org_id.as_str())?}).await
+    }
+
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<User>, Error> {
+        find_user(self, doc! {"name": name, "org_id": parse_oid(org_id.as_str())?}).await
+    }
+
+    async fn insert(&self, user: User) -> Result<User, Error> {
+        let doc = UserDoc::from_entity(user)?;
+        match self.insert_one(doc.clone(), None).await {
+            Ok(_) => Ok(doc.clone().into_entity().with_context(||format!("new user into_entity: {:?}", doc))?),
+            Err(err) if is_duplicate_key_error(&err) => Err(Error::Feedback(Code::UserAlreadyExists)),
+            Err(err) => Err(anyhow::Error::from(err).context("insert_one").into()),
+        }
+    }
+
+    async fn insert_or_get(&self, user: User) -> Result<User, Error> {
+        let doc = UserDoc::from_entity(user)?;
+        let filter = doc! {"org_id": doc.org_id, "name": doc.name.clone()};
+        let set_on_insert = bson::to_document(&doc).with_context(|| format!("to_document: {:?}", doc))?;
+        let opts = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        match self.find_one_and_update(filter.clone(), doc! {"$setOnInsert": set_on_insert}, opts).await {
+            Ok(Some(doc)) => Ok(doc.clone().into_entity().with_context(|| format!("upserted user into_entity: {:?}", doc))?),
+            Ok(None) => Err(Error::Feedback(Code::UserNotFound)),
+            // 并发 upsert 时后到的一方违反唯一索引, 此时用户已由另一方创建
+            Err(err) if is_duplicate_key_error(&err) => find_user(self, filter).await?
+                .ok_or(Error::Feedback(Code::UserNotFound)),
+            Err(err) => Err(anyhow::Error::from(err).context(format!("find_one_and_update by {}", filter)).into()),
+        }
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, Error> {
+        let filter = user_filter(&user)?;
+        let doc = UserDoc::from_entity(user)?;
+        let preferences = bson::to_bson(&doc.preferences).with_context(|| format!("to_bson: {:?}", doc.preferences))?;
+        let set = doc! {
+            "name": doc.name,
+            "role": doc.role,
+            "disabled": doc.disabled,
+            "message_retention_days": doc.message_retention_days,
+            "preferences": preferences,
+            "updated_at": doc.updated_at,
+        };
+        match self.update_one(filter.clone(), doc! {"$set": set}, None).await {
+            Ok(_) => {}
+            Err(err) if is_duplicate_key_error(&err) => return Err(Error::Feedback(Code::UserAlreadyExists)),
+            Err(err) => return Err(anyhow::Error::from(err).context(format!("update_one by {}", filter)).into()),
+        }
+        find_user(self, filter).await
+    }
+
+    async fn list(&self, org_id: String, req: ListUsersInput) -> Result<(Vec<User>, u64), Error> {
+        let mut filter = doc! {
+            "org_id": parse_oid(org_id.as_str())?,
+        };
+        if let Some(name) = req.name.filter(|name| !name.is_empty()) {
+            filter.insert("name", doc! {"$regex": regex::escape(name.as_str()), "$options": "i"});
+        }
+        if let Some(disabled) = req.disabled {
+            filter.insert("disabled", if disabled { doc! {"$eq": true} } else { doc! {"$ne": true} });
+        }
+        let mut created_at = doc! {};
+        if let Some(created_after) = req.created_after {
+            created_at.insert("$gte", BsonDateTime::from_chrono(created_after.and_utc()));
+        }
+        if let Some(created_before) = req.created_before {
+            created_at.insert("$lt", BsonDateTime::from_chrono(created_before.and_utc()));
+        }
+        if !created_at.is_empty() {
+            filter.insert("created_at", created_at);
+        }
+        debug!("filter: {}", filter);
+        let total = self.count_documents(filter.clone(), None).await
+            .with_context(|| "count_documents".to_string())?;
+        let opts = FindOptions::builder()
+            .sort(doc! {"created_at": -1, "_id": -1})
+            .skip(req.page.saturating_sub(1).saturating_mul(req.page_size))
+            .limit(req.page_size as i64)
+            .build();
+        let mut cursor = self.find(filter, opts).await
+            .with_context(|| "find".to_string())?;
+        let mut users = vec![];
+        while let Some(doc) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            users.push(doc.into_entity()?)
+        }
+        Ok((users, total))
+    }
+
+    async fn count_in_org(&self, org_id: String) -> Result<u64, Error> {
+        let count = self.count_documents(doc! {"org_id": parse_oid(org_id.as_str())?}, None).await
+            .with_context(|| "count_documents".to_string())?;
+        Ok(count)
+    }
+
+    async fn delete(&self, user: User) -> Result<u64, Error> {
+        let filter = user_filter(&user)?;
+        let res = self.delete_one(filter.clone(), None).await
+            .with_context(|| format!("delete_one by {}", filter))?;
+        Ok(res.deleted_count)
+    }
+
+    async fn backfill_org_id(&self, org_id: String) -> Result<u64, Error> {
+        let update = doc! {"$set": {"org_id": parse_oid(org_id.as_str())?}};
+        let res = self.update_many(doc! {"org_id": {"$exists": false}}, update, None).await
+            .with_context(|| "backfill user.org_id".to_string())?;
+        Ok(res.modified_count)
+    }
+}
+
+/// 不支持事务的单机部署: 写入失败时删除已写入的部分, 使一轮对话的消息全部写入或全部不写入
+async fn insert_many_or_rollback(messages: &Collection<MessageDoc>, docs: Vec<MessageDoc>) -> Result<usize, Error> {
+    let ids = docs.iter().map(|doc| doc._id).collect::<Vec<ObjectId>>();
+    match Collection::insert_many(messages, docs, None).await {
+        Ok(res) => {
+            debug!("inserted: {:?}", res);
+            Ok(res.inserted_ids.len())
+        }
+        Err(err) => {
+            if let Err(rollback_err) = messages.delete_many(doc! {"_id": {"$in": ids.clone()}}, None).await {
+                error!("rollback inserted messages {:?}: {:?}", ids, rollback_err);
+            }
+            Err(anyhow::Error::from(err).context("insert_many").into())
+        }
+    }
+}
+
+fn bson_to_u64(value: Option<&Bson>) -> u64 {
+    match value {
+        Some(Bson::Int32(v)) => *v as u64,
+        Some(Bson::Int64(v)) => *v as u64,
+        Some(Bson::Double(v)) => *v as u64,
+        _ => 0,
+    }
+}
+
+#[rocket::async_trait]
+impl MessageRepository for Collection<MessageDoc> {
+    async fn insert_many(&self, messages: Vec<Message>) -> Result<usize, Error> {
+        let mut docs = vec![];
+        for message in messages {
+            docs.push(MessageDoc::from_entity(message)?);
+        }
+        if docs.is_empty() {
+            return Ok(0);
+        }
+        let mut session = self.client().start_session(None).await
+            .with_context(|| "start_session".to_string())?;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if let Err(err) = session.start_transaction(None).await {
+                if is_transaction_unsupported(&err) {
+                    debug!("transactions are not supported, insert without transaction");
+                    return insert_many_or_rollback(self, docs).await;
+                }
+                return Err(anyhow::Error::from(err).context("start_transaction").into());
+            }
+            let res = match self.insert_many_with_session(docs.clone(), None, &mut session).await {
+                Ok(res) => res,
+                Err(err) => {
+                    if let Err(err) = session.abort_transaction().await {
+                        debug!("abort_transaction: {:?}", err);
+                    }
+                    if err.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < TRANSACTION_ATTEMPTS {
+                        continue;
+                    }
+                    return Err(anyhow::Error::from(err).context("insert_many_with_session").into());
+                }
+            };
+            match commit_transaction(&mut session).await {
+                Ok(()) => {
+                    debug!("inserted: {:?}", res);
+                    return Ok(res.inserted_ids.len());
+                }
+                Err(err) if err.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < TRANSACTION_ATTEMPTS => continue,
+                Err(err) => return Err(anyhow::Error::from(err).context("commit_transaction").into()),
+            }
+        }
+    }
+
+    async fn find_by_id(&self, user: User, message_id: String) -> Result<Option<Message>, Error> {
+        let Ok(id) = parse_oid(message_id.as_str()) else {
+            return Ok(None);
+        };
+        let mut filter = message_filter(&user)?;
+        filter.insert("_id", id);
+        let msg = self.find_one(filter, None).await
+            .with_context(|| format!("find_one by _id {}", id))?;
+        match msg {
+            Some(msg) => Ok(Some(msg.into_entity()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_page(&self, user: User, cursor: Option<Message>, direction: Direction, limit: u64) -> Result<Vec<Message>, Error> {
+        let (op, sort) = match direction {
+            Direction::Older => ("$lt", -1),
+            Direction::Newer => ("$gt", 1),
+        };
+        let mut filter = message_filter(&user)?;
+        if let Some(cursor) = cursor {
+            // 同一轮对话的两条消息 created_at 相同, 以 _id 区分先后
+            let created_at = BsonDateTime::from_chrono(cursor.created_at.and_utc());
+            let id = parse_oid(cursor.id.as_str())?;
+            filter.insert("$or", vec![
+                doc! {"created_at": {op: created_at}},
+                doc! {"created_at": created_at, "_id": {op: id}},
+            ]);
+        }
+        let opts = FindOptions::builder().sort(doc! {"created_at": sort, "_id": sort}).limit(limit as i64).build();
+        debug!("filter: {}", filter);
+        let mut cursor = self.find(filter, opts).await
+            .with_context(|| "find".to_string())?;
+        let mut res = vec![];
+        while let Some(doc) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            res.push(doc.into_entity()?)
+        }
+        Ok(res)
+    }
+
+    async fn count(&self, user: User, type_: Option<MessageRoleType>, since: Option<DateTime<Utc>>) -> Result<u64, Error> {
+        let mut filter = message_filter(&user)?;
+        if let Some(type_) = type_ {
+            filter.insert("type", type_.to_string());
+        }
+        if let Some(since) = since {
+            filter.insert("created_at", doc! {"$gte": BsonDateTime::from_chrono(since)});
+        }
+        debug!("filter: {}", filter);
+        let count = self.count_documents(filter, None).await
+            .with_context(|| "count_documents".to_string())?;
+        Ok(count)
+    }
+
+    async fn find_first_and_last(&self, user: User) -> Result<(Option<Message>, Option<Message>), Error> {
+        let filter = message_filter(&user)?;
+        let find = |direction: i32| {
+            let filter = filter.clone();
+            async move {
+                let opts = FindOneOptions::builder().sort(doc! {"created_at": direction}).build();
+                let msg = self.find_one(filter, opts).await
+                    .with_context(|| "find_one".to_string())?;
+                match msg {
+                    Some(msg) => Ok::<_, Error>(Some(msg.into_entity()?)),
+                    None => Ok(None),
+                }
+            }
+        };
+        Ok((find(1).await?, find(-1).await?))
+    }
+
+    async fn delete_by_user(&self, user: User) -> Result<u64, Error> {
+        let res = self.delete_many(message_filter(&user)?, None).await
+            .with_context(|| "delete_many".to_string())?;
+        Ok(res.deleted_count)
+    }
+
+    async fn watch(&self, user: User) -> Result<Option<MessageStream>, Error> {
+        let pipeline = [doc! {
+            "$match": {
+                "operationType": "insert",
+                "fullDocument.org_id": parse_oid(user.org_id.as_str())?,
+                "fullDocument.user_id": parse_oid(user.id.as_str())?,
+            },
+        }];
+        let changes = match Collection::watch(self, pipeline, None).await {
+            Ok(changes) => changes,
+            Err(err) if is_change_stream_unsupported(&err) => return Ok(None),
+            Err(err) => return Err(anyhow::Error::from(err).context("watch").into()),
+        };
+        Ok(Some(changes
+            .map(|event| {
+                let event = event.with_context(|| "change stream".to_string())?;
+                let doc = event.full_document
+                    .ok_or(anyhow::anyhow!("change stream insert without fullDocument: {:?}", event.document_key))?;
+                doc.into_entity()
+            })
+            .boxed()))
+    }
+
+    async fn count_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error> {
+        let mut filter = message_filter(&user)?;
+        filter.insert("created_at", doc! {"$lt": BsonDateTime::from_chrono(before)});
+        let count = self.count_documents(filter, None).await
+            .with_context(|| "count_documents".to_string())?;
+        Ok(count)
+    }
+
+    async fn delete_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error> {
+        let mut filter = message_filter(&user)?;
+        filter.insert("created_at", doc! {"$lt": BsonDateTime::from_chrono(before)});
+        let res = self.delete_many(filter, None).await
+            .with_context(|| "delete_many".to_string())?;
+        Ok(res.deleted_count)
+    }
+
+    async fn org_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, tz: Tz) -> Result<MessageUsage, Error> {
+        let filter = doc! {
+            "org_id": parse_oid(org_id.as_str())?,
+            "created_at": {"$gte": BsonDateTime::from_chrono(start), "$lt": BsonDateTime::from_chrono(end)},
+        };
+        let pipeline = vec![
+            doc! {"$match": filter.clone()},
+            doc! {"$group": {
+                "_id": {
+                    "date": {"$dateToString": {"format": "%Y-%m-%d", "date": "$created_at", "timezone": tz.name()}},
+                    "type": "$type",
+                },
+                "count": {"$sum": 1},
+                "users": {"$addToSet": "$user_id"},
+            }},
+        ];
+        let mut cursor = self.aggregate(pipeline, None).await
+            .with_context(|| "aggregate".to_string())?;
+        let mut daily: BTreeMap<String, DailyUsage> = BTreeMap::new();
+        let mut usage = MessageUsage::default();
+        while let Some(group) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            let key = group.get_document("_id").with_context(|| "group _id".to_string())?;
+            let date = key.get_str("date").with_context(|| "group date".to_string())?.to_string();
+            let count = bson_to_u64(group.get("count"));
+            let day = daily.entry(date.clone()).or_insert_with(|| DailyUsage { date, ..Default::default() });
+            if key.get_str("type").ok() == Some(MessageRoleType::User.to_string().as_str()) {
+                day.user_message_count += count;
+                day.active_user_count = group.get_array("users").map(|users| users.len() as u64).unwrap_or_default();
+                usage.user_message_count += count;
+            } else {
+                day.ai_message_count += count;
+                usage.ai_message_count += count;
+            }
+        }
+        let mut user_filter = filter;
+        user_filter.insert("type", MessageRoleType::User.to_string());
+        let active_users = self.distinct("user_id", user_filter, None).await
+            .with_context(|| "distinct user_id".to_string())?;
+        usage.active_user_count = active_users.len() as u64;
+        usage.daily = daily.into_values().collect();
+        Ok(usage)
+    }
+
+    async fn model_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<ModelUsage>, Error> {
+        let pipeline = vec![
+            doc! {"$match": {
+                "org_id": parse_oid(org_id.as_str())?,
+                "type": MessageRoleType::AI.to_string(),
+                "created_at": {"$gte": BsonDateTime::from_chrono(start), "$lt": BsonDateTime::from_chrono(end)},
+            }},
+            doc! {"$group": {
+                "_id": {"$ifNull": ["$model", ""]},
+                "count": {"$sum": 1},
+                "prompt_tokens": {"$sum": "$usage.prompt_tokens"},
+                "completion_tokens": {"$sum": "$usage.completion_tokens"},
+                "total_tokens": {"$sum": "$usage.total_tokens"},
+                "latency_count": {"$sum": {"$cond": [{"$isNumber": "$latency_ms"}, 1, 0]}},
+                "latency_ms_sum": {"$sum": "$latency_ms"},
+            }},
+            doc! {"$sort": {"count": -1, "_id": 1}},
+        ];
+        let mut cursor = self.aggregate(pipeline, None).await
+            .with_context(|| "aggregate model usage".to_string())?;
+        let mut res = vec![];
+        while let Some(group) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            res.push(ModelUsage {
+                model: group.get_str("_id").unwrap_or_default().to_string(),
+                message_count: bson_to_u64(group.get("count")),
+                prompt_tokens: bson_to_u64(group.get("prompt_tokens")),
+                completion_tokens: bson_to_u64(group.get("completion_tokens")),
+                total_tokens: bson_to_u64(group.get("total_tokens")),
+                latency_count: bson_to_u64(group.get("latency_count")),
+                latency_ms_sum: bson_to_u64(group.get("latency_ms_sum")),
+            });
+        }
+        Ok(res)
+    }
+
+    async fn top_users(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, limit: u64) -> Result<Vec<UserActivity>, Error> {
+        let pipeline = vec![
+            doc! {"$match": {
+                "org_id": parse_oid(org_id.as_str())?,
+                "created_at": {"$gte": BsonDateTime::from_chrono(start), "$lt": BsonDateTime::from_chrono(end)},
+            }},
+            doc! {"$group": {
+                "_id": "$user_id",
+                "user_message_count": {"$sum": {"$cond": [{"$eq": ["$type", MessageRoleType::User.to_string()]}, 1, 0]}},
+                "total_tokens": {"$sum": "$usage.total_tokens"},
+            }},
+            doc! {"$match": {"user_message_count": {"$gt": 0}}},
+            doc! {"$sort": {"user_message_count": -1, "_id": 1}},
+            doc! {"$limit": limit as i64},
+        ];
+        let mut cursor = self.aggregate(pipeline, None).await
+            .with_context(|| "aggregate top users".to_string())?;
+        let mut res = vec![];
+        while let Some(group) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            res.push(UserActivity {
+                user_id: group.get_object_id("_id").with_context(|| "group _id".to_string())?.to_hex(),
+                user_message_count: bson_to_u64(group.get("user_message_count")),
+                total_tokens: bson_to_u64(group.get("total_tokens")),
+            });
+        }
+        Ok(res)
+    }
+
+    async fn backfill_org_id(&self, org_id: String) -> Result<u64, Error> {
+        let update = doc! {"$set": {"org_id": parse_oid(org_id.as_str())?}};
+        let res = self.update_many(doc! {"org_id": {"$exists": false}}, update, None).await
+            .with_context(|| "backfill message.org_id".to_string())?;
+        Ok(res.modified_count)
+    }
+}
+
+#[rocket::async_trait]
+impl OrganizationRepository for Collection<OrganizationDoc> {
+    async fn find_by_id(&self, org_id: String) -> Result<Option<Organization>, Error> {
+        let Ok(id) = parse_oid(org_id.as_str()) else {
+            return Ok(None);
+        };
+        let org = self.find_one(doc! {"_id": id}, None).await
+            .with_context(|| format!("find_one by _id {}", id))?;
+        if let Some(org) = org {
+            Ok(Some(org.into_entity()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn insert(&self, org: Organization) -> Result<Organization, Error> {
+        let doc = OrganizationDoc::from_entity(org)?;
+        match self.insert_one(doc.clone(), None).await {
+            Ok(_) => doc.into_entity(),
+            Err(err) if is_duplicate_key_error(&err) => Err(Error::Feedback(Code::OrganizationAlreadyExists)),
+            Err(err) => Err(anyhow::Error::from(err).context("insert_one").into()),
+        }
+    }
+
+    async fn insert_if_absent(&self, org: Organization) -> Result<(), Error> {
+        let doc = OrganizationDoc::from_entity(org)?;
+        let opts = UpdateOptions::builder().upsert(true).build();
+        self.update_one(
+            doc! {"_id": doc._id},
+            doc! {"$setOnInsert": {"name": doc.name, "created_at": doc.created_at}},
+            opts,
+        ).await.with_context(|| format!("upsert organization {}", doc._id))?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Organization>, Error> {
+        let opts = FindOptions::builder().sort(doc! {"created_at": 1}).build();
+        let mut cursor = self.find(None, opts).await
+            .with_context(|| "find".to_string())?;
+        let mut res = vec![];
+        while let Some(doc) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            res.push(doc.into_entity()?)
+        }
+        Ok(res)
+    }
+
+    async fn update_quota(&self, org_id: String, daily_message_limit: Option<i64>, burst_message_limit: Option<i64>) -> Result<Option<Organization>, Error> {
+        let Ok(id) = parse_oid(org_id.as_str()) else {
+            return Ok(None);
+        };
+        let update = doc! {
+            "$set": {
+                "daily_message_limit": daily_message_limit,
+                "burst_message_limit": burst_message_limit,
+                "updated_at": BsonDateTime::now(),
+            }
+        };
+        self.update_one(doc! {"_id": id}, update, None).await
+            .with_context(|| format!("update_one by _id {}", id))?;
+        self.find_by_id(org_id).await
+    }
+
+    async fn update_retention(&self, org_id: String, message_retention_days: Option<i64>) -> Result<Option<Organization>, Error> {
+        let Ok(id) = parse_oid(org_id.as_str()) else {
+            return Ok(None);
+        };
+        let update = doc! {
+            "$set": {
+                "message_retention_days": message_retention_days,
+                "updated_at": BsonDateTime::now(),
+            }
+        };
+        self.update_one(doc! {"_id": id}, update, None).await
+            .with_context(|| format!("update_one by _id {}", id))?;
+        self.find_by_id(org_id).await
+    }
+}
+
+/// 限定在组织内的角色
+fn persona_filter(org_id: &str, persona_id: &str) -> Option<Document> {
+    let (Ok(org_id), Ok(persona_id)) = (parse_oid(org_id), parse_oid(persona_id)) else {
+        return None;
+    };
+    Some(doc! {"_id": persona_id, "org_id": org_id})
+}
+
+#[rocket::async_trait]
+impl PersonaRepository for Collection<PersonaDoc> {
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<Persona>, Error> {
+        let Ok(org_id) = parse_oid(org_id.as_str()) else {
+            return Ok(None);
+        };
+        let persona = self.find_one(doc! {"org_id": org_id, "name": name.as_str()}, None).await
+            .with_context(|| format!("find_one by org_id {} and name {}", org_id, name))?;
+        Ok(persona.map(PersonaDoc::into_entity))
+    }
+
+    async fn insert(&self, persona: Persona) -> Result<Persona, Error> {
+        let doc = PersonaDoc::from_entity(persona)?;
+        match self.insert_one(doc.clone(), None).await {
+            Ok(_) => Ok(doc.into_entity()),
+            Err(err) if is_duplicate_key_error(&err) => Err(Error::Feedback(Code::PersonaAlreadyExists)),
+            Err(err) => Err(anyhow::Error::from(err).context("insert_one").into()),
+        }
+    }
+
+    async fn list(&self, org_id: String) -> Result<Vec<Persona>, Error> {
+        let Ok(org_id) = parse_oid(org_id.as_str()) else {
+            return Ok(vec![]);
+        };
+        let opts = FindOptions::builder().sort(doc! {"name": 1}).build();
+        let mut cursor = self.find(doc! {"org_id": org_id}, opts).await
+            .with_context(|| format!("find by org_id {}", org_id))?;
+        let mut res = vec![];
+        while let Some(doc) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            res.push(doc.into_entity())
+        }
+        Ok(res)
+    }
+
+    async fn update_prompt(&self, org_id: String, persona_id: String, prompt: String) -> Result<Option<Persona>, Error> {
+        let Some(filter) = persona_filter(org_id.as_str(), persona_id.as_str()) else {
+            return Ok(None);
+        };
+        let update = doc! {
+            "$set": {
+                "prompt": prompt,
+                "updated_at": BsonDateTime::now(),
+            }
+        };
+        let opts = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        let persona = self.find_one_and_update(filter, update, opts).await
+            .with_context(|| format!("find_one_and_update persona {}", persona_id))?;
+        Ok(persona.map(PersonaDoc::into_entity))
+    }
+
+    async fn delete(&self, org_id: String, persona_id: String) -> Result<Option<Persona>, Error> {
+        let Some(filter) = persona_filter(org_id.as_str(), persona_id.as_str()) else {
+            return Ok(None);
+        };
+        let persona = self.find_one_and_delete(filter, None).await
+            .with_context(|| format!("find_one_and_delete persona {}", persona_id))?;
+        Ok(persona.map(PersonaDoc::into_entity))
+    }
+}
+
+#[rocket::async_trait]
+impl ApiKeyRepository for Collection<ApiKeyDoc> {
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<ApiKey, Error> {
+        let doc = ApiKeyDoc::from_entity(api_key, key_hash)?;
+        self.insert_one(doc.clone(), None).await
+            .with_context(|| "insert_one".to_string())?;
+        doc.into_entity()
+    }
+
+    async fn find_active_by_hash(&self, key_hash: String) -> Result<Option<ApiKey>, Error> {
+        let filter = doc! {"key_hash": key_hash, "revoked_at": null};
+        let api_key = self.find_one(filter, None).await
+            .with_context(|| "find_one by key_hash".to_string())?;
+        let Some(api_key) = api_key else {
+            return Ok(None);
+        };
+        self.update_one(doc! {"_id": api_key._id}, doc! {"$set": {"last_used_at": BsonDateTime::now()}}, None).await
+            .with_context(|| format!("update_one by _id {}", api_key._id))?;
+        Ok(Some(api_key.into_entity()?))
+    }
+
+    async fn list_by_user(&self, user_id: String) -> Result<Vec<ApiKey>, Error> {
+        let opts = FindOptions::builder().sort(doc! {"created_at": -1}).build();
+        let mut cursor = self.find(doc! {"user_id": parse_oid(user_id.as_str())?}, opts).await
+            .with_context(|| "find".to_string())?;
+        let mut res = vec![];
+        while let Some(doc) = cursor.try_next().await
+            .with_context(|| "try_next".to_string())? {
+            res.push(doc.into_entity()?)
+        }
+        Ok(res)
+    }
+
+    async fn revoke(&self, user_id: String, api_key_id: String) -> Result<Option<ApiKey>, Error> {
+        let Ok(id) = parse_oid(api_key_id.as_str()) else {
+            return Ok(None);
+        };
+        let filter = doc! {"_id": id, "user_id": parse_oid(user_id.as_str())?};
+        self.update_one(filter.clone(), doc! {"$set": {"revoked_at": BsonDateTime::now()}}, None).await
+            .with_context(|| format!("update_one by _id {}", id))?;
+        let api_key = self.find_one(filter, None).await
+            .with_context(|| format!("find_one by _id {}", id))?;
+        if let Some(api_key) = api_key {
+            Ok(Some(api_key.into_entity()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn count_active_by_user(&self, user_id: String) -> Result<u64, Error> {
+        let count = self.count_documents(doc! {"user_id": parse_oid(user_id.as_str())?, "revoked_at": null}, None).await
+            .with_context(|| "count_documents".to_string())?;
+        Ok(count)
+    }
+
+    async fn delete_by_user(&self, user_id: String) -> Result<u64, Error> {
+        let res = self.delete_many(doc! {"user_id": parse_oid(user_id.as_str())?}, None).await
+            .with_context(|| "delete_many".to_string())?;
+        Ok(res.deleted_count)
+    }
+}
+
+#[rocket::async_trait]
+impl QuotaStore for Collection<QuotaDoc> {
+    async fn load(&self, user_id: ObjectId) -> Result<Option<QuotaDoc>, Error> {
+        let quota = self.find_one(doc! {"_id": user_id}, None).await
+            .with_context(|| format!("find_one by _id {}", user_id))?;
+        Ok(quota)
+    }
+
+    async fn insert(&self, quota: QuotaDoc) -> Result<bool, Error> {
+        match self.insert_one(quota, None).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_duplicate_key_error(&err) => Ok(false),
+            Err(err) => Err(anyhow::Error::from(err).context("insert_one").into()),
+        }
+    }
+
+    async fn swap(&self, expected_version: i64, quota: QuotaDoc) -> Result<bool, Error> {
+        let filter = doc! {"_id": quota._id, "version": expected_version};
+        let res = self.replace_one(filter, quota, None).await
+            .with_context(|| "replace_one".to_string())?;
+        Ok(res.matched_count == 1)
+    }
+
+    async fn delete(&self, user_id: ObjectId) -> Result<(), Error> {
+        self.delete_one(doc! {"_id": user_id}, None).await
+            .with_context(|| format!("delete_one by _id {}", user_id))?;
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl HealthCheck for Database {
+    async fn ping(&self) -> Result<(), Error> {
+        self.run_command(doc! {"ping": 1}, None).await
+            .map_err(|err| Error::DatabasePingError(err.to_string()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1215 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use mongodb::bson::DateTime as BsonDateTime;
+use mongodb::bson::oid::ObjectId;
+use sea_query::{Alias, Condition, Expr, Func, Iden, LikeExpr, OnConflict, Order, PostgresQueryBuilder, Query, SelectStatement, SimpleExpr};
+use sea_query::extension::postgres::PgExpr;
+use sea_query_binder::SqlxBinder;
+use sqlx::{FromRow, PgPool};
+use sqlx::types::Json;
+use crate::error::{Code, Error};
+use crate::model::{ApiKey, DailyUsage, ListUsersInput, Message, MessageRoleType, Organization, Persona, QuotaDoc, Role, TokenUsage, User, UserPreferences};
+use crate::store::postgres::is_unique_violation;
+use crate::store::repository::{ApiKeyRepository, Direction, HealthCheck, MessageRepository, MessageStream, MessageUsage, ModelUsage, OrganizationRepository, PersonaRepository, QuotaStore, UserActivity, UserRepository};
+
+#[derive(Iden, Clone, Copy)]
+enum Users {
+    Table,
+    Id,
+    OrgId,
+    Name,
+    Role,
+    Disabled,
+    Preferences,
+    MessageRetentionDays,
+    CreatedAt,
+    UpdatedAt,
+}
+
+const USER_COLUMNS: [Users; 9] = [
+    Users::Id, Users::OrgId, Users::Name, Users::Role, Users::Disabled,
+    Users::Preferences, Users::MessageRetentionDays, Users::CreatedAt, Users::UpdatedAt,
+];
+
+#[derive(Debug, FromRow)]
+struct UserRow {
+    id: String,
+    org_id: String,
+    name: String,
+    role: String,
+    disabled: bool,
+    preferences: Json<UserPreferences>,
+    message_retention_days: Option<i64>,
+    created_at: NaiveDateTime,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl TryFrom<UserRow> for User {
+    type Error = Error;
+
+    fn try_from(row: UserRow) -> Result<Self, Error> {
+        Ok(User {
+            id: row.id,
+            org_id: row.org_id,
+            name: row.name,
+            preferences: row.preferences.0,
+            role: Role::from_str(row.role.as_str())?,
+            disabled: row.disabled,
+            message_retention_days: row.message_retention_days,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+#[derive(Iden, Clone, Copy)]
+enum Messages {
+    Table,
+    Id,
+    OrgId,
+    UserId,
+    Type,
+    Text,
+    CreatedAt,
+    CreatedBy,
+    UpdatedAt,
+    UpdatedBy,
+    Model,
+    Usage,
+    LatencyMs,
+}
+
+const MESSAGE_COLUMNS: [Messages; 12] = [
+    Messages::Id, Messages::OrgId, Messages::UserId, Messages::Type, Messages::Text,
+    Messages::CreatedAt, Messages::CreatedBy, Messages::UpdatedAt, Messages::UpdatedBy,
+    Messages::Model, Messages::Usage, Messages::LatencyMs,
+];
+
+#[derive(Debug, FromRow)]
+struct MessageRow {
+    id: String,
+    org_id: String,
+    user_id: String,
+    #[sqlx(rename = "type")]
+    type_: String,
+    text: String,
+    created_at: NaiveDateTime,
+    created_by: String,
+    updated_at: Option<NaiveDateTime>,
+    updated_by: Option<String>,
+    model: Option<String>,
+    usage: Option<Json<TokenUsage>>,
+    latency_ms: Option<i64>,
+}
+
+impl TryFrom<MessageRow> for Message {
+    type Error = Error;
+
+    fn try_from(row: MessageRow) -> Result<Self, Error> {
+        Ok(Message {
+            id: row.id,
+            org_id: row.org_id,
+            user_id: row.user_id,
+            type_: MessageRoleType::from_str(row.type_.as_str())?,
+            text: row.text,
+            created_at: row.created_at,
+            created_by: row.created_by,
+            updated_at: row.updated_at,
+            updated_by: row.updated_by,
+            model: row.model,
+            usage: row.usage.map(|usage| usage.0),
+            latency_ms: row.latency_ms.map(|latency_ms| latency_ms.max(0) as u64),
+        })
+    }
+}
+
+#[derive(Iden, Clone, Copy)]
+enum Organizations {
+    Table,
+    Id,
+    Name,
+    DailyMessageLimit,
+    BurstMessageLimit,
+    MessageRetentionDays,
+    CreatedAt,
+    UpdatedAt,
+}
+
+const ORGANIZATION_COLUMNS: [Organizations; 7] = [
+    Organizations::Id, Organizations::Name, Organizations::DailyMessageLimit,
+    Organizations::BurstMessageLimit, Organizations::MessageRetentionDays,
+    Organizations::CreatedAt, Organizations::UpdatedAt,
+];
+
+#[derive(Debug, FromRow)]
+struct OrganizationRow {
+    id: String,
+    name: String,
+    daily_message_limit: Option<i64>,
+    burst_message_limit: Option<i64>,
+    message_retention_days: Option<i64>,
+    created_at: NaiveDateTime,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl From<OrganizationRow> for Organization {
+    fn from(row: OrganizationRow) -> Self {
+        Organization {
+            id: row.id,
+            name: row.name,
+            daily_message_limit: row.daily_message_limit,
+            burst_message_limit: row.burst_message_limit,
+            message_retention_days: row.message_retention_days,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+#[derive(Iden, Clone, Copy)]
+enum ApiKeys {
+    Table,
+    Id,
+    UserId,
+    Name,
+    Prefix,
+    KeyHash,
+    CreatedAt,
+    LastUsedAt,
+    RevokedAt,
+}
+
+const API_KEY_COLUMNS: [ApiKeys; 7] = [
+    ApiKeys::Id, ApiKeys::UserId, ApiKeys::Name, ApiKeys::Prefix,
+    ApiKeys::CreatedAt, ApiKeys::LastUsedAt, ApiKeys::RevokedAt,
+];
+
+#[derive(Debug, FromRow)]
+struct ApiKeyRow {
+    id: String,
+    user_id: String,
+    name: String,
+    prefix: String,
+    created_at: NaiveDateTime,
+    last_used_at: Option<NaiveDateTime>,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        ApiKey {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            prefix: row.prefix,
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+        }
+    }
+}
+
+#[derive(Iden, Clone, Copy)]
+enum Quotas {
+    Table,
+    UserId,
+    Day,
+    DayCount,
+    Recent,
+    Pending,
+    Version,
+}
+
+const QUOTA_COLUMNS: [Quotas; 6] = [
+    Quotas::UserId, Quotas::Day, Quotas::DayCount, Quotas::Recent, Quotas::Pending, Quotas::Version,
+];
+
+#[derive(Debug, FromRow)]
+struct QuotaRow {
+    user_id: String,
+    day: String,
+    day_count: i64,
+    recent: Json<Vec<i64>>,
+    pending: i64,
+    version: i64,
+}
+
+impl TryFrom<QuotaRow> for QuotaDoc {
+    type Error = Error;
+
+    fn try_from(row: QuotaRow) -> Result<Self, Error> {
+        Ok(QuotaDoc {
+            _id: ObjectId::from_str(row.user_id.as_str())?,
+            day: row.day,
+            day_count: row.day_count,
+            recent: row.recent.0.into_iter().map(BsonDateTime::from_millis).collect(),
+            pending: row.pending,
+            version: row.version,
+        })
+    }
+}
+
+/// recent 以毫秒时间戳数组保存为 JSONB
+fn quota_recent(quota: &QuotaDoc) -> serde_json::Value {
+    serde_json::Value::from(quota.recent.iter().map(|dt| dt.timestamp_millis()).collect::<Vec<i64>>())
+}
+
+/// LIKE 的通配符按普通字符匹配
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// 限定在用户所在组织内的用户
+fn user_condition(user: &User) -> Condition {
+    Condition::all()
+        .add(Expr::col(Users::Id).eq(user.id.as_str()))
+        .add(Expr::col(Users::OrgId).eq(user.org_id.as_str()))
+}
+
+/// 用户的消息, 限定在用户所在组织内
+fn message_condition(user: &User) -> Condition {
+    Condition::all()
+        .add(Expr::col(Messages::OrgId).eq(user.org_id.as_str()))
+        .add(Expr::col(Messages::UserId).eq(user.id.as_str()))
+}
+
+pub struct PgUsers {
+    pool: PgPool,
+}
+
+impl PgUsers {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn find_one(&self, cond: Condition) -> Result<Option<User>, Error> {
+        let (sql, values) = Query::select()
+            .columns(USER_COLUMNS)
+            .from(Users::Table)
+            .cond_where(cond)
+            .build_sqlx(PostgresQueryBuilder);
+        let row = sqlx::query_as_with::<_, UserRow, _>(&sql, values)
+            .fetch_optional(&self.pool).await
+            .with_context(|| format!("fetch_optional: {}", sql))?;
+        match row {
+            Some(row) => Ok(Some(User::try_from(row)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl UserRepository for PgUsers {
+    async fn find_by_id(&self, user_id: String) -> Result<Option<User>, Error> {
+        self.find_one(Condition::all().add(Expr::col(Users::Id).eq(user_id))).await
+    }
+
+    async fn find_in_org_by_id(&self, org_id: String, user_id: String) -> Result<Option<User>, Error> {
+        self.find_one(Condition::all()
+            .add(Expr::col(Users::Id).eq(user_id))
+            .add(Expr::col(Users::OrgId).eq(org_id))).await
+    }
+
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<User>, Error> {
+        self.find_one(Condition::all()
+            .add(Expr::col(Users::Name).eq(name))
+            .add(Expr::col(Users::OrgId).eq(org_id))).await
+    }
+
+    async fn insert(&self, user: User) -> Result<User, Error> {
+        let preferences = serde_json::to_value(&user.preferences)?;
+        let (sql, values) = Query::insert()
+            .into_table(Users::Table)
+            .columns(USER_COLUMNS)
+            .values([
+                user.id.clone().into(),
+                user.org_id.clone().into(),
+                user.name.clone().into(),
+                user.role.to_string().into(),
+                user.disabled.into(),
+                preferences.into(),
+                user.message_retention_days.into(),
+                user.created_at.into(),
+                user.updated_at.into(),
+            ])?
+            .build_sqlx(PostgresQueryBuilder);
+        match sqlx::query_with(&sql, values).execute(&self.pool).await {
+            Ok(_) => Ok(user),
+            Err(err) if is_unique_violation(&err) => Err(Error::Feedback(Code::UserAlreadyExists)),
+            Err(err) => Err(anyhow::Error::from(err).context("insert user").into()),
+        }
+    }
+
+    async fn insert_or_get(&self, user: User) -> Result<User, Error> {
+        let preferences = serde_json::to_value(&user.preferences)?;
+        let (sql, values) = Query::insert()
+            .into_table(Users::Table)
+            .columns(USER_COLUMNS)
+            .values([
+                user.id.clone().into(),
+                user.org_id.clone().into(),
+                user.name.clone().into(),
+                user.role.to_string().into(),
+                user.disabled.into(),
+                preferences.into(),
+                user.message_retention_days.into(),
+                user.created_at.into(),
+                user.updated_at.into(),
+            ])?
+            .on_conflict(OnConflict::columns([Users::OrgId, Users::Name]).do_nothing().to_owned())
+            .build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| "insert user if absent".to_string())?;
+        self.find_in_org_by_name(user.org_id, user.name).await?
+            .ok_or(Error::Feedback(Code::UserNotFound))
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, Error> {
+        let cond = user_condition(&user);
+        let preferences = serde_json::to_value(&user.preferences)?;
+        let (sql, values) = Query::update()
+            .table(Users::Table)
+            .value(Users::Name, user.name)
+            .value(Users::Role, user.role.to_string())
+            .value(Users::Disabled, user.disabled)
+            .value(Users::Preferences, preferences)
+            .value(Users::MessageRetentionDays, user.message_retention_days)
+            .value(Users::UpdatedAt, user.updated_at)
+            .cond_where(cond.clone())
+            .build_sqlx(PostgresQueryBuilder);
+        match sqlx::query_with(&sql, values).execute(&self.pool).await {
+            Ok(_) => {}
+            Err(err) if is_unique_violation(&err) => return Err(Error::Feedback(Code::UserAlreadyExists)),
+            Err(err) => return Err(anyhow::Error::from(err).context("update user").into()),
+        }
+        self.find_one(cond).await
+    }
+
+    async fn list(&self, org_id: String, req: ListUsersInput) -> Result<(Vec<User>, u64), Error> {
+        let mut cond = Condition::all().add(Expr::col(Users::OrgId).eq(org_id));
+        if let Some(name) = req.name.filter(|name| !name.is_empty()) {
+            let pattern = format!("%{}%", escape_like(name.as_str()));
+            cond = cond.add(Expr::col(Users::Name).ilike(LikeExpr::new(pattern).escape('\\')));
+        }
+        if let Some(disabled) = req.disabled {
+            cond = cond.add(Expr::col(Users::Disabled).eq(disabled));
+        }
+        if let Some(created_after) = req.created_after {
+            cond = cond.add(Expr::col(Users::CreatedAt).gte(created_after));
+        }
+        if let Some(created_before) = req.created_before {
+            cond = cond.add(Expr::col(Users::CreatedAt).lt(created_before));
+        }
+        let (sql, values) = Query::select()
+            .expr(Func::count(Expr::col(Users::Id)))
+            .from(Users::Table)
+            .cond_where(cond.clone())
+            .build_sqlx(PostgresQueryBuilder);
+        let total: i64 = sqlx::query_scalar_with(&sql, values)
+            .fetch_one(&self.pool).await
+            .with_context(|| format!("fetch_one: {}", sql))?;
+        let (sql, values) = Query::select()
+            .columns(USER_COLUMNS)
+            .from(Users::Table)
+            .cond_where(cond)
+            .order_by(Users::CreatedAt, Order::Desc)
+            .order_by(Users::Id, Order::Desc)
+            .offset(req.page.saturating_sub(1).saturating_mul(req.page_size))
+            .limit(req.page_size)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, UserRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        let mut users = vec![];
+        for row in rows {
+            users.push(User::try_from(row)?);
+        }
+        Ok((users, total as u64))
+    }
+
+    async fn count_in_org(&self, org_id: String) -> Result<u64, Error> {
+        let (sql, values) = Query::select()
+            .expr(Func::count(Expr::col(Users::Id)))
+            .from(Users::Table)
+            .and_where(Expr::col(Users::OrgId).eq(org_id))
+            .build_sqlx(PostgresQueryBuilder);
+        let count: i64 = sqlx::query_scalar_with(&sql, values)
+            .fetch_one(&self.pool).await
+            .with_context(|| format!("fetch_one: {}", sql))?;
+        Ok(count as u64)
+    }
+
+    async fn delete(&self, user: User) -> Result<u64, Error> {
+        let (sql, values) = Query::delete()
+            .from_table(Users::Table)
+            .cond_where(user_condition(&user))
+            .build_sqlx(PostgresQueryBuilder);
+        let res = sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("execute: {}", sql))?;
+        Ok(res.rows_affected())
+    }
+
+    async fn backfill_org_id(&self, _org_id: String) -> Result<u64, Error> {
+        // users.org_id 不为空, 没有需要归入默认组织的用户
+        Ok(0)
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct UsageRow {
+    date: String,
+    #[sqlx(rename = "type")]
+    type_: String,
+    count: i64,
+    users: i64,
+}
+
+/// 按日期和消息类型分组统计, created_at 保存的是 UTC 时间, 先转为 tz 的本地时间再取日期
+fn usage_query(cond: Condition, tz: Tz) -> SelectStatement {
+    let date = Expr::cust_with_values(
+        "to_char((\"created_at\" AT TIME ZONE 'UTC') AT TIME ZONE $1, 'YYYY-MM-DD')",
+        [tz.name()],
+    );
+    Query::select()
+        .expr_as(date, Alias::new("date"))
+        .column(Messages::Type)
+        .expr_as(Func::count(Expr::col(Messages::Id)), Alias::new("count"))
+        .expr_as(Func::count_distinct(Expr::col(Messages::UserId)), Alias::new("users"))
+        .from(Messages::Table)
+        .cond_where(cond)
+        .group_by_col(Alias::new("date"))
+        .group_by_col(Messages::Type)
+        .to_owned()
+}
+
+#[derive(Debug, FromRow)]
+struct ModelUsageRow {
+    model: String,
+    count: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    total_tokens: i64,
+    latency_count: i64,
+    latency_ms_sum: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct UserActivityRow {
+    user_id: String,
+    user_message_count: i64,
+    total_tokens: i64,
+}
+
+/// usage 中 token 字段之和, SUM(bigint) 的结果为 numeric, 转回 bigint
+fn sum_usage_tokens(field: &str) -> SimpleExpr {
+    Expr::cust(format!("COALESCE(SUM((\"usage\"->>'{}')::bigint), 0)::bigint", field))
+}
+
+fn range_condition(org_id: String, start: DateTime<Utc>, end: DateTime<Utc>) -> Condition {
+    Condition::all()
+        .add(Expr::col(Messages::OrgId).eq(org_id))
+        .add(Expr::col(Messages::CreatedAt).gte(start.naive_utc()))
+        .add(Expr::col(Messages::CreatedAt).lt(end.naive_utc()))
+}
+
+pub struct PgMessages {
+    pool: PgPool,
+}
+
+impl PgMessages {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// 按 (created_at, id) 排序
+    async fn find(&self, cond: Condition, order: Order, limit: u64) -> Result<Vec<Message>, Error> {
+        let (sql, values) = Query::select()
+            .columns(MESSAGE_COLUMNS)
+            .from(Messages::Table)
+            .cond_where(cond)
+            .order_by(Messages::CreatedAt, order.clone())
+            .order_by(Messages::Id, order)
+            .limit(limit)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, MessageRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        let mut res = vec![];
+        for row in rows {
+            res.push(Message::try_from(row)?);
+        }
+        Ok(res)
+    }
+}
+
+#[rocket::async_trait]
+impl MessageRepository for PgMessages {
+    async fn insert_many(&self, messages: Vec<Message>) -> Result<usize, Error> {
+        if messages.is_empty() {
+            return Ok(0);
+        }
+        let mut query = Query::insert();
+        query.into_table(Messages::Table).columns(MESSAGE_COLUMNS);
+        for message in messages {
+            let usage = message.usage.as_ref().map(serde_json::to_value).transpose()?;
+            query.values([
+                message.id.into(),
+                message.org_id.into(),
+                message.user_id.into(),
+                message.type_.to_string().into(),
+                message.text.into(),
+                message.created_at.into(),
+                message.created_by.into(),
+                message.updated_at.into(),
+                message.updated_by.into(),
+                message.model.into(),
+                usage.into(),
+                message.latency_ms.map(|latency_ms| latency_ms as i64).into(),
+            ])?;
+        }
+        let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+        let res = sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| "insert messages".to_string())?;
+        debug!("inserted: {:?}", res);
+        Ok(res.rows_affected() as usize)
+    }
+
+    async fn find_by_id(&self, user: User, message_id: String) -> Result<Option<Message>, Error> {
+        let cond = message_condition(&user).add(Expr::col(Messages::Id).eq(message_id));
+        Ok(self.find(cond, Order::Asc, 1).await?.into_iter().next())
+    }
+
+    async fn find_page(&self, user: User, cursor: Option<Message>, direction: Direction, limit: u64) -> Result<Vec<Message>, Error> {
+        let mut cond = message_condition(&user);
+        if let Some(cursor) = cursor {
+            // 同一轮对话的两条消息 created_at 相同, 以 id 区分先后
+            let columns = Expr::tuple([Expr::col(Messages::CreatedAt).into(), Expr::col(Messages::Id).into()]);
+            let values = Expr::tuple([Expr::val(cursor.created_at).into(), Expr::val(cursor.id).into()]);
+            cond = cond.add(match direction {
+                Direction::Older => columns.lt(values),
+                Direction::Newer => columns.gt(values),
+            });
+        }
+        let order = match direction {
+            Direction::Older => Order::Desc,
+            Direction::Newer => Order::Asc,
+        };
+        self.find(cond, order, limit).await
+    }
+
+    async fn count(&self, user: User, type_: Option<MessageRoleType>, since: Option<DateTime<Utc>>) -> Result<u64, Error> {
+        let mut cond = message_condition(&user);
+        if let Some(type_) = type_ {
+            cond = cond.add(Expr::col(Messages::Type).eq(type_.to_string()));
+        }
+        if let Some(since) = since {
+            cond = cond.add(Expr::col(Messages::CreatedAt).gte(since.naive_utc()));
+        }
+        let (sql, values) = Query::select()
+            .expr(Func::count(Expr::col(Messages::Id)))
+            .from(Messages::Table)
+            .cond_where(cond)
+            .build_sqlx(PostgresQueryBuilder);
+        let count: i64 = sqlx::query_scalar_with(&sql, values)
+            .fetch_one(&self.pool).await
+            .with_context(|| format!("fetch_one: {}", sql))?;
+        Ok(count as u64)
+    }
+
+    async fn find_first_and_last(&self, user: User) -> Result<(Option<Message>, Option<Message>), Error> {
+        let first = self.find(message_condition(&user), Order::Asc, 1).await?;
+        let last = self.find(message_condition(&user), Order::Desc, 1).await?;
+        Ok((first.into_iter().next(), last.into_iter().next()))
+    }
+
+    async fn delete_by_user(&self, user: User) -> Result<u64, Error> {
+        let (sql, values) = Query::delete()
+            .from_table(Messages::Table)
+            .cond_where(message_condition(&user))
+            .build_sqlx(PostgresQueryBuilder);
+        let res = sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("execute: {}", sql))?;
+        Ok(res.rows_affected())
+    }
+
+    async fn watch(&self, user: User) -> Result<Option<MessageStream>, Error> {
+        Ok(None)
+    }
+
+    async fn count_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error> {
+        let (sql, values) = Query::select()
+            .expr(Func::count(Expr::col(Messages::Id)))
+            .from(Messages::Table)
+            .cond_where(message_condition(&user).add(Expr::col(Messages::CreatedAt).lt(before.naive_utc())))
+            .build_sqlx(PostgresQueryBuilder);
+        let count: i64 = sqlx::query_scalar_with(&sql, values)
+            .fetch_one(&self.pool).await
+            .with_context(|| format!("fetch_one: {}", sql))?;
+        Ok(count as u64)
+    }
+
+    async fn delete_before(&self, user: User, before: DateTime<Utc>) -> Result<u64, Error> {
+        let (sql, values) = Query::delete()
+            .from_table(Messages::Table)
+            .cond_where(message_condition(&user).add(Expr::col(Messages::CreatedAt).lt(before.naive_utc())))
+            .build_sqlx(PostgresQueryBuilder);
+        let res = sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("execute: {}", sql))?;
+        Ok(res.rows_affected())
+    }
+
+    async fn org_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, tz: Tz) -> Result<MessageUsage, Error> {
+        let cond = range_condition(org_id, start, end);
+        let (sql, values) = usage_query(cond.clone(), tz).build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, UsageRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        let mut daily: BTreeMap<String, DailyUsage> = BTreeMap::new();
+        let mut usage = MessageUsage::default();
+        for row in rows {
+            let count = row.count as u64;
+            let day = daily.entry(row.date.clone()).or_insert_with(|| DailyUsage { date: row.date, ..Default::default() });
+            if row.type_ == MessageRoleType::User.to_string() {
+                day.user_message_count += count;
+                day.active_user_count = row.users as u64;
+                usage.user_message_count += count;
+            } else {
+                day.ai_message_count += count;
+                usage.ai_message_count += count;
+            }
+        }
+        let (sql, values) = Query::select()
+            .expr(Func::count_distinct(Expr::col(Messages::UserId)))
+            .from(Messages::Table)
+            .cond_where(cond.add(Expr::col(Messages::Type).eq(MessageRoleType::User.to_string())))
+            .build_sqlx(PostgresQueryBuilder);
+        let active_users: i64 = sqlx::query_scalar_with(&sql, values)
+            .fetch_one(&self.pool).await
+            .with_context(|| format!("fetch_one: {}", sql))?;
+        usage.active_user_count = active_users as u64;
+        usage.daily = daily.into_values().collect();
+        Ok(usage)
+    }
+
+    async fn model_usage(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<ModelUsage>, Error> {
+        let (sql, values) = Query::select()
+            .expr_as(Expr::cust("COALESCE(\"model\", '')"), Alias::new("model"))
+            .expr_as(Func::count(Expr::col(Messages::Id)), Alias::new("count"))
+            .expr_as(sum_usage_tokens("prompt_tokens"), Alias::new("prompt_tokens"))
+            .expr_as(sum_usage_tokens("completion_tokens"), Alias::new("completion_tokens"))
+            .expr_as(sum_usage_tokens("total_tokens"), Alias::new("total_tokens"))
+            .expr_as(Func::count(Expr::col(Messages::LatencyMs)), Alias::new("latency_count"))
+            .expr_as(Expr::cust("COALESCE(SUM(\"latency_ms\"), 0)::bigint"), Alias::new("latency_ms_sum"))
+            .from(Messages::Table)
+            .cond_where(range_condition(org_id, start, end).add(Expr::col(Messages::Type).eq(MessageRoleType::AI.to_string())))
+            .group_by_col(Alias::new("model"))
+            .order_by(Alias::new("count"), Order::Desc)
+            .order_by(Alias::new("model"), Order::Asc)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, ModelUsageRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        Ok(rows.into_iter()
+            .map(|row| ModelUsage {
+                model: row.model,
+                message_count: row.count as u64,
+                prompt_tokens: row.prompt_tokens as u64,
+                completion_tokens: row.completion_tokens as u64,
+                total_tokens: row.total_tokens as u64,
+                latency_count: row.latency_count as u64,
+                latency_ms_sum: row.latency_ms_sum as u64,
+            })
+            .collect())
+    }
+
+    async fn top_users(&self, org_id: String, start: DateTime<Utc>, end: DateTime<Utc>, limit: u64) -> Result<Vec<UserActivity>, Error> {
+        let user_message_count = Expr::cust_with_values(
+            "COUNT(*) FILTER (WHERE \"type\" = $1)",
+            [MessageRoleType::User.to_string()],
+        );
+        let (sql, values) = Query::select()
+            .column(Messages::UserId)
+            .expr_as(user_message_count.clone(), Alias::new("user_message_count"))
+            .expr_as(sum_usage_tokens("total_tokens"), Alias::new("total_tokens"))
+            .from(Messages::Table)
+            .cond_where(range_condition(org_id, start, end))
+            .group_by_col(Messages::UserId)
+            .and_having(Expr::expr(user_message_count).gt(0))
+            .order_by(Alias::new("user_message_count"), Order::Desc)
+            .order_by(Messages::UserId, Order::Asc)
+            .limit(limit)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, UserActivityRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        Ok(rows.into_iter()
+            .map(|row| UserActivity {
+                user_id: row.user_id,
+                user_message_count: row.user_message_count as u64,
+                total_tokens: row.total_tokens as u64,
+            })
+            .collect())
+    }
+
+    async fn backfill_org_id(&self, _org_id: String) -> Result<u64, Error> {
+        // messages.org_id 不为空, 没有需要归入默认组织的消息
+        Ok(0)
+    }
+}
+
+pub struct PgOrganizations {
+    pool: PgPool,
+}
+
+impl PgOrganizations {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    fn insert_query(org: &Organization) -> Result<sea_query::InsertStatement, Error> {
+        let mut query = Query::insert();
+        query.into_table(Organizations::Table)
+            .columns(ORGANIZATION_COLUMNS)
+            .values([
+                org.id.clone().into(),
+                org.name.clone().into(),
+                org.daily_message_limit.into(),
+                org.burst_message_limit.into(),
+                org.message_retention_days.into(),
+                org.created_at.into(),
+                org.updated_at.into(),
+            ])?;
+        Ok(query)
+    }
+}
+
+#[rocket::async_trait]
+impl OrganizationRepository for PgOrganizations {
+    async fn find_by_id(&self, org_id: String) -> Result<Option<Organization>, Error> {
+        let (sql, values) = Query::select()
+            .columns(ORGANIZATION_COLUMNS)
+            .from(Organizations::Table)
+            .and_where(Expr::col(Organizations::Id).eq(org_id))
+            .build_sqlx(PostgresQueryBuilder);
+        let row = sqlx::query_as_with::<_, OrganizationRow, _>(&sql, values)
+            .fetch_optional(&self.pool).await
+            .with_context(|| format!("fetch_optional: {}", sql))?;
+        Ok(row.map(Organization::from))
+    }
+
+    async fn insert(&self, org: Organization) -> Result<Organization, Error> {
+        let (sql, values) = Self::insert_query(&org)?.build_sqlx(PostgresQueryBuilder);
+        match sqlx::query_with(&sql, values).execute(&self.pool).await {
+            Ok(_) => Ok(org),
+            Err(err) if is_unique_violation(&err) => Err(Error::Feedback(Code::OrganizationAlreadyExists)),
+            Err(err) => Err(anyhow::Error::from(err).context("insert organization").into()),
+        }
+    }
+
+    async fn insert_if_absent(&self, org: Organization) -> Result<(), Error> {
+        let (sql, values) = Self::insert_query(&org)?
+            .on_conflict(OnConflict::column(Organizations::Id).do_nothing().to_owned())
+            .build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("upsert organization {}", org.id))?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Organization>, Error> {
+        let (sql, values) = Query::select()
+            .columns(ORGANIZATION_COLUMNS)
+            .from(Organizations::Table)
+            .order_by(Organizations::CreatedAt, Order::Asc)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, OrganizationRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        Ok(rows.into_iter().map(Organization::from).collect())
+    }
+
+    async fn update_quota(&self, org_id: String, daily_message_limit: Option<i64>, burst_message_limit: Option<i64>) -> Result<Option<Organization>, Error> {
+        let (sql, values) = Query::update()
+            .table(Organizations::Table)
+            .value(Organizations::DailyMessageLimit, daily_message_limit)
+            .value(Organizations::BurstMessageLimit, burst_message_limit)
+            .value(Organizations::UpdatedAt, Utc::now().naive_utc())
+            .and_where(Expr::col(Organizations::Id).eq(org_id.as_str()))
+            .build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("execute: {}", sql))?;
+        self.find_by_id(org_id).await
+    }
+
+    async fn update_retention(&self, org_id: String, message_retention_days: Option<i64>) -> Result<Option<Organization>, Error> {
+        let (sql, values) = Query::update()
+            .table(Organizations::Table)
+            .value(Organizations::MessageRetentionDays, message_retention_days)
+            .value(Organizations::UpdatedAt, Utc::now().naive_utc())
+            .and_where(Expr::col(Organizations::Id).eq(org_id.as_str()))
+            .build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("execute: {}", sql))?;
+        self.find_by_id(org_id).await
+    }
+}
+
+#[derive(Iden, Clone, Copy)]
+enum Personas {
+    Table,
+    Id,
+    OrgId,
+    Name,
+    Prompt,
+    CreatedAt,
+    UpdatedAt,
+}
+
+const PERSONA_COLUMNS: [Personas; 6] = [
+    Personas::Id, Personas::OrgId, Personas::Name, Personas::Prompt,
+    Personas::CreatedAt, Personas::UpdatedAt,
+];
+
+#[derive(Debug, FromRow)]
+struct PersonaRow {
+    id: String,
+    org_id: String,
+    name: String,
+    prompt: String,
+    created_at: NaiveDateTime,
+    updated_at: Option<NaiveDateTime>,
+}
+
+impl From<PersonaRow> for Persona {
+    fn from(row: PersonaRow) -> Self {
+        Persona {
+            id: row.id,
+            org_id: row.org_id,
+            name: row.name,
+            prompt: row.prompt,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+pub struct PgPersonas {
+    pool: PgPool,
+}
+
+impl PgPersonas {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    fn org_persona(org_id: String, persona_id: String) -> Condition {
+        Condition::all()
+            .add(Expr::col(Personas::Id).eq(persona_id))
+            .add(Expr::col(Personas::OrgId).eq(org_id))
+    }
+}
+
+#[rocket::async_trait]
+impl PersonaRepository for PgPersonas {
+    async fn find_in_org_by_name(&self, org_id: String, name: String) -> Result<Option<Persona>, Error> {
+        let (sql, values) = Query::select()
+            .columns(PERSONA_COLUMNS)
+            .from(Personas::Table)
+            .and_where(Expr::col(Personas::OrgId).eq(org_id))
+            .and_where(Expr::col(Personas::Name).eq(name))
+            .build_sqlx(PostgresQueryBuilder);
+        let row = sqlx::query_as_with::<_, PersonaRow, _>(&sql, values)
+            .fetch_optional(&self.pool).await
+            .with_context(|| format!("fetch_optional: {}", sql))?;
+        Ok(row.map(Persona::from))
+    }
+
+    async fn insert(&self, persona: Persona) -> Result<Persona, Error> {
+        let (sql, values) = Query::insert()
+            .into_table(Personas::Table)
+            .columns(PERSONA_COLUMNS)
+            .values([
+                persona.id.clone().into(),
+                persona.org_id.clone().into(),
+                persona.name.clone().into(),
+                persona.prompt.clone().into(),
+                persona.created_at.into(),
+                persona.updated_at.into(),
+            ])?
+            .build_sqlx(PostgresQueryBuilder);
+        match sqlx::query_with(&sql, values).execute(&self.pool).await {
+            Ok(_) => Ok(persona),
+            Err(err) if is_unique_violation(&err) => Err(Error::Feedback(Code::PersonaAlreadyExists)),
+            Err(err) => Err(anyhow::Error::from(err).context("insert persona").into()),
+        }
+    }
+
+    async fn list(&self, org_id: String) -> Result<Vec<Persona>, Error> {
+        let (sql, values) = Query::select()
+            .columns(PERSONA_COLUMNS)
+            .from(Personas::Table)
+            .and_where(Expr::col(Personas::OrgId).eq(org_id))
+            .order_by(Personas::Name, Order::Asc)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, PersonaRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        Ok(rows.into_iter().map(Persona::from).collect())
+    }
+
+    async fn update_prompt(&self, org_id: String, persona_id: String, prompt: String) -> Result<Option<Persona>, Error> {
+        let (sql, values) = Query::update()
+            .table(Personas::Table)
+            .value(Personas::Prompt, prompt)
+            .value(Personas::UpdatedAt, Utc::now().naive_utc())
+            .cond_where(Self::org_persona(org_id, persona_id))
+            .returning(Query::returning().columns(PERSONA_COLUMNS))
+            .build_sqlx(PostgresQueryBuilder);
+        let row = sqlx::query_as_with::<_, PersonaRow, _>(&sql, values)
+            .fetch_optional(&self.pool).await
+            .with_context(|| format!("fetch_optional: {}", sql))?;
+        Ok(row.map(Persona::from))
+    }
+
+    async fn delete(&self, org_id: String, persona_id: String) -> Result<Option<Persona>, Error> {
+        let (sql, values) = Query::delete()
+            .from_table(Personas::Table)
+            .cond_where(Self::org_persona(org_id, persona_id))
+            .returning(Query::returning().columns(PERSONA_COLUMNS))
+            .build_sqlx(PostgresQueryBuilder);
+        let row = sqlx::query_as_with::<_, PersonaRow, _>(&sql, values)
+            .fetch_optional(&self.pool).await
+            .with_context(|| format!("fetch_optional: {}", sql))?;
+        Ok(row.map(Persona::from))
+    }
+}
+
+pub struct PgApiKeys {
+    pool: PgPool,
+}
+
+impl PgApiKeys {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// 更新满足条件的 API Key 并返回更新后的结果
+    async fn update_returning(&self, column: ApiKeys, value: NaiveDateTime, cond: Condition) -> Result<Option<ApiKey>, Error> {
+        let (sql, values) = Query::update()
+            .table(ApiKeys::Table)
+            .value(column, value)
+            .cond_where(cond)
+            .returning(Query::returning().columns(API_KEY_COLUMNS))
+            .build_sqlx(PostgresQueryBuilder);
+        let row = sqlx::query_as_with::<_, ApiKeyRow, _>(&sql, values)
+            .fetch_optional(&self.pool).await
+            .with_context(|| format!("fetch_optional: {}", sql))?;
+        Ok(row.map(ApiKey::from))
+    }
+}
+
+#[rocket::async_trait]
+impl ApiKeyRepository for PgApiKeys {
+    async fn insert(&self, api_key: ApiKey, key_hash: String) -> Result<ApiKey, Error> {
+        let (sql, values) = Query::insert()
+            .into_table(ApiKeys::Table)
+            .columns(API_KEY_COLUMNS.into_iter().chain([ApiKeys::KeyHash]))
+            .values([
+                api_key.id.clone().into(),
+                api_key.user_id.clone().into(),
+                api_key.name.clone().into(),
+                api_key.prefix.clone().into(),
+                api_key.created_at.into(),
+                api_key.last_used_at.into(),
+                api_key.revoked_at.into(),
+                key_hash.into(),
+            ])?
+            .build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| "insert api_key".to_string())?;
+        Ok(api_key)
+    }
+
+    async fn find_active_by_hash(&self, key_hash: String) -> Result<Option<ApiKey>, Error> {
+        let cond = Condition::all()
+            .add(Expr::col(ApiKeys::KeyHash).eq(key_hash))
+            .add(Expr::col(ApiKeys::RevokedAt).is_null());
+        self.update_returning(ApiKeys::LastUsedAt, Utc::now().naive_utc(), cond).await
+    }
+
+    async fn list_by_user(&self, user_id: String) -> Result<Vec<ApiKey>, Error> {
+        let (sql, values) = Query::select()
+            .columns(API_KEY_COLUMNS)
+            .from(ApiKeys::Table)
+            .and_where(Expr::col(ApiKeys::UserId).eq(user_id))
+            .order_by(ApiKeys::CreatedAt, Order::Desc)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows = sqlx::query_as_with::<_, ApiKeyRow, _>(&sql, values)
+            .fetch_all(&self.pool).await
+            .with_context(|| format!("fetch_all: {}", sql))?;
+        Ok(rows.into_iter().map(ApiKey::from).collect())
+    }
+
+    async fn revoke(&self, user_id: String, api_key_id: String) -> Result<Option<ApiKey>, Error> {
+        let cond = Condition::all()
+            .add(Expr::col(ApiKeys::Id).eq(api_key_id))
+            .add(Expr::col(ApiKeys::UserId).eq(user_id));
+        self.update_returning(ApiKeys::RevokedAt, Utc::now().naive_utc(), cond).await
+    }
+
+    async fn count_active_by_user(&self, user_id: String) -> Result<u64, Error> {
+        let (sql, values) = Query::select()
+            .expr(Func::count(Expr::col(ApiKeys::Id)))
+            .from(ApiKeys::Table)
+            .and_where(Expr::col(ApiKeys::UserId).eq(user_id))
+            .and_where(Expr::col(ApiKeys::RevokedAt).is_null())
+            .build_sqlx(PostgresQueryBuilder);
+        let count: i64 = sqlx::query_scalar_with(&sql, values)
+            .fetch_one(&self.pool).await
+            .with_context(|| format!("fetch_one: {}", sql))?;
+        Ok(count as u64)
+    }
+
+    async fn delete_by_user(&self, user_id: String) -> Result<u64, Error> {
+        let (sql, values) = Query::delete()
+            .from_table(ApiKeys::Table)
+            .and_where(Expr::col(ApiKeys::UserId).eq(user_id))
+            .build_sqlx(PostgresQueryBuilder);
+        let res = sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("execute: {}", sql))?;
+        Ok(res.rows_affected())
+    }
+}
+
+pub struct PgQuotas {
+    pool: PgPool,
+}
+
+impl PgQuotas {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[rocket::async_trait]
+impl QuotaStore for PgQuotas {
+    async fn load(&self, user_id: ObjectId) -> Result<Option<QuotaDoc>, Error> {
+        let (sql, values) = Query::select()
+            .columns(QUOTA_COLUMNS)
+            .from(Quotas::Table)
+            .and_where(Expr::col(Quotas::UserId).eq(user_id.to_hex()))
+            .build_sqlx(PostgresQueryBuilder);
+        let row = sqlx::query_as_with::<_, QuotaRow, _>(&sql, values)
+            .fetch_optional(&self.pool).await
+            .with_context(|| format!("fetch_optional: {}", sql))?;
+        match row {
+            Some(row) => Ok(Some(QuotaDoc::try_from(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn insert(&self, quota: QuotaDoc) -> Result<bool, Error> {
+        let recent = quota_recent(&quota);
+        let (sql, values) = Query::insert()
+            .into_table(Quotas::Table)
+            .columns(QUOTA_COLUMNS)
+            .values([
+                quota._id.to_hex().into(),
+                quota.day.into(),
+                quota.day_count.into(),
+                recent.into(),
+                quota.pending.into(),
+                quota.version.into(),
+            ])?
+            .on_conflict(OnConflict::column(Quotas::UserId).do_nothing().to_owned())
+            .build_sqlx(PostgresQueryBuilder);
+        let res = sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| "insert quota".to_string())?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn swap(&self, expected_version: i64, quota: QuotaDoc) -> Result<bool, Error> {
+        let recent = quota_recent(&quota);
+        let (sql, values) = Query::update()
+            .table(Quotas::Table)
+            .value(Quotas::Day, quota.day)
+            .value(Quotas::DayCount, quota.day_count)
+            .value(Quotas::Recent, recent)
+            .value(Quotas::Pending, quota.pending)
+            .value(Quotas::Version, quota.version)
+            .and_where(Expr::col(Quotas::UserId).eq(quota._id.to_hex()))
+            .and_where(Expr::col(Quotas::Version).eq(expected_version))
+            .build_sqlx(PostgresQueryBuilder);
+        let res = sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("execute: {}", sql))?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn delete(&self, user_id: ObjectId) -> Result<(), Error> {
+        let (sql, values) = Query::delete()
+            .from_table(Quotas::Table)
+            .and_where(Expr::col(Quotas::UserId).eq(user_id.to_hex()))
+            .build_sqlx(PostgresQueryBuilder);
+        sqlx::query_with(&sql, values)
+            .execute(&self.pool).await
+            .with_context(|| format!("delete quota {}", user_id))?;
+        Ok(())
+    }
+}
+
+pub struct PgHealth {
+    pool: PgPool,
+}
+
+impl PgHealth {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[rocket::async_trait]
+impl HealthCheck for PgHealth {
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool).await
+            .map_err(|err| Error::DatabasePingError(err.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::DEFAULT_ORGANIZATION_ID;
+
+    #[test]
+    fn escape_like_wildcards() {
+        assert_eq!(escape_like("a_b%c\\d"), "a\\_b\\%c\\\\d");
+    }
+
+    #[test]
+    fn usage_query_numbers_timezone_first() {
+        let cond = Condition::all()
+            .add(Expr::col(Messages::OrgId).eq(DEFAULT_ORGANIZATION_ID))
+            .add(Expr::col(Messages::Type).eq("user"));
+        let (sql, values) = usage_query(cond, Tz::Asia__Shanghai).build_sqlx(PostgresQueryBuilder);
+        assert!(sql.starts_with(r#"SELECT to_char(("created_at" AT TIME ZONE 'UTC') AT TIME ZONE $1, 'YYYY-MM-DD') AS "date""#), "{}", sql);
+        assert!(sql.contains(r#"WHERE "org_id" = $2 AND "type" = $3"#), "{}", sql);
+        assert!(sql.ends_with(r#"GROUP BY "date", "type""#), "{}", sql);
+        assert_eq!(values.0.0.len(), 3);
+    }
+}
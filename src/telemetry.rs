@@ -0,0 +1,462 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::warn;
+use rocket::Request;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use crate::conf::Config;
+use crate::error::Error;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+/// 攒够这么多 span 或每隔 EXPORT_INTERVAL 导出一次
+const EXPORT_BATCH_SIZE: usize = 512;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// 等待导出的 span 最多排队这么多, 导出跟不上(如 collector 不可用)时丢弃新的 span, 与 OTel SDK 的 max_queue_size 默认值一致
+const MAX_QUEUE_SIZE: usize = 2048;
+
+/// W3C Trace Context 中的 trace-id 和 parent-id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+}
+
+impl SpanContext {
+    /// `00-{trace-id}-{parent-id}-01`, 总是标记为已采样
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", hex::encode(self.trace_id), hex::encode(self.span_id))
+    }
+
+    /// 只接受版本 00, id 全为 0 或格式不对时返回 None
+    pub fn parse_traceparent(value: &str) -> Option<Self> {
+        let parts = value.trim().split('-').collect::<Vec<&str>>();
+        let [version, trace_id, span_id, flags] = parts.as_slice() else {
+            return None;
+        };
+        if *version != "00" || flags.len() != 2 {
+            return None;
+        }
+        let trace_id: [u8; 16] = hex::decode(trace_id).ok()?.try_into().ok()?;
+        let span_id: [u8; 8] = hex::decode(span_id).ok()?.try_into().ok()?;
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(SpanContext { trace_id, span_id })
+    }
+}
+
+/// 与 OTLP 中 Span.SpanKind 的取值一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<u32> for AttributeValue {
+    fn from(value: u32) -> Self {
+        AttributeValue::Int(value as i64)
+    }
+}
+
+impl From<u16> for AttributeValue {
+    fn from(value: u16) -> Self {
+        AttributeValue::Int(value as i64)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SpanData {
+    context: SpanContext,
+    parent_span_id: Option<[u8; 8]>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    end: Option<SystemTime>,
+    attributes: Vec<(&'static str, AttributeValue)>,
+    /// 出错时的错误信息, 导出为 ERROR 状态
+    error: Option<String>,
+}
+
+/// 一次操作的耗时, 属性和结果, 结束时交给导出器
+#[derive(Clone)]
+pub struct Span(Arc<Mutex<SpanData>>);
+
+tokio::task_local! {
+    /// 当前所在的 span, 其中开始的 span 以它为父 span
+    static CURRENT_SPAN: Span;
+}
+
+impl Span {
+    /// 以当前所在的 span 为父 span, 不在 span 中时开始新的 trace
+    pub fn start(name: impl Into<String>, kind: SpanKind) -> Self {
+        Self::start_with_parent(name, kind, current_context())
+    }
+
+    pub fn start_with_parent(name: impl Into<String>, kind: SpanKind, parent: Option<SpanContext>) -> Self {
+        let context = SpanContext {
+            trace_id: parent.map(|parent| parent.trace_id).unwrap_or_else(new_trace_id),
+            span_id: new_span_id(),
+        };
+        Span(Arc::new(Mutex::new(SpanData {
+            context,
+            parent_span_id: parent.map(|parent| parent.span_id),
+            name: name.into(),
+            kind,
+            start: SystemTime::now(),
+            end: None,
+            attributes: vec![],
+            error: None,
+        })))
+    }
+
+    pub fn context(&self) -> SpanContext {
+        self.0.lock().unwrap().context
+    }
+
+    /// 同名属性会被覆盖
+    pub fn set_attribute(&self, key: &'static str, value: impl Into<AttributeValue>) {
+        let value = value.into();
+        let mut data = self.0.lock().unwrap();
+        match data.attributes.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, old)) => *old = value,
+            None => data.attributes.push((key, value)),
+        }
+    }
+
+    pub fn set_error(&self, error: impl Display) {
+        self.0.lock().unwrap().error = Some(error.to_string());
+    }
+
+    /// 在此 span 中执行 future, 不结束 span
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        CURRENT_SPAN.scope(self.clone(), future).await
+    }
+
+    /// 在此 span 中执行 future, 按结果记录状态后结束 span
+    pub async fn instrument<T, E: Display, F: Future<Output = Result<T, E>>>(self, future: F) -> Result<T, E> {
+        let res = self.scope(future).await;
+        if let Err(err) = &res {
+            self.set_error(err);
+        }
+        self.end();
+        res
+    }
+
+    /// 重复结束时只导出一次
+    pub fn end(&self) {
+        let mut data = self.0.lock().unwrap();
+        if data.end.is_some() {
+            return;
+        }
+        data.end = Some(SystemTime::now());
+        export(data.clone());
+    }
+}
+
+/// 当前所在 span 的 context, 不在 span 中时为 None
+pub fn current_context() -> Option<SpanContext> {
+    CURRENT_SPAN.try_with(|span| span.context()).ok()
+}
+
+/// 传给上游的 traceparent, 不在 span 中时为 None
+pub fn current_traceparent() -> Option<String> {
+    current_context().map(|context| context.traceparent())
+}
+
+/// 给当前所在的 span 添加属性, 不在 span 中时忽略
+pub fn record(key: &'static str, value: impl Into<AttributeValue>) {
+    let _ = CURRENT_SPAN.try_with(|span| span.set_attribute(key, value));
+}
+
+/// 记录已经结束的操作(如 MongoDB 命令事件), 不在 span 中时忽略, 避免后台任务产生大量孤立的 trace
+pub fn record_finished(name: impl Into<String>, kind: SpanKind, duration: Duration, attributes: Vec<(&'static str, AttributeValue)>, error: Option<String>) {
+    let Some(parent) = current_context() else {
+        return;
+    };
+    let end = SystemTime::now();
+    export(SpanData {
+        context: SpanContext {
+            trace_id: parent.trace_id,
+            span_id: new_span_id(),
+        },
+        parent_span_id: Some(parent.span_id),
+        name: name.into(),
+        kind,
+        start: end.checked_sub(duration).unwrap_or(end),
+        end: Some(end),
+        attributes,
+        error,
+    });
+}
+
+/// 请求的根 span, 请求头中有合法的 traceparent 时加入调用方的 trace
+pub fn server_span(req: &Request<'_>) -> Span {
+    let parent = req.headers().get_one(TRACEPARENT_HEADER).and_then(SpanContext::parse_traceparent);
+    let route = req.route().map(|route| route.uri.path().to_string()).unwrap_or_else(|| req.uri().path().to_string());
+    let span = Span::start_with_parent(format!("{} {}", req.method(), route), SpanKind::Server, parent);
+    span.set_attribute("http.request.method", req.method().as_str());
+    span.set_attribute("http.route", route);
+    span
+}
+
+fn new_trace_id() -> [u8; 16] {
+    *uuid::Uuid::new_v4().as_bytes()
+}
+
+fn new_span_id() -> [u8; 8] {
+    let mut span_id = [0; 8];
+    span_id.copy_from_slice(&uuid::Uuid::new_v4().as_bytes()[..8]);
+    span_id
+}
+
+enum Exporter {
+    /// 每行输出一个 span 的 JSON, 用于本地调试
+    Stdout,
+    /// OTLP/HTTP JSON
+    Otlp { client: reqwest::Client, url: String },
+}
+
+/// 结束的 span 发往导出任务, 未初始化或队列已满时丢弃
+static EXPORT_QUEUE: OnceLock<Sender<SpanData>> = OnceLock::new();
+/// 上次导出以来因队列已满丢弃的 span 数
+static DROPPED_SPANS: AtomicU64 = AtomicU64::new(0);
+
+fn export(span: SpanData) {
+    if let Some(queue) = EXPORT_QUEUE.get() {
+        enqueue(queue, span);
+    }
+}
+
+/// 不等待队列空出位置, 已满时丢弃并计数
+fn enqueue(queue: &Sender<SpanData>, span: SpanData) {
+    if let Err(mpsc::error::TrySendError::Full(_)) = queue.try_send(span) {
+        DROPPED_SPANS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 按 OTEL_TRACES_EXPORTER 启动导出任务, none 时不导出
+pub fn init(config: &Config) -> Result<(), Error> {
+    let exporter = match config.otel_traces_exporter.as_str() {
+        "none" | "" => return Ok(()),
+        "stdout" => Exporter::Stdout,
+        "otlp" => Exporter::Otlp {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            url: otlp_traces_url(config.otel_exporter_otlp_endpoint.as_str()),
+        },
+        exporter => return Err(Error::Misconfigured(format!("OTEL_TRACES_EXPORTER 无效: {}", exporter))),
+    };
+    let (sender, receiver) = mpsc::channel(MAX_QUEUE_SIZE);
+    if EXPORT_QUEUE.set(sender).is_ok() {
+        tokio::spawn(run_exporter(exporter, config.otel_service_name.clone(), receiver));
+    }
+    Ok(())
+}
+
+/// 与 OTel SDK 一致, 配置的是根地址时追加 /v1/traces
+fn otlp_traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
+
+async fn run_exporter(exporter: Exporter, service_name: String, mut receiver: Receiver<SpanData>) {
+    let mut batch = vec![];
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        tokio::select! {
+            span = receiver.recv() => {
+                let Some(span) = span else {
+                    break;
+                };
+                batch.push(span);
+                if batch.len() < EXPORT_BATCH_SIZE {
+                    continue;
+                }
+            }
+            _ = interval.tick() => {
+                let dropped = DROPPED_SPANS.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    warn!("span export queue is full, dropped {} spans", dropped);
+                }
+            }
+        }
+        if !batch.is_empty() {
+            export_batch(&exporter, service_name.as_str(), std::mem::take(&mut batch)).await;
+        }
+    }
+}
+
+async fn export_batch(exporter: &Exporter, service_name: &str, spans: Vec<SpanData>) {
+    match exporter {
+        Exporter::Stdout => {
+            let mut stdout = std::io::stdout().lock();
+            for span in &spans {
+                let _ = writeln!(stdout, "{}", span_json(span));
+            }
+        }
+        Exporter::Otlp { client, url } => {
+            let res = client.post(url.as_str())
+                .json(&otlp_request(service_name, &spans))
+                .send().await
+                .and_then(|response| response.error_for_status());
+            if let Err(err) = res {
+                warn!("export {} spans to {}: {}", spans.len(), url, err);
+            }
+        }
+    }
+}
+
+/// ExportTraceServiceRequest 的 JSON 编码
+fn otlp_request(service_name: &str, spans: &[SpanData]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute_json("service.name", &AttributeValue::from(service_name))],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(span_json).collect::<Vec<Value>>(),
+            }],
+        }],
+    })
+}
+
+fn span_json(span: &SpanData) -> Value {
+    let status = match &span.error {
+        Some(message) => json!({ "code": 2, "message": message }),
+        None => json!({ "code": 0 }),
+    };
+    json!({
+        "traceId": hex::encode(span.context.trace_id),
+        "spanId": hex::encode(span.context.span_id),
+        "parentSpanId": span.parent_span_id.map(hex::encode).unwrap_or_default(),
+        "name": span.name,
+        "kind": span.kind as i32,
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end.unwrap_or(span.start)),
+        "attributes": span.attributes.iter().map(|(key, value)| attribute_json(key, value)).collect::<Vec<Value>>(),
+        "status": status,
+    })
+}
+
+/// OTLP JSON 中 64 位整数编码为字符串
+fn attribute_json(key: &str, value: &AttributeValue) -> Value {
+    let value = match value {
+        AttributeValue::String(value) => json!({ "stringValue": value }),
+        AttributeValue::Int(value) => json!({ "intValue": value.to_string() }),
+        AttributeValue::Bool(value) => json!({ "boolValue": value }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_traceparent() {
+        let value = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = SpanContext::parse_traceparent(value).unwrap();
+        assert_eq!(context.traceparent(), value);
+        assert_eq!(SpanContext::parse_traceparent("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"), None);
+        assert_eq!(SpanContext::parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"), None);
+        assert_eq!(SpanContext::parse_traceparent("00-4bf92f3577b34da6-00f067aa0ba902b7-01"), None);
+        assert_eq!(SpanContext::parse_traceparent("not a traceparent"), None);
+    }
+
+    #[tokio::test]
+    async fn child_spans_join_the_current_trace() {
+        let parent = Span::start("parent", SpanKind::Server);
+        let (child, traceparent) = parent.scope(async {
+            record("enduser.id", "u1");
+            let child = Span::start("child", SpanKind::Client);
+            let traceparent = child.scope(async { current_traceparent() }).await;
+            (child, traceparent)
+        }).await;
+        assert_eq!(current_context(), None);
+        let parent = parent.0.lock().unwrap().clone();
+        let child = child.0.lock().unwrap().clone();
+        assert_eq!(parent.attributes, vec![("enduser.id", AttributeValue::from("u1"))]);
+        assert_eq!(child.context.trace_id, parent.context.trace_id);
+        assert_eq!(child.parent_span_id, Some(parent.context.span_id));
+        assert_eq!(traceparent, Some(child.context.traceparent()));
+    }
+
+    #[tokio::test]
+    async fn exports_otlp_json() {
+        let span = Span::start("chat", SpanKind::Client);
+        span.set_attribute("gen_ai.usage.input_tokens", 12u32);
+        let res = span.clone().instrument(async { Err::<(), _>("upstream timeout") }).await;
+        assert!(res.is_err());
+        let data = span.0.lock().unwrap().clone();
+        let body = otlp_request("simplylab", std::slice::from_ref(&data));
+        let json = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(json["traceId"], hex::encode(data.context.trace_id));
+        assert_eq!(json["parentSpanId"], "");
+        assert_eq!(json["kind"], 3);
+        assert_eq!(json["attributes"][0], json!({ "key": "gen_ai.usage.input_tokens", "value": { "intValue": "12" } }));
+        assert_eq!(json["status"], json!({ "code": 2, "message": "upstream timeout" }));
+        assert_eq!(body["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"], "simplylab");
+        assert_eq!(otlp_traces_url("http://collector:4318/"), "http://collector:4318/v1/traces");
+        assert_eq!(otlp_traces_url("http://collector:4318/v1/traces"), "http://collector:4318/v1/traces");
+    }
+
+    #[tokio::test]
+    async fn full_export_queue_drops_spans() {
+        let span = Span::start("chat", SpanKind::Client).0.lock().unwrap().clone();
+        let (sender, mut receiver) = mpsc::channel(2);
+        let dropped = DROPPED_SPANS.load(Ordering::Relaxed);
+        for _ in 0..3 {
+            enqueue(&sender, span.clone());
+        }
+        assert_eq!(DROPPED_SPANS.load(Ordering::Relaxed) - dropped, 1);
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_err());
+    }
+}